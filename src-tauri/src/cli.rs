@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Subcommand, ValueEnum};
+use serde_json::{Map, Value};
+
+use crate::flute::Flute;
+use crate::ipc;
+use crate::protocol::{Command, Id, LizError, PageArgs};
+use crate::setup::open_flute;
+use crate::tools::{
+    db::Shortcut,
+    exec::{Preview, PreviewEvent},
+    rhythm::Rhythm,
+    sequence::Placeholder,
+    utils::InstanceLock,
+};
+
+/// The subcommands run without the window
+#[derive(Subcommand, Debug)]
+pub enum CliCommand {
+    /// Search the shortcuts, best first
+    Search {
+        /// The query, such as `app:vim copy`
+        query: Vec<String>,
+        /// How many shortcuts to show at most, 0 for all
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+        #[arg(short, long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+    /// Execute a shortcut by its id, or the best match of a query
+    Exec {
+        #[arg(required = true, value_name = "ID|QUERY")]
+        target: Vec<String>,
+        /// Print the keys it would press and the text it would type instead
+        #[arg(long)]
+        dry_run: bool,
+        /// The value of a placeholder, the missing ones are asked in a terminal
+        #[arg(long = "set", value_name = "NAME=VALUE", value_parser = parse_value)]
+        values: Vec<(String, String)>,
+    },
+    /// Import the shortcuts of json or txt files
+    Import {
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Export the shortcuts to a json file
+    Export {
+        path: String,
+        /// Only export the shortcuts matching this query
+        #[arg(short, long, value_name = "QUERY")]
+        filter: Option<String>,
+    },
+    /// List all the shortcuts
+    List {
+        #[arg(short, long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+    /// Show or change the settings in the rhythm file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Show one setting, or all of them
+    Get { name: Option<String> },
+    /// Change one setting
+    Set { name: String, value: String },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Table,
+}
+
+/// The arguments a second `liz` hands to the running instance
+#[derive(Args, Debug, Default)]
+pub struct Forward {
+    /// Open the launcher with this query
+    #[arg(long, value_name = "QUERY")]
+    pub search: Option<String>,
+    /// Execute the shortcut of this id
+    #[arg(long, value_name = "ID")]
+    pub exec: Option<String>,
+    /// Import the shortcuts of these json or txt files
+    #[arg(long, value_name = "PATH", num_args = 1..)]
+    pub import: Vec<String>,
+}
+
+impl Forward {
+    pub fn is_empty(&self) -> bool {
+        self.search.is_none() && self.exec.is_none() && self.import.is_empty()
+    }
+
+    /// The commands to run in order, the import paths are relative to `cwd`
+    pub fn commands(&self, cwd: &Path) -> Result<Vec<Command>, LizError> {
+        let mut commands = Vec::new();
+        if !self.import.is_empty() {
+            let paths = self
+                .import
+                .iter()
+                .map(|path| cwd.join(path).to_string_lossy().to_string())
+                .collect();
+            commands.push(Command::ImportShortcuts { paths });
+        }
+        if let Some(id) = &self.exec {
+            commands.push(Command::Execute {
+                id: Id::parse(id)?,
+                values: None,
+            });
+        }
+        if let Some(query) = &self.search {
+            commands.push(Command::Show {
+                query: Some(query.clone()),
+            });
+        }
+        Ok(commands)
+    }
+}
+
+/// Run the forwarded arguments in the running instance and report how they went,
+/// None if no instance answers on the socket. Then the single instance plugin hands them
+/// to the running instance, if there is one, which cannot report back to the terminal.
+pub fn forward(forward: &Forward, config: Option<String>) -> Option<i32> {
+    let rhythm = Rhythm::read_rhythm(config).ok()?;
+    if !rhythm.ipc_enabled {
+        return None;
+    }
+    let client = ipc::Client::connect(&rhythm.ipc_path()).ok()?;
+    match run_forward(client, forward) {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("liz: {}", e);
+            Some(1)
+        }
+    }
+}
+
+fn run_forward(mut client: ipc::Client, forward: &Forward) -> Result<(), Box<dyn Error>> {
+    for cmd in forward.commands(&std::env::current_dir()?)? {
+        let reply = client.call(&cmd)??;
+        match cmd {
+            Command::ImportShortcuts { paths } => println!("Imported {}", paths.join(", ")),
+            Command::Execute { id, .. } if reply["type"] == "needs_input" => {
+                return Err(
+                    format!("{} needs values, run `liz exec {}` to fill them", id, id).into(),
+                )
+            }
+            Command::Execute { id, .. } => println!("Executed {}", id),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Where the commands run: the running instance if it answers on its socket,
+/// otherwise a flute on the music sheet of this process. Its lock is None when the app
+/// runs without the socket, then the music sheet is only read.
+enum Backend {
+    Remote(ipc::Client),
+    Local(Box<Flute>, Option<InstanceLock>),
+}
+
+impl Backend {
+    fn open(rhythm: &Rhythm, config: Option<String>) -> Result<Self, Box<dyn Error>> {
+        if rhythm.ipc_enabled {
+            if let Ok(client) = ipc::Client::connect(&rhythm.ipc_path()) {
+                return Ok(Backend::Remote(client));
+            }
+        }
+        let flute = open_flute(config)?;
+        let lock = InstanceLock::acquire(Path::new(&flute.rhythm.instance_lock_path()))?;
+        Ok(Backend::Local(Box::new(flute), lock))
+    }
+
+    /// Run the command and return the data of its `Reply`
+    fn run(&mut self, cmd: Command) -> Result<Value, LizError> {
+        let reply = self.reply(cmd)?;
+        Ok(reply.get("data").cloned().unwrap_or(Value::Null))
+    }
+
+    /// Run the command and return its `Reply`, tagged by its type
+    fn reply(&mut self, cmd: Command) -> Result<Value, LizError> {
+        Ok(match self {
+            Backend::Remote(client) => client
+                .call(&cmd)
+                .map_err(|e| LizError::io(format!("Lost the running instance: {}", e)))??,
+            Backend::Local(_, None) if writes(&cmd) => {
+                let message = "Liz is running without its socket, \
+                    enable ipc_enabled or quit Liz to change the music sheet";
+                return Err(LizError::io(message));
+            }
+            Backend::Local(flute, _) => {
+                serde_json::to_value(flute.run(cmd)?).map_err(|e| LizError::Internal {
+                    message: e.to_string(),
+                })?
+            }
+        })
+    }
+
+    /// Execute the shortcut, the placeholders without a value are asked in a terminal
+    fn execute(
+        &mut self,
+        id: Id,
+        mut values: HashMap<String, String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let terminal = io::stdin().is_terminal();
+        // In a terminal all the placeholders are asked unless some values are given
+        let given = (!terminal || !values.is_empty()).then(|| values.clone());
+        let reply = self.reply(Command::Execute { id, values: given })?;
+        if reply["type"] != "needs_input" {
+            return Ok(());
+        }
+        let placeholders: Vec<Placeholder> =
+            serde_json::from_value(reply["data"]["placeholders"].clone())?;
+        let missing: Vec<&Placeholder> = placeholders
+            .iter()
+            .filter(|p| !values.contains_key(&p.name))
+            .collect();
+        if !terminal {
+            let names: Vec<&str> = missing
+                .iter()
+                .filter(|p| p.default.is_none())
+                .map(|p| p.name.as_str())
+                .collect();
+            return Err(format!(
+                "Missing values of {}, give them with --set",
+                names.join(", ")
+            )
+            .into());
+        }
+        for p in missing {
+            let value = ask(p)?;
+            values.insert(p.name.clone(), value);
+        }
+        self.reply(Command::Execute {
+            id,
+            values: Some(values),
+        })?;
+        Ok(())
+    }
+
+    /// Save the changes made here, the running instance saves its own.
+    /// No backup is kept, one would be rolled out by every hit of `liz exec`.
+    fn close(self) -> Result<(), Box<dyn Error>> {
+        if let Backend::Local(mut flute, _) = self {
+            if flute.music_sheet.dirty_times().is_some() {
+                flute.music_sheet.persist()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn search(&mut self, query: &str, limit: usize) -> Result<Vec<Shortcut>, Box<dyn Error>> {
+        let data = self.run(Command::GetShortcutDetails {
+            query: query.to_string(),
+            page: PageArgs {
+                limit: Some(limit),
+                ..Default::default()
+            },
+        })?;
+        Ok(serde_json::from_value(data["shortcuts"].clone())?)
+    }
+}
+
+/// Run the subcommand and return the exit code, the errors are printed to stderr
+pub fn run(command: CliCommand, config: Option<String>) -> i32 {
+    match run_command(command, config) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("liz: {}", e);
+            1
+        }
+    }
+}
+
+fn run_command(command: CliCommand, config: Option<String>) -> Result<(), Box<dyn Error>> {
+    let rhythm = Rhythm::read_rhythm(config.clone())?;
+    if let CliCommand::Config { action } = command {
+        return run_config(action, rhythm, config);
+    }
+    let mut backend = Backend::open(&rhythm, config)?;
+    match command {
+        CliCommand::Search {
+            query,
+            limit,
+            format,
+        } => print_shortcuts(&backend.search(&query.join(" "), limit)?, format)?,
+        CliCommand::Exec {
+            target,
+            dry_run,
+            values,
+        } => {
+            let target = target.join(" ");
+            let id = match Id::parse(&target) {
+                Ok(id) => id,
+                Err(_) => match backend.search(&target, 1)?.first() {
+                    Some(sc) => Id(sc.id),
+                    None => return Err(format!("No shortcut matches {}", target).into()),
+                },
+            };
+            if dry_run {
+                let preview = backend.run(Command::PreviewExecute { id })?;
+                print_preview(&serde_json::from_value(preview)?);
+            } else {
+                backend.execute(id, values.into_iter().collect())?;
+            }
+        }
+        CliCommand::Import { paths } => {
+            let paths = paths
+                .iter()
+                .map(|path| absolute(path))
+                .collect::<Result<_, _>>()?;
+            backend.run(Command::ImportShortcuts { paths })?;
+        }
+        CliCommand::Export { path, filter } => {
+            let shortcuts = backend.search(filter.as_deref().unwrap_or(""), 0)?;
+            let ids = shortcuts.iter().map(|sc| Id(sc.id)).collect();
+            let path = absolute(&path)?;
+            backend.run(Command::ExportShortcuts { path, ids })?;
+            eprintln!("Exported {} shortcuts", shortcuts.len());
+        }
+        CliCommand::List { format } => print_shortcuts(&backend.search("", 0)?, format)?,
+        CliCommand::Config { .. } => unreachable!(),
+    }
+    backend.close()
+}
+
+/// Whether the command changes the music sheet, executing records a hit
+fn writes(cmd: &Command) -> bool {
+    cmd.changes_shortcuts()
+        || matches!(
+            cmd,
+            Command::Execute { .. }
+                | Command::Persist
+                | Command::PurgeDeleted { .. }
+                | Command::PurgeDeletedOlderThan { .. }
+        )
+}
+
+/// `NAME=VALUE` of `exec --set`
+fn parse_value(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("Expect NAME=VALUE, got {}", arg)),
+    }
+}
+
+/// Ask the value of the placeholder on stdin, an empty answer takes its default
+fn ask(placeholder: &Placeholder) -> Result<String, Box<dyn Error>> {
+    let mut prompt = placeholder.name.clone();
+    if !placeholder.hint.is_empty() {
+        prompt.push_str(&format!(" ({})", placeholder.hint));
+    }
+    if let Some(default) = &placeholder.default {
+        prompt.push_str(&format!(" [{}]", default));
+    }
+    eprint!("{}: ", prompt);
+    io::stderr().flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Err(format!("No value of {}", placeholder.name).into());
+    }
+    let value = line.trim_end_matches(['\r', '\n']);
+    Ok(match &placeholder.default {
+        Some(default) if value.is_empty() => default.clone(),
+        _ => value.to_string(),
+    })
+}
+
+/// The running instance has another working dir, so the paths are made absolute
+fn absolute(path: &str) -> Result<String, Box<dyn Error>> {
+    Ok(std::path::absolute(path)?.to_string_lossy().to_string())
+}
+
+fn run_config(
+    action: ConfigAction,
+    rhythm: Rhythm,
+    config: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let Value::Object(settings) = serde_json::to_value(&rhythm)? else {
+        return Err("Failed to read the settings".into());
+    };
+    match action {
+        ConfigAction::Get { name: Some(name) } => {
+            let value = settings
+                .get(&name)
+                .ok_or_else(|| format!("Unknown setting {}", name))?;
+            println!("{}", format_value(value));
+        }
+        ConfigAction::Get { name: None } => {
+            for (name, value) in &settings {
+                println!("{} = {}", name, format_value(value));
+            }
+        }
+        ConfigAction::Set { name, value } => {
+            let new_rhythm = set_setting(settings, &name, &value)?;
+            // The running instance applies the rhythm and saves it
+            if rhythm.ipc_enabled {
+                if let Ok(mut client) = ipc::Client::connect(&rhythm.ipc_path()) {
+                    client.call(&Command::UpdateRhythm {
+                        rhythm: Box::new(new_rhythm),
+                    })??;
+                    return Ok(());
+                }
+            }
+            new_rhythm.save_rhythm(config.map(PathBuf::from))?;
+        }
+    }
+    Ok(())
+}
+
+/// The rhythm with the setting changed, the value is parsed as the type of the old one
+fn set_setting(
+    mut settings: Map<String, Value>,
+    name: &str,
+    value: &str,
+) -> Result<Rhythm, Box<dyn Error>> {
+    let old = settings
+        .get(name)
+        .ok_or_else(|| format!("Unknown setting {}", name))?;
+    let new = match old {
+        Value::Bool(_) => Value::Bool(value.parse()?),
+        Value::Number(_) => Value::from(value.parse::<u64>()?),
+        _ => Value::String(value.to_string()),
+    };
+    settings.insert(name.to_string(), new);
+    Ok(serde_json::from_value(Value::Object(settings))?)
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+fn print_shortcuts(shortcuts: &[Shortcut], format: Format) -> Result<(), Box<dyn Error>> {
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(shortcuts)?),
+        Format::Table => print!("{}", format_table(shortcuts)),
+    }
+    Ok(())
+}
+
+/// The events one per line, and the warnings on stderr
+fn print_preview(preview: &Preview) {
+    for event in &preview.events {
+        match event {
+            PreviewEvent::Press { key } => println!("press {}", key),
+            PreviewEvent::Release { key } => println!("release {}", key),
+            PreviewEvent::Type { text } => println!("type {:?}", text),
+            PreviewEvent::Delay { ms } => println!("delay {}ms", ms),
+        }
+    }
+    for warning in &preview.warnings {
+        eprintln!("warning: {} at position {}", warning.message, warning.start);
+    }
+}
+
+/// One shortcut per line with its id, application, description and shortcut in columns
+fn format_table(shortcuts: &[Shortcut]) -> String {
+    let width = |field: fn(&Shortcut) -> &str| {
+        shortcuts
+            .iter()
+            .map(|sc| field(sc).chars().count())
+            .max()
+            .unwrap_or(0)
+    };
+    let app_width = width(|sc| &sc.application);
+    let desc_width = width(|sc| &sc.description);
+    shortcuts
+        .iter()
+        .map(|sc| {
+            format!(
+                "{}  {:app_width$}  {:desc_width$}  {}\n",
+                Id(sc.id),
+                sc.application,
+                sc.description,
+                sc.shortcut
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forward_commands() {
+        let cwd = Path::new("/home/me");
+        assert!(Forward::default().is_empty());
+        let id = Id(5).to_string();
+        let forward = Forward {
+            search: Some("git rebase".to_string()),
+            exec: Some(id.clone()),
+            import: vec!["a.json".to_string(), "/tmp/b.txt".to_string()],
+        };
+        let commands = serde_json::to_value(forward.commands(cwd).unwrap()).unwrap();
+        assert_eq!(
+            commands,
+            serde_json::json!([
+                {"action": "import_shortcuts", "paths": ["/home/me/a.json", "/tmp/b.txt"]},
+                {"action": "execute", "id": id},
+                {"action": "show", "query": "git rebase"},
+            ])
+        );
+
+        let forward = Forward {
+            exec: Some("vim".to_string()),
+            ..Default::default()
+        };
+        assert!(forward.commands(cwd).is_err());
+    }
+
+    #[test]
+    fn test_set_setting() {
+        let settings = || match serde_json::to_value(Rhythm::default()).unwrap() {
+            Value::Object(settings) => settings,
+            _ => unreachable!(),
+        };
+        let rhythm = set_setting(settings(), "autosave_ms", "100").unwrap();
+        assert_eq!(rhythm.autosave_ms, 100);
+        let rhythm = set_setting(settings(), "pinyin_search", "false").unwrap();
+        assert!(!rhythm.pinyin_search);
+        let rhythm = set_setting(settings(), "rank_mode", "hit_number").unwrap();
+        assert_eq!(rhythm.rank_mode, "hit_number");
+
+        assert!(set_setting(settings(), "autosave_ms", "soon").is_err());
+        assert!(set_setting(settings(), "pinyin_search", "1").is_err());
+        assert!(set_setting(settings(), "colour", "red").is_err());
+    }
+
+    #[test]
+    fn test_format_table() {
+        let sc = |id, application: &str, description: &str, shortcut: &str| Shortcut {
+            id,
+            application: application.to_string(),
+            description: description.to_string(),
+            shortcut: shortcut.to_string(),
+            ..Default::default()
+        };
+        let table = format_table(&[
+            sc(1, "vim", "Save", "[STR]+:w"),
+            sc(2, "firefox", "New tab", "ctrl+t"),
+        ]);
+        assert_eq!(
+            table,
+            format!(
+                "{}  vim      Save     [STR]+:w\n{}  firefox  New tab  ctrl+t\n",
+                Id(1),
+                Id(2)
+            )
+        );
+        assert_eq!(format_table(&[]), "");
+    }
+}
@@ -1,13 +1,23 @@
-use std::error::Error;
+use std::collections::HashMap;
 use std::fmt;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
+use crate::protocol::{Command, Hit, Id, LizError, PageArgs, Reply, TagCount};
 use crate::tools::{
-    db::{MusicSheetDB, Shortcut, UserSheet},
-    exec::{convert_shortcut_to_keycode, execute_shortcut_enigo},
-    rhythm::{parse_rhythm, Rhythm},
-    utils::{generate_id, id_to_string, string_to_id},
+    backup::list_backups,
+    db::{MusicSheetDB, SearchHit, Shortcut, UserSheet},
+    exec::{execute_sequence, preview_shortcut},
+    input::{BackendKind, OpenBackend},
+    page::Page,
+    query::Query,
+    ranking::RankMode,
+    rhythm::Rhythm,
+    sequence::{Placeholder, Sequence, SequenceError},
+    utils::generate_id,
 };
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -15,6 +25,8 @@ pub enum StateCode {
     OK,
     FAIL,
     BUG,
+    #[allow(non_camel_case_types)]
+    NEEDS_INPUT, // The placeholders in the results are asked before executing again
 }
 
 // Implement Display for StateCode to allow it to be printed
@@ -24,17 +36,20 @@ impl fmt::Display for StateCode {
             StateCode::OK => "OK",
             StateCode::FAIL => "FAIL",
             StateCode::BUG => "BUG",
+            StateCode::NEEDS_INPUT => "NEEDS_INPUT",
         };
         write!(f, "{}", state_str)
     }
 }
 
+/// A command of the old string protocol, see `Command` for the typed one
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LizCommand {
     pub action: String,
     pub args: Vec<String>,
 }
 
+/// The response of the old string protocol, see `Reply` and `LizError` for the typed one
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BlueBirdResponse {
     pub code: StateCode,
@@ -50,46 +65,19 @@ impl BlueBirdResponse {
     }
 }
 
-#[derive(Debug)]
-pub struct FluteExecuteError {
-    msg: String,
-    code: StateCode,
-}
-
-impl FluteExecuteError {
-    // Constructor to create a new FluteExecuteError
-    pub fn new(msg: &str, code: StateCode) -> Self {
-        FluteExecuteError {
-            msg: msg.to_string(),
-            code,
-        }
-    }
+/// Changes keep coming in are still saved after waiting this many times `autosave_ms`
+const AUTOSAVE_MAX_DELAY_FACTOR: u32 = 6;
 
-    // Method to get the error message
-    pub fn message(&self) -> &str {
-        &self.msg
-    }
-
-    // Method to get the state code
-    pub fn code(&self) -> &StateCode {
-        &self.code
-    }
-}
-
-// Implement the Display trait to format the error message
-impl fmt::Display for FluteExecuteError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}: {}", self.code(), self.message())
-    }
-}
-
-// Implement the Error trait for FluteExecuteError
-impl Error for FluteExecuteError {}
+/// A failed autosave is retried after `autosave_ms`, doubled by every failure up to this many times
+const AUTOSAVE_MAX_BACKOFF: u32 = 6;
 
 #[derive(Debug)]
 pub struct Flute {
     pub music_sheet: MusicSheetDB,
     pub rhythm: Rhythm,
+    pub last_saved: Option<DateTime<Local>>, // The last time the music sheet was persisted successfully
+    pub autosave_retry: Option<(Instant, u32)>, // When to retry the failed autosaves, and how many failed in a row
+    pub input: Option<OpenBackend>, // Opened by the first execution, and again when input_backend changes
 }
 
 impl Flute {
@@ -99,407 +87,504 @@ impl Flute {
     }
 
     fn update_rank(&mut self) {
-        self.music_sheet.sort_by_column("application", true);
-        self.music_sheet.sort_by_column("hit_number", false);
+        self.music_sheet.rank(
+            RankMode::parse(&self.rhythm.rank_mode),
+            self.rhythm.frecency_half_life_secs(),
+        );
     }
 
+    /// Run a command of the old string protocol, see `Command::from_legacy`
     pub fn play(&mut self, cmd: &LizCommand) -> BlueBirdResponse {
-        match cmd.action.as_str() {
-            "get_shortcuts" => self.command_get_shortcuts(cmd),
-            // "reload" => self.command_reload(cmd),
-            "execute" => self.command_execute(cmd),
-            "persist" => self.command_persist(cmd),
-            "info" => self.command_info(cmd),
-            "get_shortcut_details" => self.command_get_shortcut_details(cmd),
-            "new_id" => self.command_new_id(cmd),
-            "create_shortcuts" => self.command_create_shortcuts(cmd),
-            "update_shortcuts" => self.command_update_shortcuts(cmd),
-            "delete_shortcuts" => self.command_delete_shortcuts(cmd),
-            "get_deleted_shortcut_details" => self.command_get_deleted_shortcut_details(cmd),
-            "export_shortcuts" => self.command_export_shortcuts(cmd),
-            "import_shortcuts" => self.command_import_shortcuts(cmd),
-            "update_rhythm" => self.command_update_rhythm(cmd),
-            _ => self.command_default(cmd),
+        match Command::from_legacy(cmd).and_then(|command| self.run(command)) {
+            Ok(reply) => reply.into_legacy(cmd),
+            Err(e) => {
+                eprintln!("{}: {}", cmd.action, e);
+                e.into_legacy(cmd)
+            }
+        }
+    }
+
+    pub fn run(&mut self, cmd: Command) -> Result<Reply, LizError> {
+        match cmd {
+            Command::GetShortcuts { query, page } => self.command_get_shortcuts(&query, &page),
+            Command::Execute { id, values } => self.command_execute(id, values),
+            Command::PreviewExecute { id } => self.command_preview_execute(id),
+            Command::Persist => self.command_persist(),
+            Command::Info => self.command_info(),
+            Command::GetShortcutDetails { query, page } => {
+                self.command_get_shortcut_details(&query, &page)
+            }
+            Command::NewId => Ok(Reply::Id(Id(generate_id()))),
+            Command::CreateShortcuts { shortcuts } => self.command_create_shortcuts(shortcuts),
+            Command::UpdateShortcuts { shortcuts } => self.command_update_shortcuts(shortcuts),
+            Command::DeleteShortcuts { ids } => self.command_delete_shortcuts(&ids),
+            Command::GetDeletedShortcutDetails => {
+                Ok(Reply::Deleted(self.music_sheet.retrieve_deleted().clone()))
+            }
+            Command::RestoreShortcuts { ids } => self.command_restore_shortcuts(&ids),
+            Command::GetRevisions { id } => self.command_get_revisions(id),
+            Command::RevertShortcut { id, revision } => self.command_revert_shortcut(id, revision),
+            Command::ListTags => self.command_list_tags(),
+            Command::RenameTag { from, to } => self.command_rename_tags(&[from], &to),
+            Command::MergeTags { into, tags } => self.command_rename_tags(&tags, &into),
+            Command::Undo => self.command_undo(),
+            Command::Redo => self.command_redo(),
+            Command::PurgeDeleted { ids } => self.command_purge_deleted(&ids),
+            Command::PurgeDeletedOlderThan { days } => Ok(Reply::Count(
+                self.music_sheet
+                    .purge_deleted_older_than(days_to_secs(days)),
+            )),
+            Command::ExportShortcuts { path, ids } => self.command_export_shortcuts(&path, &ids),
+            Command::ImportShortcuts { paths } => self.command_import_shortcuts(&paths),
+            Command::UpdateRhythm { rhythm } => self.command_update_rhythm(*rhythm),
+            Command::ListBackups => self.command_list_backups(),
+            Command::RestoreBackup { name } => self.command_restore_backup(&name),
+            Command::Show { .. } => Ok(Reply::Done), // The app shows the window, see `run_typed`
         }
     }
 
-    fn _get_sc_by_id(&self, id_str: &str) -> Result<Shortcut, Box<dyn Error>> {
-        let id: u128 = string_to_id(id_str)?;
+    fn _get_sc_by_id(&self, id: Id) -> Result<Shortcut, LizError> {
         let r: &Shortcut = self
             .music_sheet
-            .retrieve(id, None)
-            .ok_or("Id does not exist".to_string())?;
+            .retrieve(id.0, None)
+            .ok_or(LizError::NotFound { ids: vec![id] })?;
         Ok(r.clone())
     }
 
-    fn command_export_shortcuts(&self, cmd: &LizCommand) -> BlueBirdResponse {
-        fn split_vec(vec: &Vec<String>) -> Option<(String, Vec<String>)> {
-            let (first, rest) = vec.split_first()?; // Get first element and the rest
-            Some((first.clone(), rest.to_vec())) // Clone to return owned values
-        }
-        if let Some((file_path, id_list)) = split_vec(&cmd.args) {
-            let sc_to_export: Result<Vec<Shortcut>, _> = id_list
-                .iter()
-                .map(|id_str| self._get_sc_by_id(&id_str))
-                .collect();
-            match sc_to_export {
-                Ok(sc_to_export) => {
-                    println!("Export to {}", file_path);
-                    let sheet = UserSheet::new(sc_to_export);
-                    match sheet.export_to_json(&file_path) {
-                        Ok(_) => BlueBirdResponse::new(),
-                        Err(e) => {
-                            let err_str = format!("Failed to export to {}: {}", file_path, e);
-                            eprintln!("Export Shortcuts: {}", err_str);
-                            BlueBirdResponse {
-                                code: StateCode::BUG,
-                                results: vec![err_str],
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    let err_str = format!("Failed to parse id: {}", e);
-                    eprintln!("Export Shortcuts: {}", err_str);
-                    BlueBirdResponse {
-                        code: StateCode::BUG,
-                        results: vec![err_str],
-                    }
-                }
-            }
-        } else {
-            BlueBirdResponse {
-                code: StateCode::BUG,
-                results: vec!["File path is not given".to_string()],
-            }
-        }
+    fn command_export_shortcuts(&self, file_path: &str, ids: &[Id]) -> Result<Reply, LizError> {
+        let sc_to_export: Vec<Shortcut> = ids
+            .iter()
+            .map(|id| self._get_sc_by_id(*id))
+            .collect::<Result<_, _>>()?;
+        eprintln!("Export to {}", file_path);
+        let sheet = UserSheet::new(sc_to_export);
+        sheet
+            .export_to_json(file_path)
+            .map_err(|e| LizError::io(format!("Failed to export to {}: {}", file_path, e)))?;
+        Ok(Reply::Done)
     }
 
-    fn command_import_shortcuts(&mut self, cmd: &LizCommand) -> BlueBirdResponse {
-        if cmd.args.is_empty() {
-            eprintln!("BUG: Empty args, expect one file_path");
-            return BlueBirdResponse {
-                code: StateCode::BUG,
-                results: vec!["Empty args, expect one shortcut id".to_string()],
-            };
-        }
-        println!("Import from {:?}", cmd.args);
+    fn command_import_shortcuts(&mut self, paths: &[String]) -> Result<Reply, LizError> {
+        eprintln!("Import from {:?}", paths);
         let mut failed_paths: Vec<String> = Vec::new();
-        for file in cmd.args.iter() {
-            match UserSheet::import_from(file) {
+        self.music_sheet.begin_change_group("import"); // Undo the whole import at once
+        for file in paths.iter() {
+            match UserSheet::import_from(file, self.rhythm.repeat_limit) {
                 Ok(sheet) => {
                     sheet.transform_to_db(&mut self.music_sheet);
                 }
                 Err(e) => {
-                    let err_str = format!("Failed to import file {}: {}", file, e);
-                    eprintln!("Export Shortcuts: {}", err_str);
+                    eprintln!("Import Shortcuts: Failed to import file {}: {}", file, e);
                     failed_paths.push(file.clone());
                 }
             }
         }
+        self.music_sheet.end_change_group();
         if failed_paths.is_empty() {
-            BlueBirdResponse::new()
+            Ok(Reply::Done)
         } else {
-            BlueBirdResponse {
-                code: StateCode::FAIL,
-                results: failed_paths,
-            }
+            Err(LizError::ImportFailed {
+                paths: failed_paths,
+            })
         }
     }
 
-    fn command_get_shortcuts(&self, cmd: &LizCommand) -> BlueBirdResponse {
-        let fmt = &self.rhythm.shortcut_print_fmt;
-        let shortcuts = if cmd.args.is_empty() {
-            self.music_sheet.retrieve_all()
+    /// Search the shortcuts by the query, all of them if there is no query,
+    /// and keep the page of them that is asked
+    fn _search(
+        &self,
+        query: &str,
+        page: &PageArgs,
+    ) -> Result<(Vec<SearchHit<'_>>, Page), LizError> {
+        let request = page.request()?;
+        let (hits, total) = if query.trim().is_empty() {
+            let hits: Vec<SearchHit> = self
+                .music_sheet
+                .retrieve_all()
+                .into_iter()
+                .map(|shortcut| SearchHit {
+                    shortcut,
+                    score: 0.0,
+                    matches: Vec::new(),
+                })
+                .collect();
+            let total = hits.len();
+            (hits, total)
         } else {
-            self.music_sheet.fuzzy_search(&cmd.args[0])
+            let query = Query::parse(query).map_err(|e| LizError::InvalidQuery {
+                position: e.position,
+                message: e.message,
+            })?;
+            // Only the hits up to the end of the page are ranked
+            self.music_sheet.search_top(&query, request.end())
         };
-        let sc_vec: Vec<String> = shortcuts
+        request
+            .apply_first(hits, total, self.music_sheet.generation())
+            .ok_or(LizError::StaleCursor)
+    }
+
+    /// The shortcuts matching the query best first, formatted by `shortcut_print_fmt`.
+    /// The matched chars are wrapped in <mark>, and their ranges of each field are in "matches".
+    fn command_get_shortcuts(&self, query: &str, page: &PageArgs) -> Result<Reply, LizError> {
+        let fmt = &self.rhythm.shortcut_print_fmt;
+        let (hits, page) = self._search(query, page)?;
+        let hits = hits
             .into_iter()
-            .map(|sc| {
-                // Create a JSON string
-                let json = serde_json::json!({
-                    "id": id_to_string(sc.id),  // Convert id to string
-                    "sc": sc.format_output(fmt)
-                });
-                // Serialize it into a JSON string
-                serde_json::to_string(&json).unwrap() // Use unwrap or handle errors properly
+            .map(|hit| Hit {
+                id: Id(hit.shortcut.id),
+                sc: hit.shortcut.format_highlighted(fmt, &hit.matches),
+                matches: hit.matches,
             })
             .collect();
-        BlueBirdResponse {
-            code: StateCode::OK,
-            results: sc_vec,
-        }
+        Ok(Reply::Hits { page, hits })
+    }
+
+    /// The shortcuts matching the query, best first
+    fn command_get_shortcut_details(
+        &self,
+        query: &str,
+        page: &PageArgs,
+    ) -> Result<Reply, LizError> {
+        let (hits, page) = self._search(query, page)?;
+        let shortcuts = hits.into_iter().map(|hit| hit.shortcut.clone()).collect();
+        Ok(Reply::Shortcuts { page, shortcuts })
+    }
+
+    fn command_create_shortcuts(&mut self, shortcuts: Vec<Shortcut>) -> Result<Reply, LizError> {
+        check_shortcuts(&shortcuts, self.rhythm.repeat_limit)?;
+        self.music_sheet.add_shortcuts(shortcuts, None);
+        Ok(Reply::Done)
     }
 
-    fn command_get_shortcut_details(&self, cmd: &LizCommand) -> BlueBirdResponse {
-        let shortcuts = if cmd.args.is_empty() {
-            self.music_sheet.retrieve_all()
+    /// The shortcuts whose ids are not in data are not updated, and returned as NotFound
+    fn command_update_shortcuts(&mut self, shortcuts: Vec<Shortcut>) -> Result<Reply, LizError> {
+        check_shortcuts(&shortcuts, self.rhythm.repeat_limit)?;
+        let unmatched: Vec<Shortcut> = self.music_sheet.update_shortcuts(shortcuts);
+        if unmatched.is_empty() {
+            Ok(Reply::Done)
         } else {
-            self.music_sheet.fuzzy_search(&cmd.args[0])
-        };
-        let sc_vec: Vec<String> = shortcuts
-            .into_iter()
-            .map(|sc| sc.to_json_string())
-            .collect();
-        BlueBirdResponse {
-            code: StateCode::OK,
-            results: sc_vec,
+            Err(LizError::NotFound {
+                ids: unmatched.iter().map(|sc| Id(sc.id)).collect(),
+            })
         }
     }
 
-    fn command_get_deleted_shortcut_details(&self, _cmd: &LizCommand) -> BlueBirdResponse {
-        let shortcuts = self.music_sheet.retrieve_deleted();
-        let sc_vec: Vec<String> = shortcuts
+    fn command_delete_shortcuts(&mut self, ids: &[Id]) -> Result<Reply, LizError> {
+        self.music_sheet
+            .delete_shortcuts(ids.iter().map(|id| id.0).collect());
+        Ok(Reply::Done)
+    }
+
+    /// Restore the latest deleted version of the shortcuts of the given ids.
+    /// The ids that are not in deleted are returned as NotFound.
+    fn command_restore_shortcuts(&mut self, ids: &[Id]) -> Result<Reply, LizError> {
+        let ids: Vec<u128> = ids.iter().map(|id| id.0).collect();
+        let unmatched: Vec<Id> = self
+            .music_sheet
+            .restore_shortcuts(&ids)
             .into_iter()
-            .map(|sc| sc.to_json_string())
+            .map(Id)
             .collect();
-        BlueBirdResponse {
-            code: StateCode::OK,
-            results: sc_vec,
+        self.update_rank();
+        if unmatched.is_empty() {
+            Ok(Reply::Done)
+        } else {
+            Err(LizError::NotFound { ids: unmatched })
         }
     }
 
-    fn command_new_id(&self, _cmd: &LizCommand) -> BlueBirdResponse {
-        BlueBirdResponse {
-            code: StateCode::OK,
-            results: vec![id_to_string(generate_id())],
-        }
+    /// The old versions of the shortcut, the newest first.
+    /// Each revision has its number, time, old version and changed fields.
+    fn command_get_revisions(&self, id: Id) -> Result<Reply, LizError> {
+        Ok(Reply::Revisions(
+            self.music_sheet
+                .retrieve_revisions(id.0)
+                .into_iter()
+                .cloned()
+                .collect(),
+        ))
     }
 
-    fn _args_to_shortcut_vec(&self, cmd: &LizCommand) -> Result<Vec<Shortcut>, String> {
-        let shortcuts: Result<Vec<Shortcut>, _> = cmd
-            .args
-            .iter()
-            .map(|sc_str| Shortcut::from_json_string(&sc_str))
-            .collect();
-        match shortcuts {
-            Ok(shortcuts) => Ok(shortcuts),
-            Err(e) => {
-                let err_str = format!("Failed to parse shortcut: {}", e);
-                Err(err_str)
-            }
+    /// Bring the shortcut back to the revision, keeping its hit_number
+    fn command_revert_shortcut(&mut self, id: Id, revision: u64) -> Result<Reply, LizError> {
+        self._get_sc_by_id(id)?;
+        self.music_sheet
+            .revert_shortcut(id.0, revision)
+            .map_err(|_| LizError::RevisionNotFound { revision })?;
+        self.update_rank();
+        Ok(Reply::Done)
+    }
+
+    /// All the tags with how many shortcuts use them, the most used first
+    fn command_list_tags(&self) -> Result<Reply, LizError> {
+        Ok(Reply::Tags(
+            self.music_sheet
+                .tag_counts()
+                .into_iter()
+                .map(|(tag, count)| TagCount { tag, count })
+                .collect(),
+        ))
+    }
+
+    /// Rename the tags `from` to `to` in all the shortcuts.
+    /// If `to` is already used, the tags are merged. Returns how many shortcuts changed.
+    fn command_rename_tags(&mut self, from: &[String], to: &str) -> Result<Reply, LizError> {
+        let to = to.trim();
+        if to.is_empty() {
+            return Err(LizError::EmptyTag);
         }
+        let changed = self.music_sheet.rename_tags(from, to);
+        self.update_rank();
+        Ok(Reply::Count(changed))
     }
 
-    fn command_create_shortcuts(&mut self, cmd: &LizCommand) -> BlueBirdResponse {
-        match self._args_to_shortcut_vec(cmd) {
-            Ok(shortcuts) => {
-                self.music_sheet.add_shortcuts(shortcuts, None);
-                BlueBirdResponse::new()
-            }
-            Err(e) => {
-                eprintln!("Create Shortcuts: {}", e);
-                BlueBirdResponse {
-                    code: StateCode::BUG,
-                    results: vec![e],
-                }
-            }
+    /// Undo the latest change of the shortcuts, returns what kind of change was undone
+    fn command_undo(&mut self) -> Result<Reply, LizError> {
+        let label = self.music_sheet.undo().ok_or(LizError::NothingToUndo)?;
+        self.update_rank();
+        Ok(Reply::Label(label))
+    }
+
+    /// Redo the latest undone change of the shortcuts, returns what kind of change was redone
+    fn command_redo(&mut self) -> Result<Reply, LizError> {
+        let label = self.music_sheet.redo().ok_or(LizError::NothingToRedo)?;
+        self.update_rank();
+        Ok(Reply::Label(label))
+    }
+
+    /// Permanently remove the deleted shortcuts of the given ids, or all of them if no id is given.
+    /// Returns how many entries were removed.
+    fn command_purge_deleted(&mut self, ids: &[Id]) -> Result<Reply, LizError> {
+        let purged = if ids.is_empty() {
+            self.music_sheet.clear_deleted()
+        } else {
+            let ids: Vec<u128> = ids.iter().map(|id| id.0).collect();
+            self.music_sheet.purge_deleted(&ids)
+        };
+        Ok(Reply::Count(purged))
+    }
+
+    /// Apply `trash_retention_days` on the deleted shortcuts, 0 means to keep nothing
+    pub fn apply_trash_retention(&mut self) {
+        let purged = self
+            .music_sheet
+            .purge_deleted_older_than(days_to_secs(self.rhythm.trash_retention_days));
+        if purged > 0 {
+            eprintln!("Purged {} deleted shortcuts out of retention", purged);
         }
     }
 
-    fn command_update_shortcuts(&mut self, cmd: &LizCommand) -> BlueBirdResponse {
-        match self._args_to_shortcut_vec(cmd) {
-            Ok(shortcuts) => {
-                let unmatched: Vec<Shortcut> = self.music_sheet.update_shortcuts(shortcuts);
-                let unmatched: Vec<String> =
-                    unmatched.iter().map(|sc| sc.to_json_string()).collect();
-                if unmatched.is_empty() {
-                    BlueBirdResponse {
-                        code: StateCode::OK,
-                        results: unmatched,
-                    }
-                } else {
-                    eprintln!("Unmatched: {:?}", unmatched);
-                    BlueBirdResponse {
-                        code: StateCode::FAIL,
-                        results: unmatched,
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("Update Shortcuts: {}", e);
-                BlueBirdResponse {
-                    code: StateCode::BUG,
-                    results: vec![e],
-                }
-            }
+    /// Apply the new rhythm and save it to the default path, which is returned.
+    /// The rhythm is applied even if saving it fails.
+    fn command_update_rhythm(&mut self, new_rhythm: Rhythm) -> Result<Reply, LizError> {
+        let saved_path = new_rhythm.save_rhythm(None); // Save to the default path
+        self.music_sheet.set_undo_depth(new_rhythm.undo_depth);
+        self.music_sheet.set_pinyin_search(new_rhythm.pinyin_search);
+        if new_rhythm.input_backend != self.rhythm.input_backend {
+            self.input = None; // Closes the old backend
         }
+        self.rhythm = new_rhythm;
+        self.update_rank();
+        saved_path
+            .map(Reply::Path)
+            .map_err(|e| LizError::io(format!("Failed to save rhythm: {}", e)))
     }
 
-    fn command_delete_shortcuts(&mut self, cmd: &LizCommand) -> BlueBirdResponse {
-        let id_to_delete: Result<Vec<u128>, _> = cmd
-            .args
-            .iter()
-            .map(|id_str| string_to_id(&id_str))
-            .collect();
-        match id_to_delete {
-            Ok(id_to_delete) => {
-                self.music_sheet.delete_shortcuts(id_to_delete);
-                BlueBirdResponse::new()
-            }
-            Err(e) => {
-                let err_str = format!("Failed to parse id: {}", e);
-                eprintln!("Delete Shortcuts: {}", err_str);
-                BlueBirdResponse {
-                    code: StateCode::BUG,
-                    results: vec![err_str],
-                }
+    /// Execute the shortcut of given id, its placeholders are filled in `sequence`
+    fn _execute(&mut self, id: Id, sc: &Shortcut, sequence: &Sequence) -> Result<(), LizError> {
+        eprintln!("Execute: {}: {}", id, sc.shortcut); // Not the values, which may be secret
+        let kind = BackendKind::parse(&self.rhythm.input_backend);
+        let input = match &mut self.input {
+            Some(input) if input.kind == kind => input,
+            input => {
+                let opened = OpenBackend::open(kind).map_err(|e| LizError::ExecuteFailed {
+                    message: format!("Failed to open the {} input backend: {}", kind.name(), e),
+                })?;
+                input.insert(opened)
             }
+        };
+        if let Err(e) = execute_sequence(
+            input.backend.as_mut(),
+            sequence,
+            &self.music_sheet.keymap,
+            self.rhythm.interval_ms,
+            self.rhythm.key_delay_ms,
+        ) {
+            return Err(LizError::ExecuteFailed {
+                message: format!("Failed to execute shortcut {}: {}", sc.shortcut, e),
+            });
         }
+        let half_life_secs = self.rhythm.frecency_half_life_secs();
+        let _ = self.music_sheet.record_hit(id.0, half_life_secs);
+        self.update_rank();
+        Ok(())
     }
 
-    fn command_update_rhythm(&mut self, cmd: &LizCommand) -> BlueBirdResponse {
-        if cmd.args.is_empty() {
-            return BlueBirdResponse {
-                code: StateCode::BUG,
-                results: vec![format!("Settings is missing")]
+    /// The placeholders are asked when no values are given, or when one has neither a value
+    /// nor a default
+    fn command_execute(
+        &mut self,
+        id: Id,
+        values: Option<HashMap<String, String>>,
+    ) -> Result<Reply, LizError> {
+        let sc = self._get_sc_by_id(id)?;
+        let mut sequence = Sequence::parse_limited(&sc.shortcut, self.rhythm.repeat_limit)
+            .map_err(|e| invalid_shortcut(id, e))?;
+        let placeholders = placeholders_of(&sc, &sequence);
+        let asked = values.is_some();
+        let mut values = values.unwrap_or_default();
+        for p in &placeholders {
+            if let Some(default) = &p.default {
+                values.entry(p.name.clone()).or_insert_with(|| default.clone());
             }
         }
-
-        let new_rhythm = parse_rhythm(&cmd.args[0]);
-        match new_rhythm {
-            Ok(new_rhythm) => {
-                let saved_path = new_rhythm.save_rhythm(None); // Save to the default path
-                self.rhythm = new_rhythm;
-                match saved_path {
-                    Ok(saved_path) => BlueBirdResponse {
-                        code: StateCode::OK,
-                        results: vec![saved_path]
-                    },
-                    Err(e) => {
-                        let err_msg = format!("Failed to save rhythm to {}\nError: {}", &cmd.args[0], e);
-                        BlueBirdResponse {
-                        code: StateCode::FAIL,
-                        results: vec![err_msg]
-                        }
-                    }
-                }
-                
-            },
-            Err(e) => {
-                let err_msg = format!("Failed to parse rhythm: {}\nError: {}", cmd.args[0], e);
-                eprint!("{}", err_msg);
-                BlueBirdResponse {
-                    code: StateCode::BUG,
-                    results: vec![err_msg]
-                }
-            },
+        let missing = placeholders.iter().any(|p| !values.contains_key(&p.name));
+        if !placeholders.is_empty() && (!asked || missing) {
+            return Ok(Reply::NeedsInput { id, placeholders });
         }
+        sequence.fill(&values);
+        self._execute(id, &sc, &sequence)?;
+        Ok(Reply::Done)
     }
 
-    // fn command_reload(&mut self, cmd: &LizCommand) -> BlueBirdResponse {
-    //     let user_data_path: &String;
-    //     if cmd.args.is_empty() {
-    //         user_data_path = &self.rhythm.user_sheets_path;
-    //     } else {
-    //         user_data_path = &cmd.args[0];
-    //     }
-    //     match UserSheet::import_from(&user_data_path) {
-    //         Ok(user_data) => {
-    //             user_data.transform_to_db(&mut self.music_sheet);
-    //             BlueBirdResponse::new()
-    //         }
-    //         Err(e) => {
-    //             eprintln!(
-    //                 "Failure: failed to import user data from: {}, error: {}",
-    //                 user_data_path, e
-    //             );
-    //             BlueBirdResponse {
-    //                 code: StateCode::FAIL,
-    //                 results: vec![
-    //                     "Failure:".to_string(),
-    //                     "Failed to import:".to_string(),
-    //                     user_data_path.to_string(),
-    //                 ],
-    //             }
-    //         }
-    //     }
-    // }
-
-    /// Execute the shortcut of given id
-    fn _execute(&mut self, id_str: &str) -> Result<(), FluteExecuteError> {
-        match string_to_id(id_str) {
-            Ok(id) => {
-                let sc = self.music_sheet.retrieve(id, None);
-                if sc.is_none() {
-                    return Err(FluteExecuteError::new(
-                        &format!("No keycode found for id {}", id_str),
-                        StateCode::BUG,
-                    ));
-                }
-                let sc: &Shortcut = sc.unwrap();
-                let keycode = convert_shortcut_to_keycode(&sc.shortcut, &self.music_sheet.keymap);
-                println!("Execute: {}: {}", id_str, keycode);
-                if let Err(e) = execute_shortcut_enigo(&keycode, self.rhythm.interval_ms) {
-                    let err_str =
-                        format!("Enigo fails to execute shortcut {}: {}", sc.shortcut, e);
-                    return Err(FluteExecuteError::new(&err_str, StateCode::FAIL));
-                }
-                let _ = self.music_sheet.hit_num_up(id);
-                self.update_rank();
-                Ok(())
-            }
-            Err(e) => {
-                let err_str = format!("BUG: Failed to parse ID {}: {}", id_str, e);
-                Err(FluteExecuteError::new(&err_str, StateCode::BUG))
+    fn command_preview_execute(&self, id: Id) -> Result<Reply, LizError> {
+        let sc = self._get_sc_by_id(id)?;
+        let sequence = Sequence::parse_limited(&sc.shortcut, self.rhythm.repeat_limit)
+            .map_err(|e| invalid_shortcut(id, e))?;
+        let backend = BackendKind::parse(&self.rhythm.input_backend);
+        let preview = preview_shortcut(
+            &sequence,
+            &self.music_sheet.keymap,
+            backend,
+            self.rhythm.interval_ms,
+            self.rhythm.key_delay_ms,
+        )
+        .map_err(|e| LizError::ExecuteFailed {
+            message: format!("Failed to preview shortcut {}: {}", sc.shortcut, e),
+        })?;
+        Ok(Reply::Preview(preview))
+    }
+
+    /// Persist the music sheet, and keep a backup of it if `backup_count` is set
+    pub fn persist(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.rhythm.backup_count > 0 {
+            if let Err(e) = self
+                .music_sheet
+                .backup(&self.rhythm.backup_dir(), self.rhythm.backup_count)
+            {
+                eprintln!("Failed to back up music_sheet, error: {}", e);
             }
         }
+        self.music_sheet.persist()?;
+        self.last_saved = Some(Local::now());
+        self.autosave_retry = None;
+        Ok(())
     }
 
-    fn command_execute(&mut self, cmd: &LizCommand) -> BlueBirdResponse {
-        if cmd.args.is_empty() {
-            eprintln!("BUG: Empty args, expect one index on args[0]");
-            return BlueBirdResponse {
-                code: StateCode::BUG,
-                results: vec!["Empty args, expect one shortcut id".to_string()],
-            };
+    /// Persist the music sheet when there are changes and no more came in for `autosave_ms`.
+    /// Called periodically by the autosave thread. Nothing is saved without a storage,
+    /// and the next try after a failure waits longer each time.
+    pub fn autosave(&mut self) {
+        let debounce = Duration::from_millis(self.rhythm.autosave_ms);
+        if debounce.is_zero() || !self.music_sheet.has_storage() {
+            return;
         }
-        match self._execute(cmd.args[0].as_str()) {
-            Ok(_) => BlueBirdResponse::new(),
-            Err(e) => {
-                eprint!("Execute: {}", e);
-                BlueBirdResponse {
-                    results: vec![e.message().to_string()],
-                    code: e.code,
+        if let Some((retry_at, _)) = self.autosave_retry {
+            if Instant::now() < retry_at {
+                return;
+            }
+        }
+        if let Some((dirty_since, last_change)) = self.music_sheet.dirty_times() {
+            if last_change.elapsed() >= debounce
+                || dirty_since.elapsed() >= debounce * AUTOSAVE_MAX_DELAY_FACTOR
+            {
+                match self.music_sheet.persist() {
+                    Ok(()) => {
+                        self.last_saved = Some(Local::now());
+                        self.autosave_retry = None;
+                    }
+                    Err(e) => {
+                        let failures = self.autosave_retry.map_or(0, |(_, n)| n) + 1;
+                        let wait = debounce * (1 << failures.min(AUTOSAVE_MAX_BACKOFF));
+                        eprintln!(
+                            "Failed to autosave music_sheet, retrying in {:?}, error: {}",
+                            wait, e
+                        );
+                        self.autosave_retry = Some((Instant::now() + wait, failures));
+                    }
                 }
             }
         }
     }
 
-    pub fn persist(&self) -> Result<(), Box<dyn std::error::Error>> {
+    fn command_persist(&mut self) -> Result<Reply, LizError> {
+        self.persist()
+            .map_err(|e| LizError::io(format!("Failed to persist music_sheet: {}", e)))?;
+        Ok(Reply::Done)
+    }
+
+    fn command_list_backups(&self) -> Result<Reply, LizError> {
+        list_backups(&self.rhythm.backup_dir())
+            .map(Reply::Backups)
+            .map_err(|e| LizError::io(format!("Failed to list backups: {}", e)))
+    }
+
+    /// Restore the music sheet from the backup of the given name
+    fn command_restore_backup(&mut self, name: &str) -> Result<Reply, LizError> {
         self.music_sheet
-            .export_to_json(&self.rhythm.music_sheet_path)
+            .restore_backup(&self.rhythm.backup_dir(), name, self.rhythm.backup_count)
+            .map_err(|e| LizError::io(format!("Failed to restore backup {}: {}", name, e)))?;
+        self.update_rank();
+        Ok(Reply::Done)
     }
 
-    fn command_persist(&self, _cmd: &LizCommand) -> BlueBirdResponse {
-        match self.persist() {
-            Ok(()) => BlueBirdResponse::new(),
-            Err(e) => {
-                eprintln!("BUG: Failed to persist music_sheet, error: {}", e);
-                BlueBirdResponse {
-                    code: StateCode::BUG,
-                    results: vec!["Failed to persist music_sheet".to_string()],
-                }
-            }
-        }
+    fn command_info(&self) -> Result<Reply, LizError> {
+        let mut settings = self.rhythm.to_settings();
+        let last_saved = match self.last_saved {
+            Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => "".to_string(),
+        };
+        settings.push(json!({"name": "last_saved", "value": last_saved, "hint": "The last time the music sheet was saved", "readonly": true}));
+        Ok(Reply::Settings(settings))
     }
+}
 
-    fn command_info(&self, _cmd: &LizCommand) -> BlueBirdResponse {
-        let r: &Rhythm = &self.rhythm;
-        BlueBirdResponse {
-            code: StateCode::OK,
-            results: r.to_string_list(),
-        }
+fn days_to_secs(days: u64) -> i64 {
+    (days as i64).saturating_mul(24 * 60 * 60)
+}
+
+fn invalid_shortcut(id: Id, e: SequenceError) -> LizError {
+    LizError::InvalidShortcut {
+        id,
+        start: e.start,
+        end: e.end,
+        message: e.message,
     }
+}
 
-    fn command_default(&self, cmd: &LizCommand) -> BlueBirdResponse {
-        eprint!("Invalid Cmd: {:#?}", cmd);
-        BlueBirdResponse {
-            code: StateCode::BUG,
-            results: vec![format!("Invalid Liz Cmd: {}", cmd.action)],
+/// The placeholders in the shortcut, with the hints and defaults it keeps for them.
+/// A default written in the shortcut wins over the kept one.
+fn placeholders_of(sc: &Shortcut, sequence: &Sequence) -> Vec<Placeholder> {
+    let mut placeholders = sequence.placeholders();
+    for p in placeholders.iter_mut() {
+        if let Some(kept) = sc.placeholders.iter().find(|kept| kept.name == p.name) {
+            p.hint = kept.hint.clone();
+            if p.default.is_none() {
+                p.default = kept.default.clone();
+            }
         }
     }
+    placeholders
+}
+
+/// Refuse the shortcuts that cannot be parsed, or run more than `repeat_limit` steps,
+/// see `Sequence::parse_limited`
+fn check_shortcuts(shortcuts: &[Shortcut], repeat_limit: usize) -> Result<(), LizError> {
+    for sc in shortcuts {
+        Sequence::parse_limited(&sc.shortcut, repeat_limit)
+            .map_err(|e| invalid_shortcut(Id(sc.id), e))?;
+    }
+    Ok(())
 }
 
 // Implement the Drop trait for Flute
@@ -514,3 +599,139 @@ impl Flute {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::db::{DeleteReason, DeletedShortcut, MusicSheetDBTable, SCHEMA_VERSION};
+    use crate::tools::storage::JsonStorage;
+    use crate::tools::utils::now_timestamp;
+    use std::thread::sleep;
+
+    fn json_flute(path: &std::path::Path) -> Flute {
+        let storage = JsonStorage::new(path.to_str().unwrap());
+        Flute {
+            music_sheet: MusicSheetDB::with_storage(Box::new(storage)).unwrap(),
+            rhythm: Rhythm {
+                autosave_ms: 40,
+                ..Default::default()
+            },
+            last_saved: None,
+            autosave_retry: None,
+            input: None,
+        }
+    }
+
+    fn change(flute: &mut Flute) {
+        let shortcut = Shortcut {
+            id: generate_id(),
+            description: "Save file".to_string(),
+            shortcut: "\":w\"".to_string(),
+            ..Default::default()
+        };
+        flute.music_sheet.add_shortcuts(vec![shortcut], None);
+    }
+
+    #[test]
+    fn test_autosave() {
+        let path = std::env::temp_dir().join(format!("liz_test_{}.json", generate_id()));
+        let mut flute = json_flute(&path);
+        change(&mut flute);
+        flute.autosave();
+        assert!(flute.last_saved.is_none());
+        sleep(Duration::from_millis(50));
+        flute.autosave();
+        assert!(flute.last_saved.is_some());
+        assert!(flute.music_sheet.dirty_times().is_none());
+        assert!(path.exists());
+
+        // Changes coming in faster than autosave_ms are saved after the max delay
+        flute.last_saved = None;
+        let start = Instant::now();
+        while flute.last_saved.is_none() && start.elapsed() < Duration::from_secs(2) {
+            change(&mut flute);
+            sleep(Duration::from_millis(10));
+            flute.autosave();
+        }
+        assert!(flute.last_saved.is_some());
+        assert!(start.elapsed() >= Duration::from_millis(40) * AUTOSAVE_MAX_DELAY_FACTOR);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut memory = json_flute(&path);
+        memory.music_sheet = MusicSheetDB::new();
+        change(&mut memory);
+        sleep(Duration::from_millis(50));
+        memory.autosave();
+        assert!(memory.last_saved.is_none() && memory.autosave_retry.is_none());
+    }
+
+    #[test]
+    fn test_autosave_backoff() {
+        let dir = std::env::temp_dir().join(format!("liz_test_{}", generate_id()));
+        let mut flute = json_flute(&dir.join("music_sheet.lock"));
+        change(&mut flute);
+        sleep(Duration::from_millis(50));
+        flute.autosave();
+        assert_eq!(flute.autosave_retry.map(|(_, n)| n), Some(1));
+        flute.autosave();
+        assert_eq!(flute.autosave_retry.map(|(_, n)| n), Some(1));
+        sleep(Duration::from_millis(90));
+        flute.autosave();
+        assert_eq!(flute.autosave_retry.map(|(_, n)| n), Some(2));
+
+        std::fs::create_dir(&dir).unwrap();
+        sleep(Duration::from_millis(170));
+        flute.autosave();
+        assert!(flute.last_saved.is_some() && flute.autosave_retry.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trash() {
+        let now = now_timestamp();
+        let trashed = |desc: &str, deleted_at: i64| DeletedShortcut {
+            shortcut: Shortcut {
+                id: generate_id(),
+                description: desc.to_string(),
+                ..Default::default()
+            },
+            deleted_at,
+            reason: DeleteReason::Delete,
+        };
+        let old = trashed("old", now - days_to_secs(30));
+        let recent = trashed("recent", now - 60);
+        let (old_id, recent_id) = (Id(old.shortcut.id), Id(recent.shortcut.id));
+        let t = MusicSheetDBTable {
+            schema_version: SCHEMA_VERSION,
+            deleted: vec![old, trashed("edge", now - days_to_secs(30) + 60), recent],
+            data: Vec::new(),
+            revisions: Vec::new(),
+        };
+        let path = std::env::temp_dir().join(format!("liz_test_{}.json", generate_id()));
+        std::fs::write(&path, serde_json::to_vec(&t).unwrap()).unwrap();
+        let mut flute = json_flute(&path);
+        let _ = std::fs::remove_file(&path);
+
+        // The retention purges what was deleted at least trash_retention_days ago
+        flute.rhythm.trash_retention_days = 30;
+        flute.apply_trash_retention();
+        assert_eq!(flute.music_sheet.retrieve_deleted().len(), 2);
+        let err = flute
+            .run(Command::RestoreShortcuts {
+                ids: vec![old_id, recent_id],
+            })
+            .unwrap_err();
+        assert_eq!(err, LizError::NotFound { ids: vec![old_id] });
+        assert!(flute.music_sheet.retrieve(recent_id.0, None).is_some());
+
+        let unknown = Id(generate_id());
+        let reply = flute.run(Command::PurgeDeleted { ids: vec![unknown] });
+        assert!(matches!(reply, Ok(Reply::Count(0))));
+        assert_eq!(flute.music_sheet.retrieve_deleted().len(), 1);
+        let reply = flute.run(Command::PurgeDeletedOlderThan { days: 30 });
+        assert!(matches!(reply, Ok(Reply::Count(0))));
+        let reply = flute.run(Command::PurgeDeletedOlderThan { days: 29 });
+        assert!(matches!(reply, Ok(Reply::Count(1))));
+        assert!(flute.music_sheet.retrieve_deleted().is_empty());
+    }
+}
@@ -0,0 +1,398 @@
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+
+use serde_json::{json, Map, Value};
+
+use crate::protocol::{Command, LizError, Reply};
+
+// The error codes of JSON-RPC 2.0
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const COMMAND_FAILED: i64 = -32000; // The command ran and failed, the LizError is in data
+
+/// The socket being listened on, removed on exit
+pub struct Socket(pub String);
+
+/// Runs the commands coming from the clients
+pub type Handler = Arc<dyn Fn(Command) -> Result<Reply, LizError> + Send + Sync>;
+
+fn error_response(id: Value, code: i64, message: &str, data: Option<Value>) -> Value {
+    let mut error = json!({"code": code, "message": message});
+    if let Some(data) = data {
+        error["data"] = data;
+    }
+    json!({"jsonrpc": "2.0", "id": id, "error": error})
+}
+
+/// Answer one line of JSON-RPC 2.0, None for a notification (a request without id).
+///
+/// The method is the action of a `Command` and the params are its fields, e.g.
+/// `{"jsonrpc": "2.0", "id": 1, "method": "get_shortcuts", "params": {"query": "vim"}}`.
+/// The result is the `Reply`, and a failed command is an error with the `LizError` in data.
+pub fn handle_line(line: &str, handler: &Handler) -> Option<String> {
+    let response = match serde_json::from_str::<Value>(line) {
+        Ok(Value::Object(request)) => handle_request(request, handler)?,
+        Ok(_) => error_response(
+            Value::Null,
+            INVALID_REQUEST,
+            "Expect one request object",
+            None,
+        ),
+        Err(e) => error_response(Value::Null, PARSE_ERROR, &e.to_string(), None),
+    };
+    Some(response.to_string())
+}
+
+fn handle_request(mut request: Map<String, Value>, handler: &Handler) -> Option<Value> {
+    let id = request.remove("id").unwrap_or(Value::Null);
+    let notification = id.is_null();
+    let version = request.remove("jsonrpc");
+    let method = match (version, request.remove("method")) {
+        (Some(v), Some(Value::String(method))) if v == "2.0" => method,
+        _ => {
+            let message = "Expect jsonrpc \"2.0\" and a method";
+            return Some(error_response(id, INVALID_REQUEST, message, None));
+        }
+    };
+    let mut params = match request.remove("params") {
+        None => Map::new(),
+        Some(Value::Object(params)) => params,
+        Some(_) => {
+            let message = "Expect the params by name";
+            return Some(error_response(id, INVALID_PARAMS, message, None));
+        }
+    };
+    params.insert("action".to_string(), Value::String(method));
+    let response = match serde_json::from_value::<Command>(Value::Object(params)) {
+        Ok(cmd) => match handler(cmd) {
+            Ok(reply) => json!({"jsonrpc": "2.0", "id": id, "result": reply}),
+            Err(e) => {
+                let data = serde_json::to_value(&e).ok();
+                error_response(id, COMMAND_FAILED, &e.to_string(), data)
+            }
+        },
+        // The action is the tag of Command, so an unknown one is an unknown variant
+        Err(e) if e.to_string().starts_with("unknown variant") => {
+            error_response(id, METHOD_NOT_FOUND, &e.to_string(), None)
+        }
+        Err(e) => error_response(id, INVALID_PARAMS, &e.to_string(), None),
+    };
+    (!notification).then_some(response)
+}
+
+/// Answer the requests of one client, one per line, until it disconnects
+pub fn serve_connection<R: BufRead, W: Write>(
+    reader: R,
+    mut writer: W,
+    handler: &Handler,
+) -> io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_line(&line, handler) {
+            writer.write_all(response.as_bytes())?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+type Stream = std::os::unix::net::UnixStream;
+#[cfg(windows)]
+type Stream = std::fs::File;
+
+/// A connection to the socket of a running instance, e.g. for the command line
+pub struct Client {
+    reader: io::BufReader<Stream>,
+    writer: Stream,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connect to the instance listening on `path`, fails if there is none
+    pub fn connect(path: &str) -> io::Result<Self> {
+        #[cfg(unix)]
+        let stream = Stream::connect(path)?;
+        #[cfg(windows)]
+        let stream = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)?;
+        Ok(Client {
+            reader: io::BufReader::new(stream.try_clone()?),
+            writer: stream,
+            next_id: 1,
+        })
+    }
+
+    /// Run the command in the instance and return the JSON of its `Reply`.
+    /// The outer error is about the connection, the inner one is the failed command.
+    pub fn call(&mut self, cmd: &Command) -> io::Result<Result<Value, LizError>> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let Value::Object(mut params) = serde_json::to_value(cmd)? else {
+            return Err(invalid("Expect a command object".to_string()));
+        };
+        let method = params.remove("action").unwrap_or(Value::Null);
+        let request =
+            json!({"jsonrpc": "2.0", "id": self.next_id, "method": method, "params": params});
+        self.next_id += 1;
+        self.writer.write_all(format!("{}\n", request).as_bytes())?;
+        self.writer.flush()?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut response: Value = serde_json::from_str(&line)?;
+        if let Some(result) = response.get_mut("result") {
+            return Ok(Ok(result.take()));
+        }
+        let error = response
+            .get_mut("error")
+            .map(Value::take)
+            .ok_or_else(|| invalid(format!("Not a JSON-RPC response: {}", line.trim())))?;
+        let message = error["message"].as_str().unwrap_or_default().to_string();
+        Ok(Err(
+            serde_json::from_value(error["data"].clone()).unwrap_or(LizError::Internal { message })
+        ))
+    }
+}
+
+/// Listen on the Unix domain socket at `path`, serving every client in its own thread.
+/// A new parent dir is only accessible by the current user, and so is the socket, which is
+/// bound in a private dir and moved into place once narrowed, so it is never open to others.
+/// A socket left by a crashed instance is replaced, but not one that is still answering.
+#[cfg(unix)]
+pub fn start(path: &str, handler: Handler) -> io::Result<()> {
+    use std::fs::{self, DirBuilder, Permissions};
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+
+    if let Some(parent) = Path::new(path).parent().filter(|p| !p.exists()) {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)?;
+    }
+    if Path::new(path).exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is used by another instance", path),
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    let private = Path::new(path).with_extension(format!("{}.d", std::process::id()));
+    if private.exists() {
+        fs::remove_dir_all(&private)?;
+    }
+    DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = private.join("s");
+    let listener = UnixListener::bind(&bound).and_then(|listener| {
+        fs::set_permissions(&bound, Permissions::from_mode(0o600))?;
+        fs::rename(&bound, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_dir_all(&private);
+    let listener = listener?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let handler = handler.clone();
+                    std::thread::spawn(move || {
+                        let reader = io::BufReader::new(&stream);
+                        if let Err(e) = serve_connection(reader, &stream, &handler) {
+                            eprintln!("IPC: Connection closed with error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => eprintln!("IPC: Failed to accept a client: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Remove the socket, called on exit
+#[cfg(unix)]
+pub fn stop(path: &str) {
+    let _ = std::fs::remove_file(path);
+}
+
+/// Listen on the named pipe at `path`, serving every client in its own task.
+/// Remote clients are rejected, and the default security of a pipe only lets
+/// the current user (and administrators) write to it.
+#[cfg(windows)]
+pub fn start(path: &str, handler: Handler) -> io::Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let path = path.to_string();
+    // The pipe is registered to the runtime, so it is created in there
+    let mut server = tauri::async_runtime::block_on(async {
+        ServerOptions::new()
+            .first_pipe_instance(true)
+            .reject_remote_clients(true)
+            .create(&path)
+    })?;
+    tauri::async_runtime::spawn(async move {
+        loop {
+            if let Err(e) = server.connect().await {
+                eprintln!("IPC: Failed to accept a client: {}", e);
+                continue;
+            }
+            let client = server;
+            server = match ServerOptions::new()
+                .reject_remote_clients(true)
+                .create(&path)
+            {
+                Ok(server) => server,
+                Err(e) => {
+                    eprintln!("IPC: Failed to create the pipe {}: {}", path, e);
+                    return;
+                }
+            };
+            let handler = handler.clone();
+            tauri::async_runtime::spawn(async move {
+                let (reader, mut writer) = tokio::io::split(client);
+                let mut lines = BufReader::new(reader).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let handler = handler.clone();
+                    let response =
+                        tokio::task::spawn_blocking(move || handle_line(&line, &handler)).await;
+                    if let Ok(Some(response)) = response {
+                        let written = writer.write_all(format!("{}\n", response).as_bytes());
+                        if written.await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// A named pipe is gone with the process
+#[cfg(windows)]
+pub fn stop(_path: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Id;
+
+    fn handler() -> Handler {
+        Arc::new(|cmd| match cmd {
+            Command::NewId => Ok(Reply::Id(Id(1))),
+            Command::Execute { id, .. } => Err(LizError::NotFound { ids: vec![id] }),
+            _ => Ok(Reply::Done),
+        })
+    }
+
+    fn call(line: &str) -> Value {
+        serde_json::from_str(&handle_line(line, &handler()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_json_rpc() {
+        let resp = call(r#"{"jsonrpc": "2.0", "id": 1, "method": "new_id"}"#);
+        assert_eq!(
+            resp["result"],
+            json!({"type": "id", "data": Id(1).to_string()})
+        );
+        assert_eq!(resp["id"], 1);
+
+        let id = Id(2).to_string();
+        let resp = call(&format!(
+            r#"{{"jsonrpc": "2.0", "id": "a", "method": "execute", "params": {{"id": "{}"}}}}"#,
+            id
+        ));
+        assert_eq!(resp["error"]["code"], COMMAND_FAILED);
+        assert_eq!(
+            resp["error"]["data"],
+            json!({"kind": "not_found", "ids": [id]})
+        );
+
+        let code = |line: &str| call(line)["error"]["code"].as_i64().unwrap();
+        assert_eq!(code("{"), PARSE_ERROR);
+        assert_eq!(code("[]"), INVALID_REQUEST);
+        assert_eq!(code(r#"{"id": 1, "method": "new_id"}"#), INVALID_REQUEST);
+        assert_eq!(
+            code(r#"{"jsonrpc": "2.0", "id": 1, "method": "fly"}"#),
+            METHOD_NOT_FOUND
+        );
+        let line = r#"{"jsonrpc": "2.0", "id": 1, "method": "execute", "params": {}}"#;
+        assert_eq!(code(line), INVALID_PARAMS);
+
+        // Notifications are not answered
+        let line = r#"{"jsonrpc": "2.0", "method": "persist"}"#;
+        assert_eq!(handle_line(line, &handler()), None);
+
+        let input = "\n{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"persist\"}\n";
+        let mut output: Vec<u8> = Vec::new();
+        serve_connection(input.as_bytes(), &mut output, &handler()).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"id\":1,\"jsonrpc\":\"2.0\",\"result\":{\"type\":\"done\"}}\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket() {
+        use std::io::{BufReader, Read};
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::UnixStream;
+
+        let dir = std::env::temp_dir().join(format!("liz_ipc_{}", std::process::id()));
+        let path = dir.join("run/liz.sock");
+        let path = path.to_str().unwrap();
+        start(path, handler()).unwrap();
+        let mode = |p: &str| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(path), 0o600);
+        assert_eq!(mode(dir.join("run").to_str().unwrap()), 0o700);
+        assert_eq!(std::fs::read_dir(dir.join("run")).unwrap().count(), 1);
+        // The socket is still answering, so it is not taken over
+        assert!(start(path, handler()).is_err());
+
+        let mut stream = UnixStream::connect(path).unwrap();
+        stream
+            .write_all(b"{\"jsonrpc\": \"2.0\", \"id\": 7, \"method\": \"new_id\"}\n")
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&line).unwrap()["id"], 7);
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        assert!(rest.is_empty());
+
+        let mut client = Client::connect(path).unwrap();
+        let reply = client.call(&Command::NewId).unwrap().unwrap();
+        assert_eq!(reply, json!({"type": "id", "data": Id(1).to_string()}));
+        let failed = client
+            .call(&Command::Execute {
+                id: Id(3),
+                values: None,
+            })
+            .unwrap();
+        assert_eq!(failed, Err(LizError::NotFound { ids: vec![Id(3)] }));
+
+        stop(path);
+        assert!(Client::connect(path).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -5,11 +5,15 @@ use setup::create_flute;
 use tauri::{AppHandle, Emitter, Manager, RunEvent};
 use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
 
+mod cli;
 mod flute;
+mod ipc;
+mod protocol;
 mod setup;
 mod tools;
 use flute::{BlueBirdResponse, Flute, LizCommand, StateCode};
-use tools::trans::TranslationCache;
+use protocol::{Command, LizError, Reply};
+use tools::{trans::TranslationCache, utils::InstanceLock};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -17,6 +21,13 @@ struct Args {
     /// Path to the configuration file
     #[arg(short, long, value_name = "PATH")]
     config: Option<String>,
+
+    #[command(flatten)]
+    forward: cli::Forward,
+
+    /// Run a command without the window, e.g. `liz search vim`
+    #[command(subcommand)]
+    command: Option<cli::CliCommand>,
 }
 
 fn execute_cmd(cmd: LizCommand, app: &AppHandle) -> BlueBirdResponse {
@@ -32,27 +43,68 @@ fn execute_cmd(cmd: LizCommand, app: &AppHandle) -> BlueBirdResponse {
     }
 }
 
+/// Reload the translations, the language could have been changed by the new rhythm
+fn reload_translations(app: &AppHandle) {
+    let cache = app.state::<Mutex<TranslationCache>>();
+    let flute = app.state::<Mutex<Flute>>();
+    let flute = flute.lock().unwrap();
+    if let Ok(resource_path) = app.path().resource_dir() {
+        cache.lock().as_mut().unwrap().reload(&flute.rhythm.language, &resource_path);
+    }
+}
+
+/// Run a command of the old string protocol
 #[tauri::command]
 fn send_command(cmd: LizCommand, app: AppHandle) -> BlueBirdResponse {
-    match cmd.action.as_str() {
-        "reload" | "create_shortcuts" | "update_shortcuts" | "delete_shortcuts"
-        | "import_shortcuts" => {
-            let resp: BlueBirdResponse = execute_cmd(cmd, &app);
-            let _ = app.emit("fetch-again", "");
-            resp
-        }
-        "update_rhythm" => {
-            let resp: BlueBirdResponse = execute_cmd(cmd, &app);
-            let cache = app.state::<Mutex<TranslationCache>>();
-            let flute = app.state::<Mutex<Flute>>();
-            let flute = flute.lock().unwrap();
-            if let Ok(resource_path) = app.path().resource_dir() {
-                cache.lock().as_mut().unwrap().reload(&flute.rhythm.language, &resource_path);
-            }
-            resp
+    let command = Command::from_legacy(&cmd).ok();
+    let changes_shortcuts = command.as_ref().is_some_and(Command::changes_shortcuts);
+    let updates_rhythm = matches!(command, Some(Command::UpdateRhythm { .. }));
+    let resp: BlueBirdResponse = execute_cmd(cmd, &app);
+    if changes_shortcuts {
+        let _ = app.emit("fetch-again", "");
+    }
+    if updates_rhythm {
+        reload_translations(&app);
+    }
+    resp
+}
+
+/// Run a typed command, a failed one rejects with the LizError
+#[tauri::command]
+fn run_command(cmd: Command, app: AppHandle) -> Result<Reply, LizError> {
+    run_typed(cmd, &app)
+}
+
+/// Run a typed command from the webview or the IPC server
+fn run_typed(cmd: Command, app: &AppHandle) -> Result<Reply, LizError> {
+    if let Command::Show { query } = &cmd {
+        setup::show_launcher(app, query.as_deref());
+        return Ok(Reply::Done);
+    }
+    let changes_shortcuts = cmd.changes_shortcuts();
+    let updates_rhythm = matches!(cmd, Command::UpdateRhythm { .. });
+    let result = match app.state::<Mutex<Flute>>().lock() {
+        Ok(mut flute) => flute.run(cmd),
+        Err(e) => {
+            eprintln!("Failed to lock Flute because: {}", e);
+            Err(LizError::Internal {
+                message: format!("Failed to lock Flute: {}", e),
+            })
         }
-        _ => execute_cmd(cmd, &app),
+    };
+    if changes_shortcuts {
+        let _ = app.emit("fetch-again", "");
     }
+    if updates_rhythm {
+        reload_translations(app);
+    }
+    result
+}
+
+/// Take the query of `liz --search` waiting for the launcher, see `setup::PendingSearch`
+#[tauri::command]
+fn take_search(state: tauri::State<setup::PendingSearch>) -> Option<String> {
+    state.0.lock().ok().and_then(|mut pending| pending.take())
 }
 
 /// Get a single translation
@@ -78,12 +130,31 @@ fn get_translations(keys: Vec<String>, state: tauri::State<Mutex<TranslationCach
     results
 }
 
+/// Run the arguments forwarded by another `liz`, which was started in `cwd`
+fn run_forwarded(app: &AppHandle, forward: &cli::Forward, cwd: &str) {
+    let commands = match forward.commands(std::path::Path::new(cwd)) {
+        Ok(commands) => commands,
+        Err(e) => {
+            eprintln!("Failed to forward the arguments: {}", e);
+            return;
+        }
+    };
+    for cmd in commands {
+        if let Err(e) = run_typed(cmd, app) {
+            eprintln!("Failed to run the forwarded arguments: {}", e);
+            return;
+        }
+    }
+}
+
 fn cleanup(app: &AppHandle) {
+    if let Some(socket) = app.try_state::<ipc::Socket>() {
+        ipc::stop(&socket.0);
+    }
     match app.state::<Mutex<Flute>>().lock() {
         Ok(mut flute) => {
-            flute.music_sheet.clear_deleted(); // TODO: Deleted shall be more wisely handled.
-            let file_path: &String = &flute.rhythm.music_sheet_path;
-            if let Err(e) = flute.music_sheet.export_to_json(file_path) {
+            flute.apply_trash_retention();
+            if let Err(e) = flute.persist() {
                 eprintln!("Failed to save music sheet in Drop: {}", e);
             } else {
                 println!("Music sheet saved successfully.");
@@ -99,6 +170,16 @@ fn cleanup(app: &AppHandle) {
 pub fn run() {
     // Parse the arguments
     let args = Args::parse();
+    if let Some(command) = args.command {
+        exit(cli::run(command, args.config));
+    }
+    // The results are reported here when the running instance answers on its socket,
+    // otherwise the arguments are forwarded by the single instance plugin
+    if !args.forward.is_empty() {
+        if let Some(code) = cli::forward(&args.forward, args.config.clone()) {
+            exit(code);
+        }
+    }
 
     let mut builder = tauri::Builder::default()
         .plugin(tauri_plugin_process::init())
@@ -108,14 +189,12 @@ pub fn run() {
 
     #[cfg(desktop)]
     {
-        builder = builder.plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
-            if let Some(win) = app.get_webview_window("main") {
-                if let Err(e) = win.show() {
-                    println!("Failed to show the app, err: {}", e);
-                }
-                if let Err(e) = win.set_focus() {
-                    println!("Failed to focus the app, err: {}", e);
-                }
+        builder = builder.plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+            let forward = Args::try_parse_from(args).map(|args| args.forward).unwrap_or_default();
+            if forward.is_empty() {
+                setup::show_launcher(app, None);
+            } else {
+                run_forwarded(app, &forward, &cwd);
             }
         }));
     }
@@ -126,15 +205,42 @@ pub fn run() {
             match create_flute(args.config) {
                 Ok(flute) => {
                     trigger_shortcut = flute.rhythm.trigger_shortcut.clone();
+                    let ipc_path = flute.rhythm.ipc_enabled.then(|| flute.rhythm.ipc_path());
+                    // Keeps `liz` on the command line from writing behind the app
+                    let lock_path = flute.rhythm.instance_lock_path();
+                    match InstanceLock::acquire(std::path::Path::new(&lock_path)) {
+                        Ok(Some(lock)) => {
+                            let _ = app.manage(lock);
+                        }
+                        Ok(None) => eprintln!("Another liz holds {}", lock_path),
+                        Err(e) => eprintln!("Failed to lock {}: {}", lock_path, e),
+                    }
                     let resource_path = app.path().resource_dir()?;
                     let cache: TranslationCache = TranslationCache::load(&flute.rhythm.language, &resource_path);
                     let _ = setup::setup_tray(app, &cache);
                     let _ = app.manage(Mutex::new(cache));
                     let _ = app.manage(Mutex::new(flute));
+                    let _ = app.manage(setup::PendingSearch::default());
+                    setup::setup_autosave(app);
+                    if let Some(ipc_path) = ipc_path {
+                        setup::setup_ipc(app, ipc_path);
+                    }
+                    // The query waits for the launcher page to be loaded
+                    let forward = args.forward;
+                    if let Ok(cwd) = std::env::current_dir() {
+                        run_forwarded(app.handle(), &forward, &cwd.to_string_lossy());
+                    }
                 }
                 Err(e) => {
                     eprintln!("Failed to get flute: {}", e);
-                    exit(1);
+                    // Nothing is managed yet, so the dialog exits the process itself
+                    app.dialog()
+                        .message(format!("Failed to start Liz: {}", e))
+                        .kind(MessageDialogKind::Error)
+                        .title("Error")
+                        .buttons(MessageDialogButtons::OkCustom("OK".to_owned()))
+                        .show(|_r| exit(1));
+                    return Ok(());
                 }
             }
             if let Err(e) = setup::register_trigger_shortcut(app, trigger_shortcut.as_str()) {
@@ -148,7 +254,7 @@ pub fn run() {
             }
             Ok(())
         })
-        .invoke_handler(tauri::generate_handler![send_command, get_translation, get_translations])
+        .invoke_handler(tauri::generate_handler![send_command, run_command, take_search, get_translation, get_translations])
         .build(tauri::generate_context!())
         .expect("error while building tauri application")
         .run(|app_handle, e| match e {
@@ -0,0 +1,802 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use serde::{de, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::flute::{BlueBirdResponse, LizCommand, StateCode};
+use crate::tools::{
+    backup::BackupInfo,
+    db::{DeletedShortcut, Revision, Shortcut},
+    exec::Preview,
+    matcher::FieldMatch,
+    page::{Page, PageRequest},
+    rhythm::{parse_rhythm, Rhythm},
+    sequence::Placeholder,
+    utils::{id_to_string, string_to_id},
+};
+
+/// The id of a shortcut, a UUID string in JSON since JavaScript numbers cannot hold an u128
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Id(pub u128);
+
+impl Id {
+    pub fn parse(s: &str) -> Result<Self, LizError> {
+        string_to_id(s)
+            .map(Id)
+            .map_err(|e| LizError::invalid(format!("Failed to parse id: {}", e)))
+    }
+}
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", id_to_string(self.0))
+    }
+}
+
+impl Serialize for Id {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&id_to_string(self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Id {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s: String = String::deserialize(deserializer)?;
+        string_to_id(&s).map(Id).map_err(de::Error::custom)
+    }
+}
+
+/// Which part of the search results to return, see `PageRequest`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PageArgs {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>, // The next_cursor of the previous page, it wins over the offset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>, // None or 0 for all the results
+}
+
+impl PageArgs {
+    pub fn request(&self) -> Result<PageRequest, LizError> {
+        PageRequest::new(self.cursor.as_deref(), self.offset, self.limit).map_err(LizError::invalid)
+    }
+}
+
+/// A command to Flute, tagged by its action, e.g.
+/// `{"action": "get_shortcuts", "query": "app:vim", "limit": 50}`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Command {
+    GetShortcuts {
+        #[serde(default)]
+        query: String,
+        #[serde(flatten)]
+        page: PageArgs,
+    },
+    GetShortcutDetails {
+        #[serde(default)]
+        query: String,
+        #[serde(flatten)]
+        page: PageArgs,
+    },
+    /// Without the values of its placeholders, the shortcut answers `needs_input`
+    Execute {
+        id: Id,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        values: Option<HashMap<String, String>>,
+    },
+    /// What `execute` would press and type, without sending any key
+    PreviewExecute {
+        id: Id,
+    },
+    Persist,
+    Info,
+    NewId,
+    CreateShortcuts {
+        shortcuts: Vec<Shortcut>,
+    },
+    UpdateShortcuts {
+        shortcuts: Vec<Shortcut>,
+    },
+    DeleteShortcuts {
+        ids: Vec<Id>,
+    },
+    GetDeletedShortcutDetails,
+    RestoreShortcuts {
+        ids: Vec<Id>,
+    },
+    GetRevisions {
+        id: Id,
+    },
+    RevertShortcut {
+        id: Id,
+        revision: u64,
+    },
+    ListTags,
+    RenameTag {
+        from: String,
+        to: String,
+    },
+    MergeTags {
+        into: String,
+        tags: Vec<String>,
+    },
+    Undo,
+    Redo,
+    PurgeDeleted {
+        #[serde(default)]
+        ids: Vec<Id>, // Empty to purge all of them
+    },
+    PurgeDeletedOlderThan {
+        days: u64,
+    },
+    ExportShortcuts {
+        path: String,
+        ids: Vec<Id>,
+    },
+    ImportShortcuts {
+        paths: Vec<String>,
+    },
+    UpdateRhythm {
+        rhythm: Box<Rhythm>,
+    },
+    ListBackups,
+    RestoreBackup {
+        name: String,
+    },
+    /// Show the launcher, with the query in the search bar if given.
+    /// Handled by the app, as Flute has no window.
+    Show {
+        query: Option<String>,
+    },
+}
+
+impl Command {
+    /// Whether the command can change the shortcuts, so the lists shown should be fetched again
+    pub fn changes_shortcuts(&self) -> bool {
+        matches!(
+            self,
+            Command::CreateShortcuts { .. }
+                | Command::UpdateShortcuts { .. }
+                | Command::DeleteShortcuts { .. }
+                | Command::ImportShortcuts { .. }
+                | Command::RestoreBackup { .. }
+                | Command::RestoreShortcuts { .. }
+                | Command::RevertShortcut { .. }
+                | Command::RenameTag { .. }
+                | Command::MergeTags { .. }
+                | Command::Undo
+                | Command::Redo
+        )
+    }
+}
+
+/// A shortcut found by `get_shortcuts`, formatted by `shortcut_print_fmt` with the
+/// matched chars wrapped in <mark>
+#[derive(Debug, Serialize)]
+pub struct Hit {
+    pub id: Id,
+    pub sc: String,
+    pub matches: Vec<FieldMatch>, // The ranges of the matched chars of each field
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+/// The result of a command, tagged by its type, e.g. `{"type": "count", "data": 3}`
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Reply {
+    Done,
+    Hits {
+        page: Page,
+        hits: Vec<Hit>,
+    },
+    Shortcuts {
+        page: Page,
+        shortcuts: Vec<Shortcut>,
+    },
+    Deleted(Vec<DeletedShortcut>),
+    Revisions(Vec<Revision>), // The newest first
+    Id(Id),
+    Tags(Vec<TagCount>), // The most used first
+    Count(usize),        // How many shortcuts changed or were purged
+    Label(String),       // What kind of change was undone or redone
+    Path(String),        // Where the rhythm was saved
+    Settings(Vec<Value>),
+    Backups(Vec<BackupInfo>),
+    Preview(Preview),
+    NeedsInput {
+        id: Id,
+        placeholders: Vec<Placeholder>, // Asked in this order, then `execute` again with their values
+    },
+}
+
+/// Why a command failed, tagged by its kind, e.g. `{"kind": "not_found", "ids": [...]}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LizError {
+    InvalidCommand { message: String }, // The command or its args cannot be parsed
+    InvalidQuery { position: usize, message: String },
+    InvalidShortcut { id: Id, start: usize, end: usize, message: String }, // The chars start..end of its shortcut are wrong
+    StaleCursor, // The shortcuts changed since the cursor was given
+    NotFound { ids: Vec<Id> },
+    RevisionNotFound { revision: u64 },
+    NothingToUndo,
+    NothingToRedo,
+    EmptyTag,
+    ImportFailed { paths: Vec<String> },
+    ExecuteFailed { message: String },
+    Io { message: String },       // Reading or writing a file failed
+    Internal { message: String }, // A bug of Liz
+}
+
+impl LizError {
+    pub fn invalid(message: impl Into<String>) -> Self {
+        LizError::InvalidCommand {
+            message: message.into(),
+        }
+    }
+
+    pub fn io(message: impl Into<String>) -> Self {
+        LizError::Io {
+            message: message.into(),
+        }
+    }
+
+    /// The code of the old protocol, BUG for what the caller or Liz got wrong
+    pub fn code(&self) -> StateCode {
+        match self {
+            LizError::InvalidCommand { .. } | LizError::Internal { .. } => StateCode::BUG,
+            _ => StateCode::FAIL,
+        }
+    }
+}
+
+impl fmt::Display for LizError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LizError::InvalidCommand { message }
+            | LizError::ExecuteFailed { message }
+            | LizError::Io { message }
+            | LizError::Internal { message } => write!(f, "{}", message),
+            LizError::InvalidQuery { position, message } => {
+                write!(f, "Invalid query: {} at position {}", message, position)
+            }
+            LizError::InvalidShortcut {
+                id, start, message, ..
+            } => {
+                write!(f, "Invalid shortcut {}: {} at position {}", id, message, start)
+            }
+            LizError::StaleCursor => write!(f, "The shortcuts changed since the cursor was given"),
+            LizError::NotFound { ids } => {
+                let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                write!(f, "Shortcuts not found: {}", ids.join(", "))
+            }
+            LizError::RevisionNotFound { revision } => {
+                write!(f, "Revision {} does not exist", revision)
+            }
+            LizError::NothingToUndo => write!(f, "Nothing to undo"),
+            LizError::NothingToRedo => write!(f, "Nothing to redo"),
+            LizError::EmptyTag => write!(f, "The new tag is empty"),
+            LizError::ImportFailed { paths } => {
+                write!(f, "Failed to import {}", paths.join(", "))
+            }
+        }
+    }
+}
+
+impl Error for LizError {}
+
+// The old protocol, where the args and the results are strings.
+// Kept for the clients still sending `LizCommand`, the results are the same as before.
+
+fn legacy_arg<'a>(cmd: &'a LizCommand, i: usize, expected: &str) -> Result<&'a str, LizError> {
+    cmd.args
+        .get(i)
+        .map(|arg| arg.as_str())
+        .ok_or_else(|| LizError::invalid(format!("Empty args, expect {}", expected)))
+}
+
+fn legacy_ids(args: &[String]) -> Result<Vec<Id>, LizError> {
+    args.iter().map(|id| Id::parse(id)).collect()
+}
+
+fn legacy_shortcuts(args: &[String]) -> Result<Vec<Shortcut>, LizError> {
+    args.iter()
+        .map(|sc| Shortcut::from_json_string(sc))
+        .collect::<Result<Vec<Shortcut>, _>>()
+        .map_err(|e| LizError::invalid(format!("Failed to parse shortcut: {}", e)))
+}
+
+/// The results are always serializable
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+/// args[1] is the values of the placeholders as a JSON object, it can be empty
+fn legacy_values(args: &[String]) -> Result<Option<HashMap<String, String>>, LizError> {
+    match args.get(1).map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        Some(values) => serde_json::from_str(values)
+            .map(Some)
+            .map_err(|e| LizError::invalid(format!("Invalid values {}: {}", values, e))),
+        None => Ok(None),
+    }
+}
+
+/// args[1] is an offset or a cursor, args[2] the limit, both can be empty
+fn legacy_page(args: &[String]) -> Result<PageArgs, LizError> {
+    let mut page = PageArgs::default();
+    if let Some(start) = args.get(1).map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        if start.contains(':') {
+            page.cursor = Some(start.to_string());
+        } else {
+            let offset = start
+                .parse()
+                .map_err(|e| LizError::invalid(format!("Invalid offset {}: {}", start, e)))?;
+            page.offset = Some(offset);
+        }
+    }
+    if let Some(limit) = args.get(2).map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let limit = limit
+            .parse()
+            .map_err(|e| LizError::invalid(format!("Invalid limit {}: {}", limit, e)))?;
+        page.limit = Some(limit);
+    }
+    Ok(page)
+}
+
+impl Command {
+    /// Parse a command of the old protocol
+    pub fn from_legacy(cmd: &LizCommand) -> Result<Self, LizError> {
+        let invalid_args = || LizError::invalid(format!("Invalid args: {:?}", cmd.args));
+        let query = || cmd.args.first().cloned().unwrap_or_default();
+        let command = match cmd.action.as_str() {
+            "get_shortcuts" => Command::GetShortcuts {
+                query: query(),
+                page: legacy_page(&cmd.args)?,
+            },
+            "get_shortcut_details" => Command::GetShortcutDetails {
+                query: query(),
+                page: legacy_page(&cmd.args)?,
+            },
+            "execute" => Command::Execute {
+                id: Id::parse(legacy_arg(cmd, 0, "one shortcut id")?)?,
+                values: legacy_values(&cmd.args)?,
+            },
+            "preview_execute" => Command::PreviewExecute {
+                id: Id::parse(legacy_arg(cmd, 0, "one shortcut id")?)?,
+            },
+            "persist" => Command::Persist,
+            "info" => Command::Info,
+            "new_id" => Command::NewId,
+            "create_shortcuts" => Command::CreateShortcuts {
+                shortcuts: legacy_shortcuts(&cmd.args)?,
+            },
+            "update_shortcuts" => Command::UpdateShortcuts {
+                shortcuts: legacy_shortcuts(&cmd.args)?,
+            },
+            "delete_shortcuts" => Command::DeleteShortcuts {
+                ids: legacy_ids(&cmd.args)?,
+            },
+            "get_deleted_shortcut_details" => Command::GetDeletedShortcutDetails,
+            "restore_shortcuts" => Command::RestoreShortcuts {
+                ids: legacy_ids(&cmd.args)?,
+            },
+            "get_revisions" => Command::GetRevisions {
+                id: Id::parse(legacy_arg(cmd, 0, "one shortcut id")?)?,
+            },
+            "revert_shortcut" => Command::RevertShortcut {
+                id: Id::parse(legacy_arg(cmd, 0, "one shortcut id")?)?,
+                revision: legacy_arg(cmd, 1, "the revision")?
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid_args())?,
+            },
+            "list_tags" => Command::ListTags,
+            "rename_tag" if cmd.args.len() == 2 => Command::RenameTag {
+                from: cmd.args[0].clone(),
+                to: cmd.args[1].clone(),
+            },
+            "merge_tags" if cmd.args.len() >= 2 => Command::MergeTags {
+                into: cmd.args[0].clone(),
+                tags: cmd.args[1..].to_vec(),
+            },
+            "rename_tag" | "merge_tags" => return Err(invalid_args()),
+            "undo" => Command::Undo,
+            "redo" => Command::Redo,
+            "purge_deleted" => Command::PurgeDeleted {
+                ids: legacy_ids(&cmd.args)?,
+            },
+            "purge_deleted_older_than" => {
+                let days = legacy_arg(cmd, 0, "the number of days")?;
+                Command::PurgeDeletedOlderThan {
+                    days: days.trim().parse().map_err(|e| {
+                        LizError::invalid(format!("Invalid number of days {}: {}", days, e))
+                    })?,
+                }
+            }
+            "export_shortcuts" => Command::ExportShortcuts {
+                path: legacy_arg(cmd, 0, "the file path")?.to_string(),
+                ids: legacy_ids(&cmd.args[1..])?,
+            },
+            "import_shortcuts" if !cmd.args.is_empty() => Command::ImportShortcuts {
+                paths: cmd.args.clone(),
+            },
+            "import_shortcuts" => {
+                return Err(LizError::invalid("Empty args, expect one file_path"))
+            }
+            "update_rhythm" => {
+                let settings = legacy_arg(cmd, 0, "the settings")?;
+                let rhythm = parse_rhythm(settings).map_err(|e| {
+                    LizError::invalid(format!(
+                        "Failed to parse rhythm: {}\nError: {}",
+                        settings, e
+                    ))
+                })?;
+                Command::UpdateRhythm {
+                    rhythm: Box::new(rhythm),
+                }
+            }
+            "list_backups" => Command::ListBackups,
+            "restore_backup" => Command::RestoreBackup {
+                name: legacy_arg(cmd, 0, "one backup name")?.to_string(),
+            },
+            _ => {
+                return Err(LizError::invalid(format!(
+                    "Invalid Liz Cmd: {}",
+                    cmd.action
+                )))
+            }
+        };
+        Ok(command)
+    }
+}
+
+impl Reply {
+    /// The results of the old protocol, `cmd` is the command they answer.
+    /// The page of the results is only the first result if the command asked for a page.
+    pub fn into_legacy(self, cmd: &LizCommand) -> BlueBirdResponse {
+        let paged = cmd.args.len() > 1;
+        let code = match self {
+            Reply::NeedsInput { .. } => StateCode::NEEDS_INPUT,
+            _ => StateCode::OK,
+        };
+        let results: Vec<String> = match self {
+            Reply::Done => Vec::new(),
+            Reply::Hits { page, hits } => paged
+                .then(|| to_json(&page))
+                .into_iter()
+                .chain(hits.iter().map(to_json))
+                .collect(),
+            Reply::Shortcuts { page, shortcuts } => paged
+                .then(|| to_json(&page))
+                .into_iter()
+                .chain(shortcuts.iter().map(|sc| sc.to_json_string()))
+                .collect(),
+            Reply::Deleted(deleted) => deleted.iter().map(to_json).collect(),
+            Reply::Revisions(revisions) => revisions.iter().map(to_json).collect(),
+            Reply::Id(id) => vec![id.to_string()],
+            Reply::Tags(tags) => tags.iter().map(to_json).collect(),
+            Reply::Count(count) => vec![count.to_string()],
+            Reply::Label(label) => vec![label],
+            Reply::Path(path) => vec![path],
+            Reply::Settings(settings) => settings.iter().map(|s| s.to_string()).collect(),
+            Reply::Backups(backups) => backups.iter().map(to_json).collect(),
+            Reply::Preview(preview) => vec![to_json(&preview)],
+            Reply::NeedsInput { placeholders, .. } => placeholders.iter().map(to_json).collect(),
+        };
+        BlueBirdResponse {
+            code,
+            results,
+        }
+    }
+}
+
+impl LizError {
+    /// The response of the old protocol, `cmd` is the command that failed.
+    /// The shortcuts not found are listed one per result, as JSON for update_shortcuts.
+    pub fn into_legacy(self, cmd: &LizCommand) -> BlueBirdResponse {
+        let results = match &self {
+            LizError::NotFound { ids } if cmd.action == "update_shortcuts" => {
+                legacy_shortcuts(&cmd.args)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|sc| ids.contains(&Id(sc.id)))
+                    .map(|sc| sc.to_json_string())
+                    .collect()
+            }
+            LizError::NotFound { ids } => ids.iter().map(|id| id.to_string()).collect(),
+            LizError::ImportFailed { paths } => paths.clone(),
+            _ => vec![self.to_string()],
+        };
+        BlueBirdResponse {
+            code: self.code(),
+            results,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flute::Flute;
+    use crate::tools::{
+        db::MusicSheetDB,
+        exec::PreviewEvent,
+        input::{BackendKind, InputEvent, OpenBackend, RecordingBackend},
+        utils::generate_id,
+    };
+    use serde_json::json;
+
+    fn legacy(action: &str, args: &[&str]) -> LizCommand {
+        LizCommand {
+            action: action.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    fn flute() -> Flute {
+        let mut music_sheet = MusicSheetDB::new();
+        music_sheet.add_shortcuts(
+            vec![
+                Shortcut {
+                    id: 1,
+                    application: "vim".to_string(),
+                    description: "Save file".to_string(),
+                    shortcut: "\":w\"".to_string(),
+                    ..Default::default()
+                },
+                Shortcut {
+                    id: 2,
+                    application: "vim".to_string(),
+                    description: "Quit".to_string(),
+                    shortcut: ":q".to_string(),
+                    ..Default::default()
+                },
+            ],
+            None,
+        );
+        Flute {
+            music_sheet,
+            rhythm: Rhythm::default(),
+            last_saved: None,
+            autosave_retry: None,
+            input: None,
+        }
+    }
+
+    #[test]
+    fn test_typed_commands() {
+        let cmd: Command = serde_json::from_value(json!({
+            "action": "get_shortcuts", "query": "app:vim", "limit": 1
+        }))
+        .unwrap();
+        let mut flute = flute();
+        let reply = serde_json::to_value(flute.run(cmd).unwrap()).unwrap();
+        assert_eq!(reply["type"], "hits");
+        assert_eq!(reply["data"]["page"]["total"], 2);
+        assert_eq!(reply["data"]["hits"].as_array().unwrap().len(), 1);
+
+        let undo = || serde_json::from_value::<Command>(json!({"action": "undo"})).unwrap();
+        let reply = serde_json::to_value(flute.run(undo()).unwrap()).unwrap();
+        assert_eq!(reply, json!({"type": "label", "data": "add"}));
+        assert_eq!(flute.run(undo()).unwrap_err(), LizError::NothingToUndo);
+
+        let missing = Id(3);
+        let err = flute
+            .run(Command::RevertShortcut {
+                id: missing,
+                revision: 1,
+            })
+            .unwrap_err();
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            json!({"kind": "not_found", "ids": [missing.to_string()]})
+        );
+
+        let err = flute
+            .run(Command::CreateShortcuts {
+                shortcuts: vec![Shortcut {
+                    id: 4,
+                    shortcut: "ctrl + c".to_string(),
+                    ..Default::default()
+                }],
+            })
+            .unwrap_err();
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            json!({
+                "kind": "invalid_shortcut", "id": Id(4).to_string(), "start": 5, "end": 6,
+                "message": "A chord is written without spaces, like ctrl+c"
+            })
+        );
+
+        assert!(
+            serde_json::from_value::<Command>(json!({"action": "execute", "id": "x"})).is_err()
+        );
+        assert!(serde_json::from_value::<Command>(json!({"action": "fly"})).is_err());
+    }
+
+    #[test]
+    fn test_repeat_limit() {
+        let mut flute = flute();
+        flute.rhythm.repeat_limit = 10;
+        flute.rhythm.input_backend = "mock".to_string();
+        let create = |flute: &mut Flute, id: u128, shortcut: &str| {
+            flute.run(Command::CreateShortcuts {
+                shortcuts: vec![Shortcut {
+                    id,
+                    shortcut: shortcut.to_string(),
+                    ..Default::default()
+                }],
+            })
+        };
+        let err = create(&mut flute, 5, "esc down*11").unwrap_err();
+        assert_eq!(
+            err,
+            LizError::InvalidShortcut {
+                id: Id(5),
+                start: 4,
+                end: 11,
+                message: "Runs more than 10 steps once repeated, see repeat_limit".to_string(),
+            }
+        );
+        assert!(create(&mut flute, 5, "esc down*9").is_ok());
+        let Reply::Preview(preview) = flute.run(Command::PreviewExecute { id: Id(5) }).unwrap()
+        else {
+            panic!("Expect a preview");
+        };
+        let presses = preview
+            .events
+            .iter()
+            .filter(|e| matches!(e, PreviewEvent::Press { .. }));
+        assert_eq!(presses.count(), 10);
+
+        // A lower limit applies to the shortcuts already there
+        flute.rhythm.repeat_limit = 5;
+        let execute = Command::Execute {
+            id: Id(5),
+            values: None,
+        };
+        assert!(matches!(
+            flute.run(execute).unwrap_err(),
+            LizError::InvalidShortcut { .. }
+        ));
+
+        let path = std::env::temp_dir().join(format!("liz_test_{}.json", generate_id()));
+        std::fs::write(&path, r#"[{"shortcut": "(tab)*6"}]"#).unwrap();
+        let path = path.to_string_lossy().to_string();
+        let err = flute
+            .run(Command::ImportShortcuts {
+                paths: vec![path.clone()],
+            })
+            .unwrap_err();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(err, LizError::ImportFailed { paths: vec![path] });
+    }
+
+    #[test]
+    fn test_execute_placeholders() {
+        let mut flute = flute();
+        flute.rhythm.input_backend = "mock".to_string();
+        let id = Id(5);
+        flute
+            .run(Command::CreateShortcuts {
+                shortcuts: vec![Shortcut {
+                    id: id.0,
+                    shortcut: "\"{{greeting:Hi}} {{name}}\" enter".to_string(),
+                    placeholders: vec![Placeholder {
+                        name: "name".to_string(),
+                        default: None,
+                        hint: "Who to greet".to_string(),
+                    }],
+                    ..Default::default()
+                }],
+            })
+            .unwrap();
+        // Without values, all the placeholders are asked, even the ones with a default
+        let reply = serde_json::to_value(flute.run(Command::Execute { id, values: None }).unwrap())
+            .unwrap();
+        assert_eq!(
+            reply,
+            json!({"type": "needs_input", "data": {"id": id.to_string(), "placeholders": [
+                {"name": "greeting", "default": "Hi"},
+                {"name": "name", "hint": "Who to greet"},
+            ]}})
+        );
+        let execute = |values: &[(&str, &str)]| Command::Execute {
+            id,
+            values: Some(
+                values
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ),
+        };
+        let reply = flute.run(execute(&[("greeting", "Hello")])).unwrap();
+        assert!(matches!(reply, Reply::NeedsInput { .. }));
+        // The recorder stands for the backend opened by the first execution
+        let recorder = RecordingBackend::default();
+        flute.input = Some(OpenBackend {
+            kind: BackendKind::Mock,
+            backend: Box::new(recorder.clone()),
+        });
+        let reply = flute.run(execute(&[("name", "Liz")])).unwrap();
+        assert!(matches!(reply, Reply::Done));
+        let hit_number = flute.music_sheet.retrieve(id.0, None).unwrap().hit_number;
+        assert_eq!(hit_number, 1);
+
+        let args = [id.to_string(), r#"{"name": "Liz"}"#.to_string()];
+        let resp = flute.play(&legacy("execute", &[&args[0]]));
+        assert_eq!(resp.code, StateCode::NEEDS_INPUT);
+        assert_eq!(resp.results.len(), 2);
+        let resp = flute.play(&legacy("execute", &[&args[0], &args[1]]));
+        assert_eq!((resp.code, resp.results.len()), (StateCode::OK, 0));
+        // The backend is kept open for the next executions
+        let texts: Vec<InputEvent> = recorder
+            .events()
+            .into_iter()
+            .filter(|e| matches!(e, InputEvent::Text(_)))
+            .collect();
+        assert_eq!(texts, vec![InputEvent::Text("Hi Liz".to_string()); 2]);
+        let resp = flute.play(&legacy("execute", &[&args[0], "[1]"]));
+        assert_eq!(resp.code, StateCode::BUG);
+    }
+
+    #[test]
+    fn test_legacy_protocol() {
+        let mut flute = flute();
+        let resp = flute.play(&legacy("get_shortcuts", &["quit"]));
+        assert_eq!(resp.code, StateCode::OK);
+        assert_eq!(resp.results.len(), 1);
+        let hit: Value = serde_json::from_str(&resp.results[0]).unwrap();
+        assert_eq!(hit["id"], Id(2).to_string());
+
+        // The page comes first only if it is asked
+        let resp = flute.play(&legacy("get_shortcut_details", &["", "", "1"]));
+        let page: Value = serde_json::from_str(&resp.results[0]).unwrap();
+        assert_eq!((page["total"].as_u64(), resp.results.len()), (Some(2), 2));
+
+        let resp = flute.play(&legacy("get_shortcuts", &["hits:>x"]));
+        assert_eq!(resp.code, StateCode::FAIL);
+        assert!(resp.results[0].starts_with("Invalid query"));
+
+        let unknown = Shortcut {
+            id: 9,
+            ..Default::default()
+        };
+        let resp = flute.play(&legacy("update_shortcuts", &[&unknown.to_json_string()]));
+        assert_eq!(resp.code, StateCode::FAIL);
+        assert_eq!(resp.results, vec![unknown.to_json_string()]);
+
+        let resp = flute.play(&legacy("rename_tag", &["git"]));
+        assert_eq!(resp.code, StateCode::BUG);
+        let resp = flute.play(&legacy("execute", &[]));
+        assert_eq!(resp.results, vec!["Empty args, expect one shortcut id"]);
+        let resp = flute.play(&legacy("fly", &[]));
+        assert_eq!(resp.results, vec!["Invalid Liz Cmd: fly"]);
+
+        let resp = flute.play(&legacy("preview_execute", &[&Id(1).to_string()]));
+        let preview: Value = serde_json::from_str(&resp.results[0]).unwrap();
+        assert_eq!(preview["keycode"], "[STR]+ :w[STR]");
+        assert_eq!(
+            preview["events"].as_array().unwrap().last(),
+            Some(&json!({"type": "type", "text": ":w"}))
+        );
+        assert_eq!(preview["warnings"], json!([]));
+
+        let resp = flute.play(&legacy("purge_deleted_older_than", &["30"]));
+        assert_eq!(
+            (resp.code, resp.results),
+            (StateCode::OK, vec!["0".to_string()])
+        );
+    }
+}
@@ -6,10 +6,17 @@ use tauri::{
 
 use crate::{
     flute::Flute,
-    tools::{db::MusicSheetDB, rhythm::Rhythm, trans::TranslationCache},
+    ipc,
+    tools::{
+        db::MusicSheetDB,
+        rhythm::Rhythm,
+        storage::{JsonStorage, SheetStorage, SqliteStorage},
+        trans::TranslationCache,
+    },
 };
 use std::io;
-use std::{fs::DirBuilder, path::PathBuf, sync::Mutex};
+use std::time::Duration;
+use std::{fs::DirBuilder, path::PathBuf, sync::Arc, sync::Mutex};
 
 /// Setup the tray, including its configuration and Menu.
 pub fn setup_tray(app: &tauri::App, transcache: &TranslationCache) -> Result<(), Box<dyn std::error::Error>> {
@@ -78,7 +85,7 @@ fn handle_menu_events(app: &AppHandle, event: &MenuEvent) {
         "persist" => {
             println!("Persist data into music_sheet.lock");
             match app.state::<Mutex<Flute>>().lock() {
-                Ok(flute) => {
+                Ok(mut flute) => {
                     if let Err(e) = flute.persist() {
                         eprintln!("Failed to persist: {}", e);
                     }
@@ -114,6 +121,26 @@ fn handle_menu_events(app: &AppHandle, event: &MenuEvent) {
     }
 }
 
+/// The query of `liz --search` waiting for the launcher page, which takes it when it is
+/// loaded or told by the "search" event. The window is often created for it, and an event
+/// sent before the page listens would be lost.
+#[derive(Default)]
+pub struct PendingSearch(pub Mutex<Option<String>>);
+
+/// Show the launcher, with the query in its search bar if given
+pub fn show_launcher(app: &AppHandle, query: Option<&str>) {
+    if let Some(query) = query {
+        match app.state::<PendingSearch>().0.lock() {
+            Ok(mut pending) => *pending = Some(query.to_string()),
+            Err(e) => eprintln!("Failed to lock the pending search because: {}", e),
+        }
+        if let Err(e) = app.emit_to("main", "search", ()) {
+            eprintln!("Failed to send the query to the launcher: {}", e);
+        }
+    }
+    create_or_open_main_window(app);
+}
+
 fn create_or_open_main_window(app: &AppHandle) {
     if let Some(win) = app.get_webview_window("main") {
         if let Err(e) = win.show() {
@@ -167,14 +194,29 @@ fn create_liz_folder(liz_path: &str) -> io::Result<()> {
     if !liz_folder.exists() {
         // Create the 'liz' folder if it does not exist
         DirBuilder::new().recursive(true).create(&liz_folder)?;
-        println!("Created 'liz' folder at {:?}", liz_folder);
+        eprintln!("Created 'liz' folder at {:?}", liz_folder);
     } else {
-        println!("'liz' folder already exists at {:?}", liz_folder);
+        eprintln!("'liz' folder already exists at {:?}", liz_folder);
     }
 
     Ok(())
 }
 
+/// Open the storage of the music sheet according to `storage_backend`.
+/// The SQLite backend migrates the json lock file when the database is created.
+fn open_storage(rhythm: &Rhythm) -> Result<Box<dyn SheetStorage>, Box<dyn std::error::Error>> {
+    match rhythm.storage_backend.as_str() {
+        "json" => Ok(Box::new(JsonStorage::new(&rhythm.music_sheet_path))),
+        "sqlite" => Ok(Box::new(SqliteStorage::open(
+            &rhythm.music_sheet_db_path,
+            Some(&rhythm.music_sheet_path),
+        )?)),
+        other => Err(format!("Unknown storage backend: {}", other).into()),
+    }
+}
+
+/// Create the flute of the app. A music sheet that cannot be opened or loaded (e.g. it was
+/// written by a newer version) is an error, rather than an empty one hiding the shortcuts.
 pub fn create_flute(rhythm_path: Option<String>) -> Result<Flute, Box<dyn std::error::Error>> {
     let rhythm: Rhythm = Rhythm::read_rhythm(rhythm_path)?;
 
@@ -183,17 +225,68 @@ pub fn create_flute(rhythm_path: Option<String>) -> Result<Flute, Box<dyn std::e
         std::process::exit(1);
     }
 
-    let music_sheet_path = &rhythm.music_sheet_path;
+    let music_sheet = load_music_sheet(&rhythm)?;
+    Ok(tune_flute(rhythm, music_sheet))
+}
+
+/// Create a flute on the stored music sheet for the command line, a storage that cannot be
+/// opened or loaded is an error
+pub fn open_flute(rhythm_path: Option<String>) -> Result<Flute, Box<dyn std::error::Error>> {
+    let rhythm: Rhythm = Rhythm::read_rhythm(rhythm_path)?;
+    create_liz_folder(&rhythm.liz_path)?;
+    let music_sheet = load_music_sheet(&rhythm)?;
+    Ok(tune_flute(rhythm, music_sheet))
+}
+
+/// Open the storage of the music sheet and load it
+fn load_music_sheet(rhythm: &Rhythm) -> Result<MusicSheetDB, Box<dyn std::error::Error>> {
+    let storage = open_storage(rhythm)
+        .map_err(|e| format!("Failed to open music sheet storage: {}", e))?;
+    Ok(MusicSheetDB::with_storage(storage)
+        .map_err(|e| format!("Failed to load music sheet: {}", e))?)
+}
+
+fn tune_flute(rhythm: Rhythm, music_sheet: MusicSheetDB) -> Flute {
     let mut flute: Flute = Flute {
-        music_sheet: MusicSheetDB::import_from_json(music_sheet_path).unwrap_or_else(|_| {
-            eprintln!("Failed to load music sheet from {}", music_sheet_path);
-            MusicSheetDB::new() // Return a default instance if loading fails
-        }),
+        music_sheet,
         rhythm: rhythm,
+        last_saved: None,
+        autosave_retry: None,
+        input: None,
     };
+    flute.music_sheet.set_undo_depth(flute.rhythm.undo_depth);
+    flute.music_sheet.set_pinyin_search(flute.rhythm.pinyin_search);
     flute.calibrate();
     flute.music_sheet.read_keymap(&flute.rhythm.keymap_path);
-    Ok(flute)
+    flute
+}
+
+/// Start the thread saving the changes of the music sheet in the background,
+/// see `Flute::autosave`.
+pub fn setup_autosave(app: &tauri::App) {
+    let app_handle: AppHandle = app.handle().clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_millis(500));
+        match app_handle.state::<Mutex<Flute>>().lock() {
+            Ok(mut flute) => flute.autosave(),
+            Err(e) => {
+                eprintln!("Autosave: Failed to lock Flute because: {}", e);
+            }
+        }
+    });
+}
+
+/// Serve the commands to external launchers on the socket, see `ipc_enabled` in Rhythm
+pub fn setup_ipc(app: &tauri::App, path: String) {
+    let app_handle: AppHandle = app.handle().clone();
+    let handler: ipc::Handler = Arc::new(move |cmd| crate::run_typed(cmd, &app_handle));
+    match ipc::start(&path, handler) {
+        Ok(()) => {
+            println!("Listening to launchers on {}", path);
+            let _ = app.manage(ipc::Socket(path));
+        }
+        Err(e) => eprintln!("Failed to listen to launchers on {}: {}", path, e),
+    }
 }
 
 pub fn register_trigger_shortcut(
@@ -0,0 +1,134 @@
+use chrono::{Local, NaiveDateTime};
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use super::db::MusicSheetDBTable;
+use super::utils::atomic_write;
+
+const BACKUP_PREFIX: &str = "music_sheet-";
+const BACKUP_EXT: &str = ".json";
+const BACKUP_TIME_FMT: &str = "%Y%m%d-%H%M%S%.3f";
+
+/// One backup of the music sheet living in the backup dir
+#[derive(Debug, Serialize)]
+pub struct BackupInfo {
+    pub name: String,    // File name, used to restore it
+    pub created: String, // When the backup was created, in local time
+    pub size: u64,       // Size in bytes
+    pub shortcuts: Option<usize>, // Number of shortcuts, None if the file cannot be read
+}
+
+/// Parse the creation time out of a backup file name, None if it is not a backup
+fn backup_time(name: &str) -> Option<NaiveDateTime> {
+    let ts = name.strip_prefix(BACKUP_PREFIX)?.strip_suffix(BACKUP_EXT)?;
+    NaiveDateTime::parse_from_str(ts, BACKUP_TIME_FMT).ok()
+}
+
+/// Names of all the backups in the dir, the newest first
+fn backup_names(dir: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    if !Path::new(dir).is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut names: Vec<String> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if backup_time(&name).is_some() {
+            names.push(name);
+        }
+    }
+    // The timestamp format sorts the same way as the time
+    names.sort_by(|a, b| b.cmp(a));
+    Ok(names)
+}
+
+/// Write a timestamped snapshot of the table into the backup dir,
+/// and only keep the newest `keep` backups. Returns the name of the new backup.
+pub fn create_backup(
+    t: &MusicSheetDBTable,
+    dir: &str,
+    keep: usize,
+) -> Result<String, Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let name = format!(
+        "{}{}{}",
+        BACKUP_PREFIX,
+        Local::now().format(BACKUP_TIME_FMT),
+        BACKUP_EXT
+    );
+    let content = serde_json::to_vec(t)?;
+    atomic_write(&Path::new(dir).join(&name), &content)?;
+
+    for old in backup_names(dir)?.iter().skip(keep) {
+        if let Err(e) = fs::remove_file(Path::new(dir).join(old)) {
+            eprintln!("Failed to remove old backup {}: {}", old, e);
+        }
+    }
+    Ok(name)
+}
+
+/// List the backups in the dir, the newest first
+pub fn list_backups(dir: &str) -> Result<Vec<BackupInfo>, Box<dyn Error>> {
+    let mut backups: Vec<BackupInfo> = Vec::new();
+    for name in backup_names(dir)? {
+        let path = Path::new(dir).join(&name);
+        let created = backup_time(&name)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        backups.push(BackupInfo {
+            size: fs::metadata(&path)?.len(),
+            shortcuts: read_table(&path).ok().map(|t| t.data.len()),
+            name,
+            created,
+        });
+    }
+    Ok(backups)
+}
+
+/// Read the backup of the given name from the dir.
+/// Only names of existing backups are accepted, so it cannot read arbitrary files.
+pub fn read_backup(dir: &str, name: &str) -> Result<MusicSheetDBTable, Box<dyn Error>> {
+    if !backup_names(dir)?.iter().any(|n| n == name) {
+        return Err(format!("Backup {} does not exist", name).into());
+    }
+    read_table(&Path::new(dir).join(name))
+}
+
+fn read_table(path: &Path) -> Result<MusicSheetDBTable, Box<dyn Error>> {
+    let content = fs::read(path)?;
+    let (t, _) = MusicSheetDBTable::from_json_slice(&content)?;
+    Ok(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::db::Shortcut;
+    use crate::tools::utils::generate_id;
+
+    #[test]
+    fn test_rolling_backups() {
+        let dir = std::env::temp_dir().join(format!("liz_test_{}", generate_id()));
+        let dir = dir.to_str().unwrap();
+
+        let mut t = MusicSheetDBTable::new();
+        let mut names: Vec<String> = Vec::new();
+        for _ in 0..4 {
+            t.data.push(Shortcut::default());
+            names.push(create_backup(&t, dir, 2).unwrap());
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        let backups = list_backups(dir).unwrap();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0].name, names[3]);
+        assert_eq!(backups[0].shortcuts, Some(4));
+        assert_eq!(backups[1].name, names[2]);
+
+        assert_eq!(read_backup(dir, &names[2]).unwrap().data.len(), 3);
+        assert!(read_backup(dir, &names[0]).is_err());
+        assert!(read_backup(dir, "../rhythm.toml").is_err());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
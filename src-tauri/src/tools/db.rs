@@ -1,11 +1,27 @@
 use serde::de;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::cell::{Ref, RefCell};
+use std::cmp::Reverse;
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs::{self, File, OpenOptions};
+use std::fs::{self, File};
 use std::io::Read;
+use std::path::Path;
+use std::time::Instant;
 
-use super::utils::{generate_id, id_to_string, string_to_id};
+use super::backup::{create_backup, read_backup};
+use super::history::{ChangeSet, History};
+use super::index::{IdMap, SearchIndex};
+use super::matcher::{
+    fuzzy_match, highlight, is_subsequence, max_score, pinyin_match, to_ranges, FieldMatch,
+};
+use super::query::{searched_fields, Query};
+use super::ranking::{rank, record_hit, RankMode};
+use super::sequence::{Placeholder, Sequence};
+use super::storage::SheetStorage;
+use super::utils::{atomic_write, generate_id, id_to_string, now_timestamp, string_to_id};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(default)]
@@ -18,6 +34,10 @@ pub struct Shortcut {
     pub application: String, // Application using this shortcut
     pub description: String, // Shortcut description, shall not be too long
     pub comment: String,     // Extra info or explanation for the shortcut
+    pub tags: Vec<String>,   // Workflow tags like "git" or "review", across applications
+    pub last_used: i64,      // When the shortcut was hit the last time, unix timestamp in seconds
+    pub frecency: f64,       // Hits decayed to last_used, see tools/ranking.rs
+    pub placeholders: Vec<Placeholder>, // Hints and defaults of the {{name}} in the shortcut
 }
 
 fn serialize_id<S>(id: &u128, serializer: S) -> Result<S::Ok, S::Error>
@@ -39,13 +59,70 @@ where
 }
 
 impl Shortcut {
-    // Example method to update shortcut values
+    // Example method to update shortcut values.
+    // last_used and frecency are kept, they are only changed by hits.
     pub fn update(&mut self, new_sc: &Shortcut) {
         self.hit_number = new_sc.hit_number;
         self.shortcut = new_sc.shortcut.clone();
         self.application = new_sc.application.clone();
         self.description = new_sc.description.clone();
         self.comment = new_sc.comment.clone();
+        self.tags = new_sc.tags.clone();
+        self.placeholders = new_sc.placeholders.clone();
+    }
+
+    /// Take the usage of another version of the shortcut: hit_number, last_used and frecency
+    pub fn take_usage(&mut self, other: &Shortcut) {
+        self.hit_number = other.hit_number;
+        self.last_used = other.last_used;
+        self.frecency = other.frecency;
+    }
+
+    /// Tags joined by ", ", used for display and comparison
+    pub fn tags_string(&self) -> String {
+        self.tags.join(", ")
+    }
+
+    /// Replace the tags in `from` by `to`, keeping each tag only once.
+    /// Returns whether any tag was replaced.
+    pub fn rename_tags(&mut self, from: &[String], to: &str) -> bool {
+        if !self.tags.iter().any(|t| from.contains(t)) {
+            return false;
+        }
+        let mut tags: Vec<String> = Vec::new();
+        for tag in &self.tags {
+            let tag = if from.contains(tag) { to } else { tag };
+            if !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+        self.tags = tags;
+        true
+    }
+}
+
+impl Shortcut {
+    /// The fields that differ from the other version of the shortcut, id is not compared
+    pub fn diff(&self, other: &Shortcut) -> Vec<FieldDiff> {
+        let placeholders = |sc: &Shortcut| serde_json::to_string(&sc.placeholders).unwrap();
+        let fields: [(&str, String, String); 7] = [
+            ("shortcut", self.shortcut.clone(), other.shortcut.clone()),
+            ("application", self.application.clone(), other.application.clone()),
+            ("description", self.description.clone(), other.description.clone()),
+            ("comment", self.comment.clone(), other.comment.clone()),
+            ("tags", self.tags_string(), other.tags_string()),
+            ("placeholders", placeholders(self), placeholders(other)),
+            ("hit_number", self.hit_number.to_string(), other.hit_number.to_string()),
+        ];
+        fields
+            .into_iter()
+            .filter(|(_, old, new)| old != new)
+            .map(|(field, old, new)| FieldDiff {
+                field: field.to_string(),
+                old,
+                new,
+            })
+            .collect()
     }
 }
 
@@ -58,6 +135,10 @@ impl Default for Shortcut {
             application: "None".to_string(),
             description: "None".to_string(),
             comment: "".to_string(),
+            tags: Vec::new(),
+            last_used: 0,
+            frecency: 0.0,
+            placeholders: Vec::new(),
         }
     }
 }
@@ -74,15 +155,27 @@ impl Shortcut {
     }
 
     pub fn format_output(&self, fmt: &str) -> String {
+        self.format_highlighted(fmt, &[])
+    }
+
+    /// Format the shortcut, and wrap the matched chars of each field in <mark>
+    pub fn format_highlighted(&self, fmt: &str, matches: &[FieldMatch]) -> String {
+        let field = |name: &str, text: &str| match matches.iter().find(|m| m.field == name) {
+            Some(m) => highlight(text, &m.ranges, "<mark>", "</mark>"),
+            None => text.to_string(),
+        };
         let mut formatted_str: String = fmt.to_string();
 
         // Replace all possible attributes
         formatted_str = formatted_str.replace("#id", &self.id.to_string());
         formatted_str = formatted_str.replace("#hit_number", &self.hit_number.to_string());
-        formatted_str = formatted_str.replace("#shortcut", &self.shortcut);
-        formatted_str = formatted_str.replace("#application", &self.application);
-        formatted_str = formatted_str.replace("#description", &self.description);
+        formatted_str = formatted_str.replace("#shortcut", &field("shortcut", &self.shortcut));
+        formatted_str =
+            formatted_str.replace("#application", &field("application", &self.application));
+        formatted_str =
+            formatted_str.replace("#description", &field("description", &self.description));
         formatted_str = formatted_str.replace("#comment", &self.comment);
+        formatted_str = formatted_str.replace("#tags", &field("tags", &self.tags_string()));
 
         formatted_str
     }
@@ -100,6 +193,7 @@ impl Shortcut {
                 shortcut.application.clone(),
                 shortcut.description.clone(),
                 shortcut.comment.clone(),
+                shortcut.tags.clone(),
             );
 
             // If the unique key AND id is not already in the set, add it to the result
@@ -114,43 +208,331 @@ impl Shortcut {
     }
 }
 
+/// Why a shortcut ended up in deleted
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DeleteReason {
+    #[default]
+    Delete, // Deleted by the user
+    Edit,   // The old version of an updated shortcut, only written by older versions of Liz
+}
+
+impl DeleteReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeleteReason::Delete => "delete",
+            DeleteReason::Edit => "edit",
+        }
+    }
+
+    /// Parse the stored string, unknown values are treated as "delete"
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "edit" => DeleteReason::Edit,
+            _ => DeleteReason::Delete,
+        }
+    }
+}
+
+/// A shortcut in the trash, with when and why it was deleted.
+/// Entries written before these fields existed get deleted_at 0 and reason "delete".
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeletedShortcut {
+    #[serde(flatten)]
+    pub shortcut: Shortcut,
+    #[serde(default)]
+    pub deleted_at: i64, // Unix timestamp in seconds
+    #[serde(default)]
+    pub reason: DeleteReason,
+}
+
+impl DeletedShortcut {
+    pub fn new(shortcut: Shortcut, reason: DeleteReason) -> Self {
+        Self {
+            shortcut,
+            deleted_at: now_timestamp(),
+            reason,
+        }
+    }
+}
+
+/// One field that differs between two versions of a shortcut
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// One old version of a shortcut, recorded when the shortcut is updated
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Revision {
+    pub revision: u64,         // Numbered from 1 for each shortcut
+    pub timestamp: i64,        // When it was replaced, unix timestamp in seconds
+    pub shortcut: Shortcut,    // The shortcut as it was before the update
+    pub diffs: Vec<FieldDiff>, // What the update changed
+}
+
+/// The version of the persisted table written by this build.
+/// Bump it together with a new step in `MIGRATIONS` whenever the format changes.
+pub const SCHEMA_VERSION: u32 = 6;
+
+/// Files written before the version marker existed
+const LEGACY_SCHEMA_VERSION: u32 = 1;
+
+/// One step of migration, it edits the raw JSON of the table in place
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// `MIGRATIONS[i]` upgrades a table of version `i + 1` to version `i + 2`
+const MIGRATIONS: [Migration; (SCHEMA_VERSION - 1) as usize] = [
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+    migrate_v5_to_v6,
+];
+
 #[derive(Debug, Serialize, Deserialize)]
-struct MusicSheetDBTable {
-    deleted: Vec<Shortcut>,
-    data: Vec<Shortcut>,
+pub struct MusicSheetDBTable {
+    #[serde(default)]
+    pub schema_version: u32,
+    pub deleted: Vec<DeletedShortcut>,
+    pub data: Vec<Shortcut>,
+    #[serde(default)]
+    pub revisions: Vec<Revision>,
 }
 
 impl MusicSheetDBTable {
     pub fn new() -> Self {
         Self {
+            schema_version: SCHEMA_VERSION,
             deleted: Vec::new(),
             data: Vec::new(),
+            revisions: Vec::new(),
+        }
+    }
+
+    /// Parse a persisted table, upgrading it step by step if it was written by an older version.
+    /// Returns the table and the version it was written in.
+    /// Tables written by a newer version are refused, since fields could be lost silently.
+    pub fn from_json_slice(content: &[u8]) -> Result<(Self, u32), Box<dyn Error>> {
+        let mut value: Value = serde_json::from_slice(content)?;
+        let version = match value.get("schema_version") {
+            None => LEGACY_SCHEMA_VERSION,
+            Some(v) => v
+                .as_u64()
+                .map(|v| v as u32)
+                .ok_or(format!("Invalid schema_version: {}", v))?,
+        };
+        if version > SCHEMA_VERSION {
+            return Err(format!(
+                "The music sheet schema version {} is newer than supported ({}), please upgrade Liz",
+                version, SCHEMA_VERSION
+            )
+            .into());
+        }
+        for (i, migrate) in MIGRATIONS
+            .iter()
+            .enumerate()
+            .skip(version.max(LEGACY_SCHEMA_VERSION) as usize - 1)
+        {
+            migrate(&mut value)
+                .map_err(|e| format!("Failed to migrate from version {}: {}", i + 1, e))?;
+        }
+        value["schema_version"] = Value::from(SCHEMA_VERSION);
+        Ok((serde_json::from_value(value)?, version))
+    }
+}
+
+/// Deleted entries get the time they were deleted and why
+fn migrate_v1_to_v2(value: &mut Value) -> Result<(), String> {
+    let deleted = value
+        .get_mut("deleted")
+        .and_then(|d| d.as_array_mut())
+        .ok_or("deleted is not a list")?;
+    for entry in deleted {
+        let entry = entry.as_object_mut().ok_or("deleted entry is not an object")?;
+        entry.entry("deleted_at").or_insert(Value::from(0));
+        entry.entry("reason").or_insert(Value::from("delete"));
+    }
+    Ok(())
+}
+
+/// Old versions of updated shortcuts are kept in revisions
+fn migrate_v2_to_v3(value: &mut Value) -> Result<(), String> {
+    let table = value.as_object_mut().ok_or("the table is not an object")?;
+    table.entry("revisions").or_insert(Value::Array(Vec::new()));
+    Ok(())
+}
+
+/// Add a field with the default value to every shortcut in data, deleted and revisions
+fn add_shortcut_field(value: &mut Value, field: &str, default: Value) -> Result<(), String> {
+    for_each_shortcut(value, |shortcut| {
+        shortcut.entry(field).or_insert(default.clone());
+    })
+}
+
+/// Run `f` on every shortcut in data, deleted and revisions
+fn for_each_shortcut(
+    value: &mut Value,
+    mut f: impl FnMut(&mut serde_json::Map<String, Value>),
+) -> Result<(), String> {
+    let table = value.as_object_mut().ok_or("the table is not an object")?;
+    for (list, entries) in table.iter_mut() {
+        let Some(entries) = entries.as_array_mut() else {
+            continue;
+        };
+        for entry in entries {
+            let shortcut = match list.as_str() {
+                "data" | "deleted" => entry,
+                "revisions" => entry.get_mut("shortcut").ok_or("revision without shortcut")?,
+                _ => continue,
+            };
+            f(shortcut.as_object_mut().ok_or("shortcut is not an object")?);
         }
     }
+    Ok(())
+}
+
+/// Shortcuts get tags, stored as a list
+fn migrate_v3_to_v4(value: &mut Value) -> Result<(), String> {
+    add_shortcut_field(value, "tags", Value::Array(Vec::new()))
+}
+
+/// Shortcuts record when they were last hit and their frecency.
+/// The frecency starts from hit_number as of now, so the favourites keep their rank.
+fn migrate_v4_to_v5(value: &mut Value) -> Result<(), String> {
+    let now = now_timestamp();
+    for_each_shortcut(value, |shortcut| {
+        let hits = shortcut.get("hit_number").and_then(Value::as_f64).unwrap_or(0.0);
+        shortcut.entry("last_used").or_insert(Value::from(now));
+        shortcut.entry("frecency").or_insert(Value::from(hits));
+    })
+}
+
+/// Shortcuts keep the hints and defaults of their placeholders, stored as a list
+fn migrate_v5_to_v6(value: &mut Value) -> Result<(), String> {
+    add_shortcut_field(value, "placeholders", Value::Array(Vec::new()))
+}
+
+/// How much the usage rank counts in search results, against the match quality.
+/// Between the first and the last shortcut, it is worth about one matched char.
+const SEARCH_RANK_WEIGHT: f64 = 16.0;
+
+/// The fields also matched by pinyin when `pinyin_search` is on, the comment only by pinyin
+const PINYIN_FIELDS: [&str; 3] = ["description", "application", "comment"];
+
+/// Whether the fields matched by pinyin have no Chinese char
+fn is_ascii(sc: &Shortcut) -> bool {
+    sc.description.is_ascii() && sc.application.is_ascii() && sc.comment.is_ascii()
+}
+
+/// A shortcut found by `MusicSheetDB::search`
+#[derive(Debug)]
+pub struct SearchHit<'a> {
+    pub shortcut: &'a Shortcut,
+    pub score: f64,               // Match quality blended with the usage rank
+    pub matches: Vec<FieldMatch>, // The matched chars of each field
 }
 
 #[derive(Debug)]
 pub struct MusicSheetDB {
     t: MusicSheetDBTable,
     pub keymap: HashMap<String, String>,
+    storage: Option<Box<dyn SheetStorage>>,
+    dirty_since: Option<Instant>, // The first change not persisted yet
+    last_change: Option<Instant>, // The latest change not persisted yet
+    history: History,             // Changes that can be undone or redone
+    pinyin_search: bool,          // Match the Chinese chars by their pinyin too
+    index: SearchIndex,           // The chars of data, to skip the shortcuts that cannot match
+    generation: u64,              // Bumped by every change or reordering of data
+    positions: RefCell<Option<(u64, IdMap<usize>)>>, // See `positions`, with its generation
 }
 
 impl MusicSheetDB {
+    fn mark_dirty(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        let now = Instant::now();
+        self.dirty_since.get_or_insert(now);
+        self.last_change = Some(now);
+    }
+
+    /// When the first and the latest changes since the last persist happened, None if nothing changed
+    pub fn dirty_times(&self) -> Option<(Instant, Instant)> {
+        self.dirty_since.zip(self.last_change)
+    }
+
+    /// Changes whenever data changes or is reordered, so the results of a search
+    /// can tell whether they are still in the same order
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The index in data of every shortcut, built again once data changed or was reordered
+    fn positions(&self) -> Ref<'_, IdMap<usize>> {
+        let stale = !matches!(&*self.positions.borrow(), Some((g, _)) if *g == self.generation);
+        if stale {
+            let data = self.t.data.iter().enumerate();
+            let positions = data.map(|(i, sc)| (sc.id, i)).collect();
+            *self.positions.borrow_mut() = Some((self.generation, positions));
+        }
+        Ref::map(self.positions.borrow(), |p| &p.as_ref().unwrap().1)
+    }
+
+    /// Forward one change to the storage if there is one.
+    /// Errors are only reported, the in-memory table keeps the change anyway.
+    fn store<F>(&mut self, op: F)
+    where
+        F: FnOnce(&mut dyn SheetStorage) -> Result<(), Box<dyn Error>>,
+    {
+        self.mark_dirty();
+        if let Some(storage) = self.storage.as_deref_mut() {
+            if let Err(e) = op(storage) {
+                eprintln!("Failed to write the change to storage: {}", e);
+            }
+        }
+    }
+
     /**
      * Add a list of Shortcuts into the data.
      * safe_check (default true) to remove duplicate shortcuts, which means the content or the id is the same.
      */
     pub fn add_shortcuts(&mut self, shortcuts: Vec<Shortcut>, safe_check: Option<bool>) {
+        let existing_ids: HashSet<u128> = self.t.data.iter().map(|sc| sc.id).collect();
         self.t.data.extend(shortcuts);
         let safe_check = safe_check.unwrap_or(true);
         if safe_check {
             self.remove_data_duplicates();
         }
+        // Only the new shortcuts surviving the duplicate check are stored
+        let added: Vec<Shortcut> = self
+            .t
+            .data
+            .iter()
+            .filter(|sc| !existing_ids.contains(&sc.id))
+            .cloned()
+            .collect();
+        let mut change_set = ChangeSet::new("add");
+        for sc in added.iter() {
+            self.index.insert(sc);
+            change_set.push(sc.id, None, Some(sc.clone()));
+        }
+        self.history.record(change_set);
+        self.store(|s| s.put_shortcuts(&added));
     }
 
     // Remove duplicates in shortcuts by considering all attributes except hit_number, or the id is the same
     pub fn remove_data_duplicates(&mut self) {
-        self.t.data = Shortcut::remove_duplicates(&self.t.data);
+        let kept = Shortcut::remove_duplicates(&self.t.data);
+        if kept.len() != self.t.data.len() {
+            // A duplicate of the same id leaves the kept one indexed
+            let kept_ids: HashSet<u128> = kept.iter().map(|sc| sc.id).collect();
+            for sc in self.t.data.iter().filter(|sc| !kept_ids.contains(&sc.id)) {
+                self.index.remove(sc.id);
+            }
+            self.generation = self.generation.wrapping_add(1);
+        }
+        self.t.data = kept;
     }
 
     /// Retrieves one shortcut by its id from either "data" or "deleted" list, default mode to be "data"
@@ -158,32 +540,169 @@ impl MusicSheetDB {
         let mode = mode.unwrap_or("data"); // Default to "data" if mode is None
         match mode {
             "data" => self.t.data.iter().find(|&shortcut| shortcut.id == id),
-            "deleted" => self.t.deleted.iter().find(|&shortcut| shortcut.id == id),
+            "deleted" => self
+                .t
+                .deleted
+                .iter()
+                .rev()
+                .find(|d| d.shortcut.id == id)
+                .map(|d| &d.shortcut),
             _ => None, // Return None if an invalid mode is provided
         }
     }
 
-    // Fuzzy search that allows partial matches, even with no spaces or flexible query patterns
+    /// Fuzzy search, the shortcuts matching the query best first
     pub fn fuzzy_search(&self, query: &str) -> Vec<&Shortcut> {
         if query.is_empty() {
             println!("Retrieve_all");
             return self.retrieve_all();
         }
-        let mut results: Vec<&Shortcut> = Vec::new();
-        let binding: String = query.to_lowercase();
-        let query_parts: Vec<&str> = binding.split_whitespace().collect::<Vec<_>>();
+        self.search(&Query::fuzzy(query))
+            .into_iter()
+            .map(|hit| hit.shortcut)
+            .collect()
+    }
 
-        // Normalize the query (remove spaces) and loop over the data to find matches
-        for shortcut in &self.t.data {
-            let normalized_description = format!("{}{}{}", shortcut.application, shortcut.description, shortcut.shortcut).to_lowercase().replace(" ", "");
+    /// Keep the shortcuts passing the clauses of the query, and every fuzzy term has to match
+    /// one field of the shortcut as a subsequence. The hits are sorted by the match quality
+    /// blended with the usage rank (the order of data).
+    /// The shortcuts missing some chars of the query are skipped by the index.
+    pub fn search(&self, query: &Query) -> Vec<SearchHit<'_>> {
+        self.search_top(query, None).0
+    }
 
-            // Check if all query parts match the normalized description
-            if query_parts.iter().all(|part| normalized_description.contains(part)) {
-                results.push(shortcut);
+    /// The best `limit` hits of `search`, all of them if None, and how many there are in all.
+    /// Once `limit` hits are found, the shortcuts that cannot rank above them are only counted,
+    /// which is what keeps a page of a query matching most of a large sheet fast.
+    pub fn search_top(&self, query: &Query, limit: Option<usize>) -> (Vec<SearchHit<'_>>, usize) {
+        let n = self.t.data.len().max(1) as f64;
+        let limit = limit.unwrap_or(usize::MAX);
+        let best_match = |pinyin| -> i64 { query.terms.iter().map(|t| max_score(t, pinyin)).sum() };
+        let (best_fuzzy, best_pinyin) = (best_match(false), best_match(true));
+        let mut hits: Vec<SearchHit> = Vec::new();
+        let mut total = 0;
+        // The score to beat to be among the best `limit` hits, once that many are found
+        let mut threshold: Option<f64> = None;
+        // Only the shortcuts the index finds are matched, in the order of data
+        let mut found = vec![false; self.t.data.len()];
+        let positions = self.positions();
+        for id in self.index.lookup(query).ids() {
+            if let Some(rank) = positions.get(id) {
+                found[*rank] = true;
             }
         }
 
-        results
+        for (rank, shortcut) in self.t.data.iter().enumerate() {
+            if !found[rank] || !query.matches(shortcut) {
+                continue;
+            }
+            let rank_score = SEARCH_RANK_WEIGHT * (1.0 - rank as f64 / n);
+            // Only the Chinese chars are matched by pinyin, which can score a bit more
+            let best_match = match self.pinyin_search && !is_ascii(shortcut) {
+                true => best_pinyin,
+                false => best_fuzzy,
+            };
+            if threshold.is_some_and(|t| best_match as f64 + rank_score <= t) {
+                if self.terms_match(query, shortcut) {
+                    total += 1;
+                }
+                continue;
+            }
+            let Some(hit) = self.hit(query, shortcut, rank_score) else {
+                continue;
+            };
+            total += 1;
+            hits.push(hit);
+            // Keep the best ones from time to time rather than sorting all of them at the end
+            if hits.len() == limit || hits.len() >= limit.saturating_mul(2) {
+                hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+                hits.truncate(limit);
+                threshold = hits.last().map(|hit| hit.score);
+            }
+        }
+
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit);
+        (hits, total)
+    }
+
+    /// Whether every fuzzy term matches one field of the shortcut, as `hit` would find
+    fn terms_match(&self, query: &Query, shortcut: &Shortcut) -> bool {
+        let searched = searched_fields(shortcut);
+        query.terms.iter().all(|term| {
+            searched.iter().any(|(name, text)| {
+                is_subsequence(term, text)
+                    || self.pinyin_search
+                        && PINYIN_FIELDS.contains(name)
+                        && pinyin_match(term, text).is_some()
+            }) || self.pinyin_search && pinyin_match(term, &shortcut.comment).is_some()
+        })
+    }
+
+    /// The shortcut scored against the fuzzy terms, None if one of them matches no field
+    fn hit<'a>(
+        &self,
+        query: &Query,
+        shortcut: &'a Shortcut,
+        rank_score: f64,
+    ) -> Option<SearchHit<'a>> {
+        let searched = searched_fields(shortcut);
+        let comment = ("comment", Cow::Borrowed(shortcut.comment.as_str()));
+        let fields = || searched.iter().chain(self.pinyin_search.then_some(&comment));
+        let mut score: i64 = 0;
+        // The matched chars of each field, in the order of the fields
+        let mut positions: Vec<(usize, &'static str, Vec<usize>)> = Vec::new();
+        for term in query.terms.iter() {
+            let best = fields()
+                .enumerate()
+                .filter_map(|(f, (name, text))| {
+                    let fuzzy = match *name {
+                        "comment" => None,
+                        _ => fuzzy_match(term, text),
+                    };
+                    let pinyin = if self.pinyin_search && PINYIN_FIELDS.contains(name) {
+                        pinyin_match(term, text)
+                    } else {
+                        None
+                    };
+                    fuzzy
+                        .into_iter()
+                        .chain(pinyin)
+                        .max_by_key(|(s, _)| *s)
+                        .map(|(s, p)| (s, f, *name, p))
+                })
+                .max_by_key(|(s, f, _, _)| (*s, Reverse(*f)));
+            let (s, f, name, p) = best?;
+            score += s;
+            match positions.iter_mut().find(|(i, _, _)| *i == f) {
+                Some((_, _, existing)) => existing.extend(p),
+                None => positions.push((f, name, p)),
+            }
+        }
+        positions.sort_by_key(|(f, _, _)| *f);
+        for (field, ranges) in query.highlights(shortcut) {
+            let chars = ranges.iter().flat_map(|r| r.0..r.1);
+            match positions.iter_mut().find(|(_, name, _)| *name == field) {
+                Some((_, _, p)) => p.extend(chars),
+                None => positions.push((usize::MAX, field, chars.collect())),
+            }
+        }
+        let matches: Vec<FieldMatch> = positions
+            .into_iter()
+            .filter(|(_, _, p)| !p.is_empty())
+            .map(|(_, field, mut p)| {
+                p.sort_unstable();
+                FieldMatch {
+                    field,
+                    ranges: to_ranges(&p),
+                }
+            })
+            .collect();
+        Some(SearchHit {
+            shortcut,
+            score: score as f64 + rank_score,
+            matches,
+        })
     }
 
     /// Retrieve all data
@@ -193,77 +712,444 @@ impl MusicSheetDB {
 
     /// Delete a list of shortcuts by id, and move the deleted shortcuts to deleted
     pub fn delete_shortcuts(&mut self, ids: Vec<u128>) {
-        let mut deleted_shortcuts: Vec<Shortcut> = Vec::new();
+        let mut deleted_shortcuts: Vec<DeletedShortcut> = Vec::new();
 
         // Collect the shortcuts with the specified IDs to move them to deleted
         self.t.data.retain(|shortcut| {
             if ids.contains(&shortcut.id) {
-                deleted_shortcuts.push(shortcut.clone());
+                deleted_shortcuts.push(DeletedShortcut::new(shortcut.clone(), DeleteReason::Delete));
                 false
             } else {
                 true
             }
         });
 
+        let ids: Vec<u128> = deleted_shortcuts.iter().map(|d| d.shortcut.id).collect();
+        for id in ids.iter() {
+            self.index.remove(*id);
+        }
+        let mut change_set = ChangeSet::new("delete");
+        for d in deleted_shortcuts.iter() {
+            change_set.push(d.shortcut.id, Some(d.shortcut.clone()), None);
+        }
+        change_set.trashed = deleted_shortcuts.clone();
+        self.history.record(change_set);
+        self.store(|s| {
+            s.remove_shortcuts(&ids)?;
+            s.put_deleted(&deleted_shortcuts)
+        });
         self.t.deleted.extend(deleted_shortcuts);
     }
 
     /// Get the shortcuts that were deleted
-    pub fn retrieve_deleted(&self) -> &Vec<Shortcut> {
+    pub fn retrieve_deleted(&self) -> &Vec<DeletedShortcut> {
         &self.t.deleted
     }
 
-    pub fn clear_deleted(&mut self) {
-        self.t.deleted.clear();
+    /// Restore the latest deleted version of each id, and return the ids not found in deleted.
+    /// If the shortcut is still in data (a legacy "edit" entry), it is revised to that version
+    /// while keeping its hit_number. The restore is undone as one step.
+    pub fn restore_shortcuts(&mut self, ids: &[u128]) -> Vec<u128> {
+        let mut unmatched: Vec<u128> = Vec::new();
+        let mut restored: Vec<Shortcut> = Vec::new();
+        let mut revised: Vec<Shortcut> = Vec::new();
+        let mut change_set = ChangeSet::new("restore");
+
+        for id in ids {
+            let Some(pos) = self.t.deleted.iter().rposition(|d| d.shortcut.id == *id) else {
+                unmatched.push(*id);
+                continue;
+            };
+            let entry = self.t.deleted.remove(pos);
+            let mut old = entry.shortcut.clone();
+            change_set.untrashed.push(entry);
+            if let Some(live) = self.t.data.iter().find(|sc| sc.id == *id) {
+                old.hit_number = live.hit_number;
+                revised.push(old);
+            } else {
+                self.index.insert(&old);
+                self.t.data.push(old.clone());
+                change_set.push(old.id, None, Some(old.clone()));
+                restored.push(old);
+            }
+        }
+
+        if !change_set.untrashed.is_empty() {
+            let deleted = self.t.deleted.clone();
+            self.store(|s| {
+                s.put_shortcuts(&restored)?;
+                s.set_deleted(&deleted)
+            });
+            self.revise_shortcuts_into(revised, change_set);
+        }
+        unmatched
+    }
+
+    /// Permanently remove the deleted entries matching the filter, return how many were removed
+    fn purge_deleted_where<F>(&mut self, f: F) -> usize
+    where
+        F: Fn(&DeletedShortcut) -> bool,
+    {
+        let before = self.t.deleted.len();
+        self.t.deleted.retain(|d| !f(d));
+        let purged = before - self.t.deleted.len();
+        if purged > 0 {
+            // Nothing to revert to anymore for the shortcuts that are completely gone
+            let kept_ids: HashSet<u128> = self
+                .t
+                .data
+                .iter()
+                .map(|sc| sc.id)
+                .chain(self.t.deleted.iter().map(|d| d.shortcut.id))
+                .collect();
+            let orphans: Vec<(u128, u64)> = self
+                .t
+                .revisions
+                .iter()
+                .filter(|r| !kept_ids.contains(&r.shortcut.id))
+                .map(|r| (r.shortcut.id, r.revision))
+                .collect();
+            self.t.revisions.retain(|r| kept_ids.contains(&r.shortcut.id));
+            let deleted = self.t.deleted.clone();
+            self.store(|s| {
+                s.set_deleted(&deleted)?;
+                s.remove_revisions(&orphans)
+            });
+        }
+        purged
+    }
+
+    /// Permanently remove every deleted entry of the given ids
+    pub fn purge_deleted(&mut self, ids: &[u128]) -> usize {
+        self.purge_deleted_where(|d| ids.contains(&d.shortcut.id))
+    }
+
+    /// Permanently remove the entries deleted at least `secs` seconds ago
+    pub fn purge_deleted_older_than(&mut self, secs: i64) -> usize {
+        let deadline = now_timestamp() - secs;
+        self.purge_deleted_where(|d| d.deleted_at <= deadline)
+    }
+
+    /// Permanently remove everything in deleted
+    pub fn clear_deleted(&mut self) -> usize {
+        self.purge_deleted_where(|_| true)
     }
 
-    /// If the id is not found in data, then return false and do nothing.
+    /// Update the shortcuts of the same id in data, and return the ones whose id is not found.
+    /// The old versions are kept as revisions.
     pub fn update_shortcuts(&mut self, new_shortcuts: Vec<Shortcut>) -> Vec<Shortcut> {
+        self.revise_shortcuts(new_shortcuts, "update")
+    }
+
+    /// Replace the shortcuts in data by the new versions, recording a revision for every
+    /// shortcut that actually changed. Returns the new versions whose id is not in data.
+    fn revise_shortcuts(&mut self, new_shortcuts: Vec<Shortcut>, label: &str) -> Vec<Shortcut> {
+        self.revise_shortcuts_into(new_shortcuts, ChangeSet::new(label))
+    }
+
+    /// `revise_shortcuts`, recording the revisions in the change set of the operation
+    fn revise_shortcuts_into(
+        &mut self,
+        new_shortcuts: Vec<Shortcut>,
+        mut change_set: ChangeSet,
+    ) -> Vec<Shortcut> {
         let mut unmatched: Vec<Shortcut> = Vec::new();
-        let mut modified_shortcuts: Vec<Shortcut> = Vec::new();
+        let mut updated_shortcuts: Vec<Shortcut> = Vec::new();
+        let mut revisions: Vec<Revision> = Vec::new();
 
         for new_sc in new_shortcuts {
-            if let Some(shortcut) = self.t.data.iter_mut().find(|s| s.id == new_sc.id) {
-                modified_shortcuts.push(shortcut.clone());
-                // *shortcut = new_sc;  // replace the entire object
-                shortcut.update(&new_sc);
-            } else {
+            let Some(pos) = self.t.data.iter().position(|s| s.id == new_sc.id) else {
                 unmatched.push(new_sc);
+                continue;
+            };
+            let old = self.t.data[pos].clone();
+            self.t.data[pos].update(&new_sc);
+            let updated = self.t.data[pos].clone();
+            self.index.insert(&updated);
+            let diffs = old.diff(&updated);
+            if diffs.is_empty() {
+                continue;
             }
+            let revision = Revision {
+                revision: self.latest_revision(old.id) + 1,
+                timestamp: now_timestamp(),
+                shortcut: old.clone(),
+                diffs,
+            };
+            self.t.revisions.push(revision.clone());
+            change_set.push(old.id, Some(old), Some(updated.clone()));
+            revisions.push(revision);
+            updated_shortcuts.push(updated);
         }
-        self.t.deleted.extend(modified_shortcuts);
+        change_set.revised.extend(revisions.iter().cloned());
+        self.history.record(change_set);
+        self.store(|s| {
+            s.put_shortcuts(&updated_shortcuts)?;
+            s.put_revisions(&revisions)
+        });
 
         unmatched
     }
+
+    fn latest_revision(&self, id: u128) -> u64 {
+        self.t
+            .revisions
+            .iter()
+            .filter(|r| r.shortcut.id == id)
+            .map(|r| r.revision)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// All the revisions of one shortcut, the newest first
+    pub fn retrieve_revisions(&self, id: u128) -> Vec<&Revision> {
+        let mut revisions: Vec<&Revision> =
+            self.t.revisions.iter().filter(|r| r.shortcut.id == id).collect();
+        revisions.sort_by_key(|r| Reverse(r.revision));
+        revisions
+    }
+
+    /// Bring the shortcut back to the given revision, keeping its hit_number.
+    /// The current version becomes a new revision, so the revert can be reverted too.
+    pub fn revert_shortcut(&mut self, id: u128, revision: u64) -> Result<(), String> {
+        let live = self
+            .retrieve(id, None)
+            .ok_or(format!("Shortcut {} is not in data", id_to_string(id)))?;
+        let hit_number = live.hit_number;
+        let mut old = self
+            .t
+            .revisions
+            .iter()
+            .find(|r| r.shortcut.id == id && r.revision == revision)
+            .ok_or(format!("Revision {} does not exist", revision))?
+            .shortcut
+            .clone();
+        old.hit_number = hit_number;
+        self.revise_shortcuts(vec![old], "revert");
+        Ok(())
+    }
+
+    /// All the tags in data with how many shortcuts use them, the most used first
+    pub fn tag_counts(&self) -> Vec<(String, usize)> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for tag in self.t.data.iter().flat_map(|sc| sc.tags.iter()) {
+            *counts.entry(tag).or_insert(0) += 1;
+        }
+        let mut counts: Vec<(String, usize)> =
+            counts.into_iter().map(|(tag, n)| (tag.to_string(), n)).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts
+    }
+
+    /// Replace the tags in `from` by `to` in all the shortcuts, which renames a tag,
+    /// or merges several tags into one. Returns how many shortcuts changed.
+    pub fn rename_tags(&mut self, from: &[String], to: &str) -> usize {
+        let renamed: Vec<Shortcut> = self
+            .t
+            .data
+            .iter()
+            .filter_map(|sc| {
+                let mut sc = sc.clone();
+                sc.rename_tags(from, to).then_some(sc)
+            })
+            .collect();
+        let n = renamed.len();
+        self.revise_shortcuts(renamed, "rename_tags");
+        n
+    }
 }
 
 impl MusicSheetDB {
-    /// Initialize an empty table
+    /// Whether the Chinese chars of the description, application and comment also match
+    /// their pinyin in `search`
+    pub fn set_pinyin_search(&mut self, enabled: bool) {
+        self.pinyin_search = enabled;
+    }
+
+    /// Change how many operations can be undone, 0 disables undo
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.history.set_depth(depth);
+    }
+
+    /// Record all the following changes as one step of undo, until `end_change_group`
+    pub fn begin_change_group(&mut self, label: &str) {
+        self.history.begin_group(label);
+    }
+
+    pub fn end_change_group(&mut self) {
+        self.history.end_group();
+    }
+
+    /// Undo the latest operation, return its label, or None if there is nothing to undo
+    pub fn undo(&mut self) -> Option<String> {
+        let change_set = self.history.pop_undo()?;
+        self.apply_change_set(&change_set, false);
+        let label = change_set.label.clone();
+        self.history.push_redo(change_set);
+        Some(label)
+    }
+
+    /// Redo the latest undone operation, return its label, or None if there is nothing to redo
+    pub fn redo(&mut self) -> Option<String> {
+        let change_set = self.history.pop_redo()?;
+        self.apply_change_set(&change_set, true);
+        let label = change_set.label.clone();
+        self.history.push_undo(change_set);
+        Some(label)
+    }
+
+    /// Bring every shortcut in the change set to its state after (forward) or before the change.
+    /// A shortcut still in data keeps its usage, which hits changed since the snapshot.
+    /// The entries the operation put into or took out of deleted, and its revisions, are
+    /// added again or taken out accordingly.
+    fn apply_change_set(&mut self, change_set: &ChangeSet, forward: bool) {
+        let mut put: Vec<Shortcut> = Vec::new();
+        let mut removed: Vec<u128> = Vec::new();
+        for change in change_set.changes.iter() {
+            let target = if forward { &change.after } else { &change.before };
+            let mut target = target.clone();
+            let pos = self.t.data.iter().position(|sc| sc.id == change.id);
+            match (target.as_mut(), pos) {
+                (Some(sc), Some(pos)) => {
+                    sc.take_usage(&self.t.data[pos]);
+                    self.t.data[pos] = sc.clone();
+                }
+                (Some(sc), None) => self.t.data.push(sc.clone()),
+                (None, Some(pos)) => {
+                    self.t.data.remove(pos);
+                }
+                (None, None) => {}
+            }
+            match target {
+                Some(sc) => {
+                    self.index.insert(&sc);
+                    put.push(sc);
+                }
+                None => {
+                    self.index.remove(change.id);
+                    removed.push(change.id);
+                }
+            }
+        }
+
+        let revision_keys: Vec<(u128, u64)> = change_set
+            .revised
+            .iter()
+            .map(|r| (r.shortcut.id, r.revision))
+            .collect();
+        self.t
+            .revisions
+            .retain(|r| !revision_keys.contains(&(r.shortcut.id, r.revision)));
+        if forward {
+            self.t.revisions.extend(change_set.revised.iter().cloned());
+        }
+
+        let (added, taken) = if forward {
+            (&change_set.trashed, &change_set.untrashed)
+        } else {
+            (&change_set.untrashed, &change_set.trashed)
+        };
+        self.t.deleted.extend(added.iter().cloned());
+        for entry in taken.iter() {
+            // Entries restored or purged since then are simply not found
+            if let Some(pos) = self.t.deleted.iter().rposition(|d| {
+                d.shortcut.id == entry.shortcut.id
+                    && d.deleted_at == entry.deleted_at
+                    && d.reason == entry.reason
+            }) {
+                self.t.deleted.remove(pos);
+            }
+        }
+
+        let deleted = self.t.deleted.clone();
+        self.store(|s| {
+            s.remove_shortcuts(&removed)?;
+            s.put_shortcuts(&put)?;
+            s.set_deleted(&deleted)?;
+            s.remove_revisions(&revision_keys)?;
+            if forward {
+                s.put_revisions(&change_set.revised)?;
+            }
+            Ok(())
+        });
+    }
+
+    /// Initialize an empty table, which lives only in memory
     pub fn new() -> Self {
         Self {
             t: MusicSheetDBTable::new(),
             keymap: HashMap::new(),
+            storage: None,
+            dirty_since: None,
+            last_change: None,
+            history: History::default(),
+            pinyin_search: true,
+            index: SearchIndex::default(),
+            generation: 0,
+            positions: RefCell::new(None),
         }
     }
 
-    /// Import from JSON file
-    pub fn import_from_json(file_path: &str) -> Result<Self, Box<dyn Error>> {
-        let file = File::open(file_path)?;
-        let t: MusicSheetDBTable = serde_json::from_reader(file)?;
+    /// Load the table from the storage, and keep the storage to write the changes.
+    /// A table that cannot be loaded (e.g. it was written by a newer version) is an error,
+    /// and the storage is left untouched.
+    pub fn with_storage(mut storage: Box<dyn SheetStorage>) -> Result<Self, Box<dyn Error>> {
+        let t = storage.load()?;
+        let index = SearchIndex::new(&t.data);
         Ok(Self {
             t,
             keymap: HashMap::new(),
+            storage: Some(storage),
+            dirty_since: None,
+            last_change: None,
+            history: History::default(),
+            pinyin_search: true,
+            index,
+            generation: 0,
+            positions: RefCell::new(None),
         })
     }
 
-    /// Export to JSON file
-    pub fn export_to_json(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
-        let _ = std::fs::remove_file(file_path);
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(file_path)?;
-        serde_json::to_writer(file, &self.t)?;
+    /// Whether the changes can be persisted, false when the music sheet only lives in memory
+    pub fn has_storage(&self) -> bool {
+        self.storage.is_some()
+    }
+
+    /// Make sure everything is written to the storage
+    pub fn persist(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.storage.as_deref_mut() {
+            Some(storage) => {
+                storage.persist(&self.t)?;
+                self.dirty_since = None;
+                self.last_change = None;
+                Ok(())
+            }
+            None => Err("No storage to persist the music sheet".into()),
+        }
+    }
+
+    /// Write a snapshot of the current table into the backup dir, keeping the newest `keep` ones
+    pub fn backup(&self, backup_dir: &str, keep: usize) -> Result<String, Box<dyn Error>> {
+        create_backup(&self.t, backup_dir, keep)
+    }
+
+    /// Replace the whole table, in memory and in the storage, by the backup of the given name.
+    /// The current table is backed up first, so the restore itself can be reverted.
+    pub fn restore_backup(
+        &mut self,
+        backup_dir: &str,
+        name: &str,
+        keep: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let t = read_backup(backup_dir, name)?;
+        self.backup(backup_dir, keep.max(1))?;
+        if let Some(storage) = self.storage.as_deref_mut() {
+            storage.replace_all(&t)?;
+        }
+        self.index = SearchIndex::new(&t.data);
+        self.t = t;
+        self.history.clear(); // The recorded changes do not apply to the restored table
+        self.mark_dirty();
         Ok(())
     }
 
@@ -293,24 +1179,35 @@ impl MusicSheetDB {
         }
     }
 
-    /// Function to increase hit_number for a given row index
-    pub fn hit_num_up(&mut self, id: u128) -> Result<(), String> {
+    /// Count one hit of the shortcut, and update its frecency with the given half-life
+    pub fn record_hit(&mut self, id: u128, half_life_secs: i64) -> Result<(), String> {
         if let Some(sc) = self.t.data.iter_mut().find(|shortcut| shortcut.id == id) {
-            sc.hit_number += 1; // Increment the hit_number
+            record_hit(sc, now_timestamp(), half_life_secs);
+            let sc = sc.clone();
+            self.store(|s| s.put_usage(&sc));
             Ok(())
         } else {
             Err(format!("ID {} not found", id)) // Return an error if the index is invalid
         }
     }
 
-    /// Sort by a specific column name, support: id, hit_number, application, description
+    /// Rank the shortcuts, the best first. The ties are sorted by application.
+    pub fn rank(&mut self, mode: RankMode, half_life_secs: i64) {
+        self.sort_by_column("application", true);
+        rank(&mut self.t.data, mode, now_timestamp(), half_life_secs);
+    }
+
+    /// Sort by a specific column name, support: id, hit_number, application, description, tags.
+    /// Sorting by tags puts the shortcuts with the same tags next to each other.
     pub fn sort_by_column(&mut self, column: &str, ascending: bool) {
+        self.generation = self.generation.wrapping_add(1);
         // Decide on the comparison function based on the column, done once
         let comparator: Box<dyn Fn(&Shortcut, &Shortcut) -> std::cmp::Ordering> = match column {
             "id" => Box::new(|a, b| a.id.cmp(&b.id)),
             "hit_number" => Box::new(|a, b| a.hit_number.cmp(&b.hit_number)),
             "application" => Box::new(|a, b| a.application.cmp(&b.application)),
             "description" => Box::new(|a, b| a.description.cmp(&b.description)),
+            "tags" => Box::new(|a, b| a.tags.cmp(&b.tags)),
             _ => Box::new(|_, _| std::cmp::Ordering::Equal), // Handle unknown column names
         };
 
@@ -337,16 +1234,31 @@ impl UserSheet {
         Self { data: shortcuts }
     }
 
-    pub fn import_from(path: &str) -> Result<Self, Box<dyn Error>> {
+    /// Import the shortcuts of a json file or dir, see `check` for `repeat_limit`
+    pub fn import_from(path: &str, repeat_limit: usize) -> Result<Self, Box<dyn Error>> {
         let metadata = fs::metadata(path)?;
 
-        if metadata.is_file() {
-            UserSheet::import_from_json(path)
+        let sheet = if metadata.is_file() {
+            UserSheet::import_from_json(path)?
         } else if metadata.is_dir() {
-            UserSheet::import_from_json_dir(path)
+            UserSheet::import_from_json_dir(path)?
         } else {
-            Err(format!("{} is neither a file nor a directory.", path).into())
+            return Err(format!("{} is neither a file nor a directory.", path).into());
+        };
+        sheet.check(repeat_limit)?;
+        Ok(sheet)
+    }
+
+    /// Refuse the sheet if one of its shortcuts cannot be parsed, or runs more than
+    /// `repeat_limit` steps, see `Sequence::parse_limited`
+    fn check(&self, repeat_limit: usize) -> Result<(), Box<dyn Error>> {
+        for sc in &self.data {
+            if let Err(e) = Sequence::parse_limited(&sc.shortcut, repeat_limit) {
+                let pointed = e.pointed(&sc.shortcut);
+                return Err(format!("Invalid shortcut of '{}': {}\n{}", sc.description, e, pointed).into());
+            }
         }
+        Ok(())
     }
 
     /// Import from JSON file
@@ -382,12 +1294,8 @@ impl UserSheet {
 
     /// Export to JSON file
     pub fn export_to_json(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
-        let _ = std::fs::remove_file(file_path);
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(file_path)?;
-        serde_json::to_writer(file, &self.data)?;
+        let content = serde_json::to_vec(&self.data)?;
+        atomic_write(Path::new(file_path), &content)?;
         Ok(())
     }
 
@@ -395,3 +1303,207 @@ impl UserSheet {
         db.add_shortcuts(self.data.clone(), None);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::storage::JsonStorage;
+
+    #[test]
+    fn test_schema_migration() {
+        let legacy = r#"{
+            "deleted": [{"id": "00000000-0000-0000-0000-000000000001", "hit_number": 2,
+                "shortcut": "ctrl+a", "application": "", "description": "old", "comment": ""}],
+            "data": []
+        }"#;
+        let (t, version) = MusicSheetDBTable::from_json_slice(legacy.as_bytes()).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(t.schema_version, SCHEMA_VERSION);
+        assert_eq!(t.deleted[0].reason, DeleteReason::Delete);
+        assert_eq!(t.deleted[0].shortcut.description, "old");
+
+        let content = serde_json::to_vec(&t).unwrap();
+        let (_, version) = MusicSheetDBTable::from_json_slice(&content).unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+
+        let newer = format!(
+            r#"{{"schema_version": {}, "deleted": [], "data": []}}"#,
+            SCHEMA_VERSION + 1
+        );
+        let err = MusicSheetDBTable::from_json_slice(newer.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("newer"));
+
+        // The music sheet is not opened, so it is not overwritten either
+        let path = std::env::temp_dir().join(format!("liz_test_{}.json", generate_id()));
+        fs::write(&path, &newer).unwrap();
+        let storage = JsonStorage::new(path.to_str().unwrap());
+        assert!(MusicSheetDB::with_storage(Box::new(storage)).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), newer);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let v5 = r#"{"schema_version": 5, "deleted": [], "data": [
+            {"id": "00000000-0000-0000-0000-000000000001", "shortcut": "ctrl+a"}
+        ]}"#;
+        let (t, _) = MusicSheetDBTable::from_json_slice(v5.as_bytes()).unwrap();
+        assert!(t.data[0].placeholders.is_empty());
+
+        let sc = Shortcut {
+            shortcut: "\"{{name:you}}\"".to_string(),
+            placeholders: vec![Placeholder {
+                name: "name".to_string(),
+                default: None,
+                hint: "Who to greet".to_string(),
+            }],
+            ..Default::default()
+        };
+        assert!(sc
+            .to_json_string()
+            .contains(r#""placeholders":[{"name":"name","hint":"Who to greet"}]"#));
+        let path = std::env::temp_dir().join(format!("liz_test_{}.json", generate_id()));
+        let path = path.to_str().unwrap();
+        UserSheet::new(vec![sc.clone()]).export_to_json(path).unwrap();
+        let sheet = UserSheet::import_from(path, 100).unwrap();
+        let _ = fs::remove_file(path);
+        assert_eq!(sheet.data[0].placeholders, sc.placeholders);
+    }
+
+    #[test]
+    fn test_usage_migration() {
+        let v4 = r#"{"schema_version": 4, "deleted": [], "data": [
+            {"id": "00000000-0000-0000-0000-000000000001", "hit_number": 7, "shortcut": "ctrl+a"},
+            {"id": "00000000-0000-0000-0000-000000000002", "shortcut": "ctrl+b"}
+        ]}"#;
+        let (t, _) = MusicSheetDBTable::from_json_slice(v4.as_bytes()).unwrap();
+        assert_eq!(t.data[0].frecency, 7.0);
+        assert_eq!(t.data[1].frecency, 0.0);
+        assert!(t.data[0].last_used >= now_timestamp() - 60);
+    }
+
+    #[test]
+    fn test_search_ranking() {
+        let shortcut = |desc: &str, app: &str| Shortcut {
+            description: desc.to_string(),
+            application: app.to_string(),
+            ..Default::default()
+        };
+        let mut db = MusicSheetDB::new();
+        db.add_shortcuts(
+            vec![
+                shortcut("Cut or paste yank", "vim"),
+                shortcut("Copy line", "vscode"),
+                shortcut("Close tab", "firefox"),
+            ],
+            None,
+        );
+        // A much better match beats a better usage rank
+        let hits = db.search(&Query::fuzzy("copy"));
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].shortcut.description, "Copy line");
+
+        // Matches of about the same quality follow the usage rank
+        let hits = db.search(&Query::fuzzy("cl"));
+        let found: Vec<&str> = hits.iter().map(|h| h.shortcut.description.as_str()).collect();
+        assert_eq!(found, vec!["Copy line", "Close tab"]);
+        assert_eq!(hits[0].matches[0].field, "description");
+        assert_eq!(hits[0].matches[0].ranges, vec![(0, 1), (5, 6)]);
+
+        // Every term must match, in any field
+        let hits = db.search(&Query::fuzzy("line code"));
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].matches.len(), 2);
+        assert_eq!(
+            hits[0].shortcut.format_highlighted("#description", &hits[0].matches),
+            "Copy <mark>line</mark>"
+        );
+
+        // The filters narrow the results and are highlighted too
+        let hits = db.search(&Query::parse(r#"app:code "line""#).unwrap());
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            hits[0]
+                .shortcut
+                .format_highlighted("#description | #application", &hits[0].matches),
+            "Copy <mark>line</mark> | vs<mark>code</mark>"
+        );
+    }
+
+    #[test]
+    fn test_pinyin_search() {
+        let mut db = MusicSheetDB::new();
+        db.add_shortcuts(
+            vec![
+                Shortcut {
+                    description: "保存文件".to_string(),
+                    application: "vscode".to_string(),
+                    ..Default::default()
+                },
+                Shortcut {
+                    description: "Close tab".to_string(),
+                    application: "浏览器".to_string(),
+                    comment: "关闭当前标签页".to_string(),
+                    ..Default::default()
+                },
+            ],
+            None,
+        );
+        let found = |db: &MusicSheetDB, q: &str| -> Vec<String> {
+            db.search(&Query::fuzzy(q))
+                .iter()
+                .map(|h| h.shortcut.description.clone())
+                .collect()
+        };
+        assert_eq!(found(&db, "baocun"), vec!["保存文件"]);
+        assert_eq!(found(&db, "bcwj"), vec!["保存文件"]);
+        assert_eq!(found(&db, "llq tab"), vec!["Close tab"]);
+        assert_eq!(found(&db, "biaoqian"), vec!["Close tab"]);
+
+        let hits = db.search(&Query::fuzzy("wj"));
+        assert_eq!(
+            hits[0].shortcut.format_highlighted("#description", &hits[0].matches),
+            "保存<mark>文件</mark>"
+        );
+
+        db.set_pinyin_search(false);
+        assert!(found(&db, "baocun").is_empty());
+        assert!(found(&db, "biaoqian").is_empty());
+    }
+
+    #[test]
+    fn test_tags() {
+        let tagged = |desc: &str, tags: &[&str]| Shortcut {
+            description: desc.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        };
+        let mut db = MusicSheetDB::new();
+        db.add_shortcuts(
+            vec![
+                tagged("commit", &["git", "review"]),
+                tagged("blame", &["git"]),
+                tagged("step", &["debug"]),
+                tagged("diff", &["review"]),
+            ],
+            None,
+        );
+        assert_eq!(db.fuzzy_search("debug").len(), 1);
+        assert_eq!(
+            db.tag_counts(),
+            vec![("git".to_string(), 2), ("review".to_string(), 2), ("debug".to_string(), 1)]
+        );
+
+        // Merging review into git keeps git only once on "commit"
+        assert_eq!(db.rename_tags(&["review".to_string()], "git"), 2);
+        assert_eq!(db.tag_counts()[0], ("git".to_string(), 3));
+        let commit = db.fuzzy_search("commit")[0];
+        assert_eq!(commit.tags, vec!["git".to_string()]);
+        assert_eq!(db.undo().as_deref(), Some("rename_tags"));
+        assert_eq!(db.tag_counts().len(), 3);
+
+        db.sort_by_column("tags", true);
+        let order: Vec<String> = db.retrieve_all().iter().map(|sc| sc.description.clone()).collect();
+        assert_eq!(order, vec!["step", "blame", "commit", "diff"]);
+    }
+}
@@ -1,16 +1,18 @@
 use std::error::Error;
-use std::thread::sleep;
-use std::time::Duration;
 use std::collections::HashMap;
+use std::fmt::Write;
 
-use enigo::{
-    Direction::{Press, Release},
-    Enigo, InputError, Key, Keyboard, Settings,
-};
+use chrono::Local;
+use enigo::Key;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::input::{BackendKind, Direction, InputBackend, InputEvent, RecordingBackend};
+use super::sequence::{Action, Sequence, SequenceError, Step};
 
 /// Converts a key name (e.g., "ctrl", "u", "enter") to an enigo::Key.
 /// Single characters are mapped to `Key::Unicode`.
-fn string_to_key(s: &str) -> Option<Key> {
+pub fn string_to_key(s: &str) -> Option<Key> {
     let key_str = s.to_lowercase();
     match key_str.as_str() {
         "ctrl" | "control" => Some(Key::Control),
@@ -57,147 +59,323 @@ fn string_to_key(s: &str) -> Option<Key> {
     }
 }
 
-/// Simulate a sequence of keyboard events using Enigo.
-/// The sequence format is space-separated tokens like "ctrl.1 u.1 u.0 ctrl.0"
-/// where "1" stands for Press and "0" stands for Release.
-fn simulate_key_events_enigo(enigo: &mut Enigo, sequence: &str) -> Result<(), Box<dyn Error>> {
-    // Split the sequence by whitespace into individual event tokens.
-    for token in sequence.split_whitespace() {
-        // Use the last dot to separate key from event code.
-        if let Some(idx) = token.rfind('.') {
-            let key_str = &token[..idx];
-            let event_code = &token[idx + 1..];
-            if event_code.is_empty() {
-                return Err(format!("Invalid token (missing event code): '{}'", token).into());
+/// The value of a built-in variable of the typed text, None if `name` is not one
+fn builtin_variable(name: &str, arg: Option<&str>) -> Option<Result<String, Box<dyn Error>>> {
+    let value = match (name, arg) {
+        ("date", format) => now_formatted(format.unwrap_or("%Y-%m-%d")),
+        ("time", format) => now_formatted(format.unwrap_or("%H:%M:%S")),
+        ("uuid", None) => Ok(Uuid::new_v4().to_string()),
+        ("env", Some(var)) => std::env::var(var)
+            .map_err(|_| format!("The environment variable {} is not set", var).into()),
+        ("hostname", None) => gethostname::gethostname()
+            .into_string()
+            .map_err(|_| "The hostname is not valid UTF-8".into()),
+        ("clipboard", None) => arboard::Clipboard::new()
+            .and_then(|mut clipboard| clipboard.get_text())
+            .map_err(|e| format!("Failed to read the clipboard: {}", e).into()),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// The local time in the chrono `format`, e.g. `%Y-%m-%d %H:%M`
+fn now_formatted(format: &str) -> Result<String, Box<dyn Error>> {
+    let mut result = String::new();
+    write!(result, "{}", Local::now().format(format))
+        .map_err(|_| format!("Invalid date format: {}", format))?;
+    Ok(result)
+}
+
+/// Expand the variables of the text with `variable`, `{name}` or `{name:arg}`.
+/// `\{` and `\}` are literal braces, and so are the braces that start no variable.
+fn expand_with(
+    text: &str,
+    variable: impl Fn(&str, Option<&str>) -> Option<Result<String, Box<dyn Error>>>,
+) -> Result<String, Box<dyn Error>> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find(['\\', '{']) {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(brace) = rest.strip_prefix('\\').and_then(|r| r.chars().next()) {
+            if brace == '{' || brace == '}' {
+                result.push(brace);
+                rest = &rest[2..];
+                continue;
             }
-            let key =
-                string_to_key(key_str).ok_or_else(|| format!("Unknown key: '{}'", key_str))?;
-            let direction = match event_code {
-                "1" => Press,
-                "0" => Release,
-                _ => return Err(format!("Unknown event code: '{}'", event_code).into()),
+        }
+        // A backslash followed by anything else is typed, it starts no variable
+        let inner = rest
+            .strip_prefix('{')
+            .and_then(|r| r.split_once('}'))
+            .map(|(inner, _)| inner);
+        if let Some(inner) = inner.filter(|inner| !inner.contains('{')) {
+            let (name, arg) = match inner.split_once(':') {
+                Some((name, arg)) => (name, Some(arg)),
+                None => (inner, None),
             };
-            // Simulate the key event.
-            enigo.key(key, direction)?;
-        } else {
-            return Err(format!("Invalid token format (no '.' found): '{}'", token).into());
+            if let Some(value) = variable(name, arg) {
+                result.push_str(&value?);
+                rest = &rest[inner.len() + 2..];
+                continue;
+            }
         }
+        result.push_str(&rest[..1]);
+        rest = &rest[1..];
     }
-    Ok(())
+    result.push_str(rest);
+    Ok(result)
 }
 
-/// Simulate tpying a text using Enigo.
-fn simulate_text_events_enigo(enigo: &mut Enigo, text: &str) -> Result<(), InputError> {
-    enigo.text(text)?;
-    Ok(())
+/// Expand the built-in variables of a typed text when it is executed:
+/// `{date}` or `{date:%d/%m/%Y}`, `{time}` or `{time:%H:%M}`, `{uuid}`, `{env:USER}`,
+/// `{hostname}` and `{clipboard}`. `\{date}` types `{date}` as it is.
+pub fn expand_variables(text: &str) -> Result<String, Box<dyn Error>> {
+    expand_with(text, builtin_variable)
 }
 
-pub fn execute_shortcut_enigo(shortcut_str: &str, delay_ms: u64) -> Result<(), Box<dyn Error>> {
-    // Initialize Enigo with the new Settings.
-    let mut enigo: Enigo = Enigo::new(&Settings::default())?;
+/// Send the steps of a sequence to the backend, see `execute_sequence`
+struct Player<'a> {
+    backend: &'a mut dyn InputBackend,
+    keymap: &'a HashMap<String, String>,
+    delay_ms: u64,
+    key_delay_ms: u64,
+    /// Whether the last step sent keys, they are one block with the next ones
+    in_keys: bool,
+    /// Whether no key event was sent in this block yet
+    first_key: bool,
+}
 
-    let shortcuts: Vec<&str> = shortcut_str.split("[STR]").collect();
+impl Player<'_> {
+    /// Sleep `delay_ms` before a block of keys, unless the last step was one
+    fn start_keys(&mut self) {
+        if !self.in_keys {
+            if self.delay_ms > 0 {
+                self.backend.delay(self.delay_ms);
+            }
+            self.in_keys = true;
+            self.first_key = true;
+        }
+    }
 
-    for shortcut in shortcuts {
-        if shortcut.is_empty() {
-            continue;
+    fn key(&mut self, key: &String, direction: Direction) -> Result<(), Box<dyn Error>> {
+        if !self.first_key && self.key_delay_ms > 0 {
+            self.backend.delay(self.key_delay_ms);
+        }
+        self.first_key = false;
+        let code = self.keymap.get(key).unwrap_or(key);
+        self.backend.key(code, direction)
+    }
+
+    fn play(&mut self, steps: &[Step]) -> Result<(), Box<dyn Error>> {
+        for step in steps {
+            match &step.action {
+                Action::Chord(keys) | Action::Hold(keys, _) => {
+                    self.start_keys();
+                    for key in keys {
+                        self.key(key, Direction::Press)?;
+                    }
+                    if let Action::Hold(_, ms @ 1..) = &step.action {
+                        self.backend.delay(*ms);
+                    }
+                    for key in keys.iter().rev() {
+                        self.key(key, Direction::Release)?;
+                    }
+                }
+                Action::Wait(ms) => {
+                    self.start_keys();
+                    if *ms > 0 {
+                        self.backend.delay(*ms);
+                    }
+                }
+                Action::KeyDelay(ms) => {
+                    self.start_keys();
+                    self.key_delay_ms = *ms;
+                }
+                Action::Text(text) => {
+                    if self.delay_ms > 0 {
+                        self.backend.delay(self.delay_ms);
+                    }
+                    self.in_keys = false;
+                    self.backend.text(&expand_variables(text)?)?;
+                }
+                Action::Repeat(steps, count) => {
+                    for _ in 0..*count {
+                        self.play(steps)?;
+                    }
+                }
+            }
         }
+        Ok(())
+    }
+}
 
-        sleep(Duration::from_millis(delay_ms)); // Sleep for the specified delay
+/// Execute the sequence with the backend, the keys are mapped to their keycodes by `keymap`.
+/// `delay_ms` is slept before each text and each block of keys between them,
+/// and `key_delay_ms` between the key events of a block until a `keydelay` changes it.
+/// The variables of the typed text are expanded, see `expand_variables`.
+pub fn execute_sequence(
+    backend: &mut dyn InputBackend,
+    sequence: &Sequence,
+    keymap: &HashMap<String, String>,
+    delay_ms: u64,
+    key_delay_ms: u64,
+) -> Result<(), Box<dyn Error>> {
+    Player {
+        backend,
+        keymap,
+        delay_ms,
+        key_delay_ms,
+        in_keys: false,
+        first_key: true,
+    }
+    .play(&sequence.steps)
+}
 
-        if shortcut.starts_with("+") {
-            let type_str: &str = &shortcut[2..]; // remove the prefix
-            simulate_text_events_enigo(&mut enigo, type_str)?;
-        } else {
-            simulate_key_events_enigo(&mut enigo, shortcut)?;
+/// One event sent when a shortcut is executed, as shown by a preview
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PreviewEvent {
+    Press { key: String },
+    Release { key: String },
+    Type { text: String },
+    Delay { ms: u64 },
+}
+
+impl From<InputEvent> for PreviewEvent {
+    fn from(event: InputEvent) -> Self {
+        match event {
+            InputEvent::Key(key, Direction::Press) => PreviewEvent::Press { key },
+            InputEvent::Key(key, Direction::Release) => PreviewEvent::Release { key },
+            InputEvent::Text(text) => PreviewEvent::Type { text },
+            InputEvent::Delay(ms) => PreviewEvent::Delay { ms },
         }
     }
+}
 
-    Ok(())
+/// A part of the shortcut that may not do what is meant, `start..end` are its chars
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreviewWarning {
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
 }
 
+/// What executing a shortcut would do, without pressing any key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preview {
+    pub keycode: String, // See `convert_shortcut_to_keycode`
+    pub events: Vec<PreviewEvent>,
+    pub warnings: Vec<PreviewWarning>,
+}
 
-/**
- * Convert shortcut string to key presses, using the keymap to map key to keycode
- * For example:
- * meta+pageup tab 123!@# tab ABC  
- * => 126.1 104.1 104.0 126.0 15.1 15.0 [STR]+ 123!@#[STR] 15.1 15.0 [STR]+ ABC[STR]
- * Where keycode of meta is 126, pageup (104), tab (15)
- * type 123!@ means directly type these characters "123!@".
- * Note: "ctrl + c" will be consider press "ctrl", then "+" then "c", as they are splited by space.
- */
-pub fn convert_shortcut_to_keycode(
-    shortcut: &str,
+/// Run the sequence as `execute_sequence` would, but record the events instead of sending
+/// them, and warn about the keys the backend cannot press and the chars it cannot type
+pub fn preview_shortcut(
+    sequence: &Sequence,
     key_event_codes: &HashMap<String, String>,
-) -> String {
-    let mut result = Vec::new();
+    backend: BackendKind,
+    delay_ms: u64,
+    key_delay_ms: u64,
+) -> Result<Preview, Box<dyn Error>> {
+    let recording = RecordingBackend::default();
+    execute_sequence(
+        &mut recording.clone(),
+        sequence,
+        key_event_codes,
+        delay_ms,
+        key_delay_ms,
+    )?;
 
-    // Split by marker [STR] to different blocks
-    let ss: Vec<&str> = shortcut.split("[STR]").collect();
+    let mut warnings = Vec::new();
+    preview_warnings(&sequence.steps, key_event_codes, backend, &mut warnings);
 
-    for s in ss {
-        if s.is_empty() {
+    Ok(Preview {
+        keycode: sequence.to_keycode(key_event_codes),
+        events: recording
+            .events()
+            .into_iter()
+            .map(PreviewEvent::from)
+            .collect(),
+        warnings,
+    })
+}
+
+/// Warn about the keys the backend cannot press and the chars it cannot type
+fn preview_warnings(
+    steps: &[Step],
+    key_event_codes: &HashMap<String, String>,
+    backend: BackendKind,
+    warnings: &mut Vec<PreviewWarning>,
+) {
+    for step in steps {
+        if let Action::Repeat(steps, _) = &step.action {
+            preview_warnings(steps, key_event_codes, backend, warnings);
             continue;
         }
-        if s.starts_with("+") {
-            // Typing the string
-            let type_str: &str = &s[2..];
-            result.push(format!("[STR]+ {}[STR]", type_str.trim()));
-        } else {
-            // Split the input by spaces
-            let parts: Vec<&str> = s.split_whitespace().collect();
-
-            for part in parts {
-                if part.is_empty() {
-                    continue;
-                }
-                if part.contains('+') && part != "+" {
-                    // Execute shortcut like ctrl+c, ctrl+v
-                    let keys: Vec<&str> = part.split('+').collect();
-                    for key in &keys {
-                        // Press
-                        let key: String = key.trim().to_lowercase();
-                        if let Some(event_code) = key_event_codes.get(&key) {
-                            result.push(format!("{}.1", event_code));
-                        } else {
-                            result.push(format!("{}.1", key));
-                        }
-                    }
-                    for key in keys.iter().rev() {
-                        // Release
-                        let key: String = key.trim().to_lowercase();
-                        if let Some(event_code) = key_event_codes.get(&key) {
-                            result.push(format!("{}.0", event_code));
-                        } else {
-                            result.push(format!("{}.0", key));
-                        }
-                    }
-                } else {
-                    // Not a shortcut, either one single key or a string to type
-                    let key = part.trim().to_lowercase();
-                    if let Some(event_code) = key_event_codes.get(&key) {
-                        // Press one key
-                        result.push(format!("{}.1", event_code));
-                        result.push(format!("{}.0", event_code));
-                    } else if key.len() == 1 {
-                        // Press one character
-                        let k = part.trim();
-                        result.push(format!("{}.1", k));
-                        result.push(format!("{}.0", k));
-                    } else if string_to_key(&key).is_some() {
-                        // Press one key
-                        result.push(format!("{}.1", key));
-                        result.push(format!("{}.0", key));
-                    } else {
-                        //  Type the string
-                        result.push(format!("[STR]+ {}[STR]", part.trim()));
+        let mut warn = |message: String| {
+            warnings.push(PreviewWarning {
+                start: step.start,
+                end: step.end,
+                message,
+            })
+        };
+        let text = match &step.action {
+            Action::Chord(keys) | Action::Hold(keys, _) => {
+                for key in keys {
+                    let code = key_event_codes.get(key).unwrap_or(key);
+                    if backend.can_press(code) {
+                        continue;
                     }
+                    let mapped = match code == key {
+                        true => String::new(),
+                        false => format!(" ({} in the keymap)", key),
+                    };
+                    warn(format!(
+                        "The {} backend cannot press '{}'{}",
+                        backend.name(),
+                        code,
+                        mapped
+                    ));
                 }
+                continue;
+            }
+            Action::Text(text) => text,
+            Action::Wait(_) | Action::KeyDelay(_) | Action::Repeat(..) => continue,
+        };
+        let mut untyped = String::new();
+        for c in text.chars().filter(|c| !backend.can_type(*c)) {
+            if !untyped.contains(c) {
+                untyped.push(c);
             }
         }
+        if !untyped.is_empty() {
+            warn(format!(
+                "The {} backend cannot type '{}'",
+                backend.name(),
+                untyped
+            ));
+        }
     }
+}
 
-    result.join(" ")
+/**
+ * Convert shortcut string to key presses, using the keymap to map key to keycode
+ * For example:
+ * meta+pageup tab "123!@#" tab "ABC"
+ * => 126.1 104.1 104.0 126.0 15.1 15.0 [STR]+ 123!@#[STR] 15.1 15.0 [STR]+ ABC[STR]
+ * Where keycode of meta is 126, pageup (104), tab (15)
+ * "123!@#" means directly type these characters 123!@#.
+ * See `Sequence` for the syntax, a shortcut that cannot be parsed is an error,
+ * and so is one running more than `repeat_limit` steps once its repeats are expanded.
+ * The keycode is only shown, the shortcuts are executed by `execute_sequence`.
+ */
+pub fn convert_shortcut_to_keycode(
+    shortcut: &str,
+    key_event_codes: &HashMap<String, String>,
+    repeat_limit: usize,
+) -> Result<String, SequenceError> {
+    Ok(Sequence::parse_limited(shortcut, repeat_limit)?.to_keycode(key_event_codes))
 }
 
 //  TEST
@@ -216,47 +394,52 @@ mod tests {
         // Test 1: Basic conversion with keys mapped to keycodes
         let shortcut = "Meta+S Tab";
         let expected = Some("126.1 s.1 s.0 126.0 15.1 15.0".to_string());
-        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes);
-        assert_eq!(Some(result), expected);
+        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes, 100).ok();
+        assert_eq!(result, expected);
 
         // Test 2: Test with characters (e.g., numbers or symbols)
-        let shortcut = "123!@# tab ABC";
+        let shortcut = "\"123!@#\" tab \"ABC\"";
         let expected = Some("[STR]+ 123!@#[STR] 15.1 15.0 [STR]+ ABC[STR]".to_string());
-        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes);
-        assert_eq!(Some(result), expected);
+        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes, 100).ok();
+        assert_eq!(result, expected);
 
         // Test 3: Test with more complex shortcuts (e.g., multiple key combinations)
-        let shortcut = "meta+pageup tab 123!@# meta+tab";
+        let shortcut = "meta+pageup tab \"123!@#\" meta+tab";
         let expected = Some(
             "126.1 104.1 104.0 126.0 15.1 15.0 [STR]+ 123!@#[STR] 126.1 15.1 15.0 126.0"
                 .to_string(),
         );
-        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes);
-        assert_eq!(Some(result), expected);
+        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes, 100).ok();
+        assert_eq!(result, expected);
 
-        // Test 4: Test with unrecognized keys (e.g., no mapping for 'enter')
+        // Test 4: Test with keys not in the keymap (e.g., no mapping for 'enter')
         let shortcut = "enter tab";
-        let expected = Some("[STR]+ enter[STR] 15.1 15.0".to_string());
-        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes);
-        assert_eq!(Some(result), expected);
+        let expected = Some("enter.1 enter.0 15.1 15.0".to_string());
+        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes, 100).ok();
+        assert_eq!(result, expected);
 
         // Test 5: Test with additional '+' combinations
         let shortcut = "meta+tab+pageup";
         let expected = Some("126.1 15.1 104.1 104.0 15.0 126.0".to_string());
-        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes);
-        assert_eq!(Some(result), expected);
+        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes, 100).ok();
+        assert_eq!(result, expected);
 
         // Test 6: Test empty input
         let shortcut = "";
         let expected = Some("".to_string());
-        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes);
-        assert_eq!(Some(result), expected);
+        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes, 100).ok();
+        assert_eq!(result, expected);
 
         // Test 7: Test plus with space
         let shortcut = "a + b + c";
         let expected = Some("a.1 a.0 +.1 +.0 b.1 b.0 +.1 +.0 c.1 c.0".to_string());
-        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes);
-        assert_eq!(Some(result), expected);
+        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes, 100).ok();
+        assert_eq!(result, expected);
+
+        // Test 7b: Test spaces around '+' after a modifier, which are a mistake
+        let shortcut = "ctrl + c";
+        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes, 100).ok();
+        assert_eq!(result, None);
 
         // Test 8: Test [STR]
         let shortcut = "meta+pageup tab [STR]+ 123! @# [STR] meta+tab";
@@ -264,7 +447,242 @@ mod tests {
             "126.1 104.1 104.0 126.0 15.1 15.0 [STR]+ 123! @#[STR] 126.1 15.1 15.0 126.0"
                 .to_string(),
         );
-        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes);
-        assert_eq!(Some(result), expected);
+        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes, 100).ok();
+        assert_eq!(result, expected);
+
+        // Test 9: Test repeats and their limit
+        let shortcut = "tab*2";
+        let expected = Some("15.1 15.0 15.1 15.0".to_string());
+        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes, 100).ok();
+        assert_eq!(result, expected);
+        let shortcut = "tab*101";
+        let result = convert_shortcut_to_keycode(shortcut, &key_event_codes, 100).ok();
+        assert_eq!(result, None);
+    }
+    /// The events of executing the shortcut
+    fn run(shortcut: &str, delay_ms: u64, key_delay_ms: u64) -> Vec<InputEvent> {
+        let sequence = Sequence::parse(shortcut).unwrap();
+        let recording = RecordingBackend::default();
+        let keymap = HashMap::new();
+        execute_sequence(
+            &mut recording.clone(),
+            &sequence,
+            &keymap,
+            delay_ms,
+            key_delay_ms,
+        )
+        .unwrap();
+        recording.events()
+    }
+
+    #[test]
+    fn test_execute_sequence() {
+        let key = |k: &str, d| InputEvent::Key(k.to_string(), d);
+        let text = |t: &str| InputEvent::Text(t.to_string());
+        assert_eq!(
+            run("ctrl+s [STR]+ hi [STR]", 0, 0),
+            vec![
+                key("ctrl", Direction::Press),
+                key("s", Direction::Press),
+                key("s", Direction::Release),
+                key("ctrl", Direction::Release),
+                text("hi"),
+            ]
+        );
+        // The text is typed as it is, whatever it holds
+        assert_eq!(
+            run("\"see [STR] docs\" [STR]+ a.1 [WAIT]x[STR]", 0, 0),
+            vec![text("see [STR] docs"), text("a.1 [WAIT]x")]
+        );
+        // The delay comes before each text and each block of keys
+        assert_eq!(
+            run("a \"b\" wait(10ms) c", 5, 0),
+            vec![
+                InputEvent::Delay(5),
+                key("a", Direction::Press),
+                key("a", Direction::Release),
+                InputEvent::Delay(5),
+                text("b"),
+                InputEvent::Delay(5),
+                InputEvent::Delay(10),
+                key("c", Direction::Press),
+                key("c", Direction::Release),
+            ]
+        );
+        // The keys are mapped by the keymap
+        let keymap = HashMap::from([("meta".to_string(), "126".to_string())]);
+        let sequence = Sequence::parse("meta").unwrap();
+        let recording = RecordingBackend::default();
+        execute_sequence(&mut recording.clone(), &sequence, &keymap, 0, 0).unwrap();
+        assert_eq!(
+            recording.events(),
+            vec![key("126", Direction::Press), key("126", Direction::Release)]
+        );
+
+        // The values of the placeholders press no key
+        let mut sequence = Sequence::parse("\"git checkout {{branch}}\"").unwrap();
+        let values = HashMap::from([("branch".to_string(), "x[STR] ctrl.1".to_string())]);
+        sequence.fill(&values);
+        let recording = RecordingBackend::default();
+        execute_sequence(&mut recording.clone(), &sequence, &keymap, 0, 0).unwrap();
+        assert_eq!(recording.events(), vec![text("git checkout x[STR] ctrl.1")]);
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        let variable = |name: &str, arg: Option<&str>| match (name, arg) {
+            ("name", None) => Some(Ok("Liz".to_string())),
+            ("greet", Some(arg)) => Some(Ok(format!("Hi {}", arg))),
+            ("fail", None) => Some(Err("failed".into())),
+            _ => None,
+        };
+        let expand = |text: &str| expand_with(text, variable).unwrap();
+        assert_eq!(expand("{name}, {greet:Bird}!"), "Liz, Hi Bird!");
+        // The braces that start no variable are typed
+        assert_eq!(
+            expand("{} {age} {greet} {{name}} name}"),
+            "{} {age} {greet} {Liz} name}"
+        );
+        assert_eq!(
+            expand(r"\{name} {name\} \{\} C:\n {"),
+            r"{name} {name} {} C:\n {"
+        );
+        assert_eq!(expand("{名字} {greet:{name}}"), "{名字} {greet:Liz}");
+        assert_eq!(
+            expand(r"\name} C:\greet:x} \\name}"),
+            r"\name} C:\greet:x} \\name}"
+        );
+        assert!(expand_with("a {fail} b", variable).is_err());
+
+        let date = Local::now().format("%Y").to_string();
+        assert!(expand_variables("{date}").unwrap().starts_with(&date));
+        assert_eq!(expand_variables("{date:%Y}").unwrap().len(), 4);
+        assert_eq!(expand_variables("{time:%H}").unwrap().len(), 2);
+        assert!(expand_variables("{date:%Q}").is_err());
+        assert!(Uuid::parse_str(&expand_variables("{uuid}").unwrap()).is_ok());
+        assert_ne!(
+            expand_variables("{uuid}").unwrap(),
+            expand_variables("{uuid}").unwrap()
+        );
+        std::env::set_var("LIZ_TEST_VAR", "blue bird");
+        assert_eq!(expand_variables("{env:LIZ_TEST_VAR}").unwrap(), "blue bird");
+        assert!(expand_variables("{env:LIZ_TEST_UNSET_VAR}").is_err());
+        assert_eq!(expand_variables("{env}").unwrap(), "{env}");
+        assert_eq!(
+            expand_variables(r"C:\date} \uuid}").unwrap(),
+            r"C:\date} \uuid}"
+        );
+
+        let text = |t: &str| InputEvent::Text(t.to_string());
+        assert_eq!(
+            run(
+                r#"[STR]+ \{uuid} [STR] "\{date}" "{env:LIZ_TEST_VAR}""#,
+                0,
+                0
+            ),
+            vec![text("{uuid}"), text("{date}"), text("blue bird")]
+        );
+
+        // The values of the placeholders are typed as they are
+        let mut sequence = Sequence::parse("\"{{note}} {uuid}\"").unwrap();
+        let values = HashMap::from([("note".to_string(), r"{uuid} \{".to_string())]);
+        sequence.fill(&values);
+        let recording = RecordingBackend::default();
+        execute_sequence(&mut recording.clone(), &sequence, &HashMap::new(), 0, 0).unwrap();
+        let events = recording.events();
+        let [InputEvent::Text(typed)] = &events[..] else {
+            panic!("Expect one text, got {:?}", events);
+        };
+        assert!(typed.starts_with(r"{uuid} \{ "));
+        assert!(Uuid::parse_str(&typed[r"{uuid} \{ ".len()..]).is_ok());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_execute_timing() {
+        let run = |shortcut: &str, key_delay_ms| run(shortcut, 0, key_delay_ms);
+        let key = |k: &str, d| InputEvent::Key(k.to_string(), d);
+        assert_eq!(
+            run("hold(shift, 500ms) wait(250ms) a", 10),
+            vec![
+                key("shift", Direction::Press),
+                InputEvent::Delay(500),
+                InputEvent::Delay(10),
+                key("shift", Direction::Release),
+                InputEvent::Delay(250),
+                InputEvent::Delay(10),
+                key("a", Direction::Press),
+                InputEvent::Delay(10),
+                key("a", Direction::Release),
+            ]
+        );
+        // The delay of the shortcut wins, and lasts after the text
+        assert_eq!(
+            run("keydelay(30ms) \"hi\" tab", 10),
+            vec![
+                InputEvent::Text("hi".to_string()),
+                key("tab", Direction::Press),
+                InputEvent::Delay(30),
+                key("tab", Direction::Release),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preview_shortcut() {
+        let mut keymap = HashMap::new();
+        keymap.insert("meta".to_string(), "126".to_string());
+        let sequence = Sequence::parse("meta+s \":wq\" [STR]+ hi").unwrap();
+        let preview = preview_shortcut(&sequence, &keymap, BackendKind::Enigo, 5, 0).unwrap();
+        assert_eq!(
+            preview.keycode,
+            "126.1 s.1 s.0 126.0 [STR]+ :wq[STR] [STR]+ hi[STR]"
+        );
+        let press = |k: &str| PreviewEvent::Press { key: k.to_string() };
+        let release = |k: &str| PreviewEvent::Release { key: k.to_string() };
+        let delay = PreviewEvent::Delay { ms: 5 };
+        let type_ = |t: &str| PreviewEvent::Type {
+            text: t.to_string(),
+        };
+        assert_eq!(
+            preview.events,
+            vec![
+                delay.clone(),
+                press("126"),
+                press("s"),
+                release("s"),
+                release("126"),
+                delay.clone(),
+                type_(":wq"),
+                delay.clone(),
+                type_("hi"),
+            ]
+        );
+        let warnings: Vec<(usize, usize, &str)> = preview
+            .warnings
+            .iter()
+            .map(|w| (w.start, w.end, w.message.as_str()))
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![(
+                0,
+                6,
+                "The enigo backend cannot press '126' (meta in the keymap)"
+            ),]
+        );
+
+        // The steps of a repeat are warned about once
+        let sequence = Sequence::parse("(meta)*3").unwrap();
+        let preview = preview_shortcut(&sequence, &keymap, BackendKind::Enigo, 0, 0).unwrap();
+        assert_eq!(preview.warnings.len(), 1);
+        assert_eq!(preview.events.len(), 6);
+
+        let sequence = Sequence::parse("ctrl+v \"café\"").unwrap();
+        let preview = preview_shortcut(&sequence, &keymap, BackendKind::Mock, 0, 0).unwrap();
+        assert!(preview.warnings.is_empty());
+        assert!(!preview
+            .events
+            .iter()
+            .any(|e| matches!(e, PreviewEvent::Delay { .. })));
+    }
+}
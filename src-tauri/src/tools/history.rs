@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+
+use super::db::{DeletedShortcut, Revision, Shortcut};
+
+/// The state of one shortcut before and after a change, None when it is not in data
+#[derive(Debug, Clone)]
+pub struct ShortcutChange {
+    pub id: u128,
+    pub before: Option<Shortcut>,
+    pub after: Option<Shortcut>,
+}
+
+/// All the changes made by one operation, they are undone and redone together
+#[derive(Debug, Clone)]
+pub struct ChangeSet {
+    pub label: String,
+    pub changes: Vec<ShortcutChange>,
+    pub trashed: Vec<DeletedShortcut>, // Entries the operation appended to deleted
+    pub untrashed: Vec<DeletedShortcut>, // Entries the operation took out of deleted
+    pub revised: Vec<Revision>,        // Revisions the operation recorded
+}
+
+impl ChangeSet {
+    pub fn new(label: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            changes: Vec::new(),
+            trashed: Vec::new(),
+            untrashed: Vec::new(),
+            revised: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+            && self.trashed.is_empty()
+            && self.untrashed.is_empty()
+            && self.revised.is_empty()
+    }
+
+    /// Record that one shortcut changed from `before` to `after`
+    pub fn push(&mut self, id: u128, before: Option<Shortcut>, after: Option<Shortcut>) {
+        self.changes.push(ShortcutChange { id, before, after });
+    }
+
+    /// Append the changes of a later operation. A shortcut changed by both keeps
+    /// the state before the first change and the state after the last one.
+    fn merge(&mut self, other: ChangeSet) {
+        for change in other.changes {
+            match self.changes.iter_mut().find(|c| c.id == change.id) {
+                Some(existing) => existing.after = change.after,
+                None => self.changes.push(change),
+            }
+        }
+        self.trashed.extend(other.trashed);
+        self.untrashed.extend(other.untrashed);
+        self.revised.extend(other.revised);
+    }
+}
+
+/// Bounded undo and redo stacks of change sets.
+/// Between `begin_group` and `end_group`, all the recorded change sets become one step.
+#[derive(Debug)]
+pub struct History {
+    undo: VecDeque<ChangeSet>,
+    redo: Vec<ChangeSet>,
+    depth: usize,
+    group: Option<ChangeSet>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(50)
+    }
+}
+
+impl History {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            depth,
+            group: None,
+        }
+    }
+
+    /// Change how many steps can be undone, 0 disables the history
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.undo.len() > depth {
+            self.undo.pop_front();
+        }
+        self.redo.truncate(depth);
+    }
+
+    pub fn begin_group(&mut self, label: &str) {
+        self.end_group();
+        self.group = Some(ChangeSet::new(label));
+    }
+
+    pub fn end_group(&mut self) {
+        if let Some(group) = self.group.take() {
+            self.push_new(group);
+        }
+    }
+
+    /// Record the change set of a new operation
+    pub fn record(&mut self, change_set: ChangeSet) {
+        match self.group.as_mut() {
+            Some(group) => group.merge(change_set),
+            None => self.push_new(change_set),
+        }
+    }
+
+    /// A new operation invalidates everything that was undone
+    fn push_new(&mut self, change_set: ChangeSet) {
+        if change_set.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        self.push_undo(change_set);
+    }
+
+    pub fn push_undo(&mut self, change_set: ChangeSet) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.undo.len() >= self.depth {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(change_set);
+    }
+
+    pub fn pop_undo(&mut self) -> Option<ChangeSet> {
+        self.end_group();
+        self.undo.pop_back()
+    }
+
+    pub fn push_redo(&mut self, change_set: ChangeSet) {
+        self.redo.push(change_set);
+    }
+
+    pub fn pop_redo(&mut self) -> Option<ChangeSet> {
+        self.end_group();
+        self.redo.pop()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.group = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tools::db::{MusicSheetDB, Shortcut};
+
+    fn shortcut(desc: &str) -> Shortcut {
+        Shortcut {
+            description: desc.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut db = MusicSheetDB::new();
+        let a = shortcut("a");
+        db.add_shortcuts(vec![a.clone()], None);
+        db.update_shortcuts(vec![Shortcut {
+            description: "a2".to_string(),
+            ..a.clone()
+        }]);
+        db.delete_shortcuts(vec![a.id]);
+        assert!(db.retrieve(a.id, None).is_none());
+        assert_eq!(db.retrieve_deleted().len(), 1);
+        assert_eq!(db.retrieve_revisions(a.id).len(), 1);
+
+        assert_eq!(db.undo().as_deref(), Some("delete"));
+        assert_eq!(db.retrieve(a.id, None).unwrap().description, "a2");
+        assert!(db.retrieve_deleted().is_empty());
+        assert_eq!(db.undo().as_deref(), Some("update"));
+        assert_eq!(db.retrieve(a.id, None).unwrap().description, "a");
+        assert!(db.retrieve_revisions(a.id).is_empty());
+        assert_eq!(db.undo().as_deref(), Some("add"));
+        assert!(db.retrieve_all().is_empty());
+        assert!(db.undo().is_none());
+
+        assert_eq!(db.redo().as_deref(), Some("add"));
+        assert_eq!(db.redo().as_deref(), Some("update"));
+        assert_eq!(db.retrieve(a.id, None).unwrap().description, "a2");
+        assert_eq!(db.retrieve_revisions(a.id).len(), 1);
+
+        // A new change drops what is left to redo
+        db.add_shortcuts(vec![shortcut("b")], None);
+        assert!(db.redo().is_none());
+    }
+
+    #[test]
+    fn test_undo_group_and_depth() {
+        let mut db = MusicSheetDB::new();
+        db.set_undo_depth(2);
+        db.begin_change_group("import");
+        db.add_shortcuts(vec![shortcut("a"), shortcut("b")], None);
+        db.add_shortcuts(vec![shortcut("c")], None);
+        db.end_change_group();
+        assert_eq!(db.retrieve_all().len(), 3);
+        assert_eq!(db.undo().as_deref(), Some("import"));
+        assert!(db.retrieve_all().is_empty());
+        db.redo();
+
+        for desc in ["d", "e", "f"] {
+            db.add_shortcuts(vec![shortcut(desc)], None);
+        }
+        assert!(db.undo().is_some());
+        assert!(db.undo().is_some());
+        assert!(db.undo().is_none());
+        assert_eq!(db.retrieve_all().len(), 4);
+    }
+
+    #[test]
+    fn test_undo_restore() {
+        let mut db = MusicSheetDB::new();
+        let a = shortcut("a");
+        db.add_shortcuts(vec![a.clone()], None);
+        db.delete_shortcuts(vec![a.id]);
+        assert!(db.restore_shortcuts(&[a.id]).is_empty());
+        assert!(db.retrieve(a.id, None).is_some());
+        assert!(db.retrieve_deleted().is_empty());
+
+        assert_eq!(db.undo().as_deref(), Some("restore"));
+        assert!(db.retrieve(a.id, None).is_none());
+        assert_eq!(db.retrieve_deleted().len(), 1);
+        assert_eq!(db.redo().as_deref(), Some("restore"));
+        assert!(db.retrieve(a.id, None).is_some());
+        assert!(db.retrieve_deleted().is_empty());
+        assert!(db.restore_shortcuts(&[a.id]).contains(&a.id));
+    }
+
+    #[test]
+    fn test_undo_keeps_usage() {
+        let mut db = MusicSheetDB::new();
+        let a = shortcut("a");
+        db.add_shortcuts(vec![a.clone()], None);
+        db.update_shortcuts(vec![Shortcut {
+            description: "a2".to_string(),
+            ..a.clone()
+        }]);
+        db.record_hit(a.id, 3600).unwrap();
+        db.record_hit(a.id, 3600).unwrap();
+        let hit = db.retrieve(a.id, None).unwrap().clone();
+        assert_eq!(hit.hit_number, 2);
+
+        assert_eq!(db.undo().as_deref(), Some("update"));
+        let undone = db.retrieve(a.id, None).unwrap();
+        assert_eq!(undone.description, "a");
+        assert_eq!(undone.hit_number, 2);
+        assert_eq!(undone.last_used, hit.last_used);
+        assert_eq!(undone.frecency, hit.frecency);
+        db.redo();
+        assert_eq!(db.retrieve(a.id, None).unwrap().description, "a2");
+        assert_eq!(db.retrieve(a.id, None).unwrap().hit_number, 2);
+    }
+}
@@ -0,0 +1,443 @@
+use pinyin::ToPinyin;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasherDefault, Hasher};
+
+use super::db::Shortcut;
+use super::matcher::lower;
+use super::query::{searched_fields, Filter, Query};
+
+/// Hash the ids by mixing their bits once, they are random already (or small in tests).
+/// The slots of the shortcuts are looked up by id on every change, and their positions in
+/// data for every shortcut the index finds.
+#[derive(Default)]
+pub struct IdHasher(u64);
+
+impl Hasher for IdHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = (self.0.rotate_left(8) ^ *b as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        }
+    }
+
+    fn write_u128(&mut self, n: u128) {
+        self.0 = ((n as u64) ^ ((n >> 64) as u64)).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    }
+}
+
+type BuildIdHasher = BuildHasherDefault<IdHasher>;
+
+/// A map from the ids of the shortcuts, hashed by `IdHasher`
+pub type IdMap<V> = HashMap<u128, V, BuildIdHasher>;
+
+#[derive(Debug)]
+struct Slot {
+    id: u128,
+    spelling: (usize, usize), // The byte range of its spelling in `spellings`
+}
+
+/// An inverted index from the chars of the shortcuts to the shortcuts containing them,
+/// with the spelling of every shortcut.
+///
+/// A fuzzy term only matches a text containing all its chars in the same order, and a phrase
+/// or a field filter only matches a text containing all its chars, so most shortcuts that
+/// cannot be found are skipped without matching anything: the postings of the chars of the
+/// query narrow the shortcuts down, then the terms are checked against the spelling.
+///
+/// Every indexed shortcut gets a new slot, the postings are lists of slots in increasing
+/// order. Removing a shortcut only frees its slot, the postings are compacted once half
+/// of the slots are free.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    slots: Vec<Option<Slot>>,          // None once the shortcut is removed
+    spellings: String,                 // The spellings of the slots one after another
+    slot_of: IdMap<u32>,               // The slot of each shortcut
+    postings: HashMap<char, Vec<u32>>, // The slots of the shortcuts containing the char
+    free: usize,                       // How many slots are None
+}
+
+/// The shortcuts a query may find, see `SearchIndex::lookup`
+pub struct Lookup {
+    ids: Vec<u128>,
+}
+
+impl Lookup {
+    pub fn ids(&self) -> &[u128] {
+        &self.ids
+    }
+}
+
+/// Whether the bytes of the term appear in the spelling in the same order, both in lower case.
+/// It holds for the bytes whenever it holds for the chars.
+fn in_order(term: &str, spelling: &str) -> bool {
+    let mut rest = spelling.as_bytes();
+    term.bytes()
+        .all(|b| match rest.iter().position(|c| *c == b) {
+            Some(i) => {
+                rest = &rest[i + 1..];
+                true
+            }
+            None => false,
+        })
+}
+
+/// The text fields, with the tags joined as they are searched
+fn texts(sc: &Shortcut) -> impl Iterator<Item = Cow<'_, str>> {
+    let searched = searched_fields(sc).into_iter().map(|(_, text)| text);
+    searched.chain([Cow::Borrowed(sc.comment.as_str())])
+}
+
+/// The text fields in lower case, each Chinese char followed by its pinyin,
+/// so that both `fuzzy_match` and `pinyin_match` only match subsequences of it
+fn spelling(sc: &Shortcut) -> String {
+    let mut spelling = String::new();
+    for text in texts(sc) {
+        for c in text.chars() {
+            spelling.push(lower(c));
+            if let Some(p) = c.to_pinyin() {
+                spelling.push_str(p.plain());
+            }
+        }
+    }
+    spelling
+}
+
+/// The chars every shortcut found by the query contains
+fn query_chars(query: &Query) -> HashSet<char> {
+    let mut chars: HashSet<char> = HashSet::new();
+    for term in query.terms.iter() {
+        chars.extend(term.chars().map(lower));
+    }
+    for clause in query.clauses.iter().filter(|c| !c.negated) {
+        match &clause.filter {
+            Filter::Field(_, text) | Filter::Tag(text) | Filter::Text(text) => {
+                chars.extend(text.chars())
+            }
+            Filter::Hits(..) => {}
+        }
+    }
+    chars.retain(|c| !c.is_whitespace());
+    chars
+}
+
+/// The slots in both sorted lists, `a` being the shorter one.
+/// The position in `b` moves forward by galloping, so a short `a` skips most of `b`.
+fn intersect(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut both: Vec<u32> = Vec::with_capacity(a.len());
+    let mut rest = b;
+    for slot in a {
+        let mut step = 1;
+        while step < rest.len() && rest[step] < *slot {
+            step *= 2;
+        }
+        let skipped = rest[..(step + 1).min(rest.len())].partition_point(|s| s < slot);
+        rest = &rest[skipped..];
+        match rest.first() {
+            None => break,
+            Some(s) if s == slot => both.push(*slot),
+            Some(_) => {}
+        }
+    }
+    both
+}
+
+impl SearchIndex {
+    pub fn new(shortcuts: &[Shortcut]) -> Self {
+        let mut index = Self::default();
+        for sc in shortcuts {
+            index.insert(sc);
+        }
+        index
+    }
+
+    /// Index a new shortcut, or index it again if it changed
+    pub fn insert(&mut self, sc: &Shortcut) {
+        self.remove(sc.id);
+        let slot = self.slots.len() as u32;
+        let spelling = spelling(sc);
+        // Field filters compare the whole text in lower case, a few chars get longer then
+        let mut chars: HashSet<char> = spelling.chars().collect();
+        for text in texts(sc) {
+            chars.extend(
+                text.chars()
+                    .filter(|c| !c.is_ascii())
+                    .flat_map(|c| c.to_lowercase()),
+            );
+        }
+        for c in chars.into_iter().filter(|c| !c.is_whitespace()) {
+            // The slot is the largest one, so the postings stay sorted
+            self.postings.entry(c).or_default().push(slot);
+        }
+        let start = self.spellings.len();
+        self.spellings.push_str(&spelling);
+        self.slots.push(Some(Slot {
+            id: sc.id,
+            spelling: (start, self.spellings.len()),
+        }));
+        self.slot_of.insert(sc.id, slot);
+    }
+
+    pub fn remove(&mut self, id: u128) {
+        let Some(slot) = self.slot_of.remove(&id) else {
+            return;
+        };
+        self.slots[slot as usize] = None;
+        self.free += 1;
+        if self.free * 2 > self.slots.len() {
+            self.compact();
+        }
+    }
+
+    /// Drop the free slots, and renumber the others in the same order
+    fn compact(&mut self) {
+        let mut renumbered: Vec<Option<u32>> = Vec::with_capacity(self.slots.len());
+        let mut slots: Vec<Option<Slot>> = Vec::with_capacity(self.slot_of.len());
+        let mut spellings = String::new();
+        for slot in std::mem::take(&mut self.slots) {
+            match slot {
+                Some(slot) => {
+                    renumbered.push(Some(slots.len() as u32));
+                    self.slot_of.insert(slot.id, slots.len() as u32);
+                    let start = spellings.len();
+                    spellings.push_str(&self.spellings[slot.spelling.0..slot.spelling.1]);
+                    slots.push(Some(Slot {
+                        id: slot.id,
+                        spelling: (start, spellings.len()),
+                    }));
+                }
+                None => renumbered.push(None),
+            }
+        }
+        for list in self.postings.values_mut() {
+            *list = list
+                .iter()
+                .filter_map(|slot| renumbered[*slot as usize])
+                .collect();
+        }
+        self.postings.retain(|_, list| !list.is_empty());
+        self.slots = slots;
+        self.spellings = spellings;
+        self.free = 0;
+    }
+
+    /// Look up the shortcuts the query may find, they have all the chars of the query
+    /// and every fuzzy term is a subsequence of their spelling.
+    /// When even the rarest char of the query is in most shortcuts, the postings are not
+    /// worth intersecting and all the spellings are checked. So are the postings of the chars
+    /// in most shortcuts, checking the spellings of the few they leave out is faster.
+    pub fn lookup(&self, query: &Query) -> Lookup {
+        let terms: Vec<String> = query
+            .terms
+            .iter()
+            .map(|t| t.chars().map(lower).collect())
+            .collect();
+        let mut lists: Vec<&[u32]> = Vec::new();
+        for c in query_chars(query) {
+            lists.push(self.postings.get(&c).map_or(&[], |list| list.as_slice()));
+        }
+        lists.sort_by_key(|list| list.len());
+        let may_find = |slot: &&Slot| {
+            let spelling = &self.spellings[slot.spelling.0..slot.spelling.1];
+            terms.iter().all(|t| in_order(t, spelling))
+        };
+        let ids = match lists.split_first() {
+            Some((shortest, others)) if shortest.len() * 2 <= self.slot_of.len() => {
+                let mut found: Vec<u32> = shortest.to_vec();
+                for list in others.iter().filter(|l| l.len() * 2 <= self.slot_of.len()) {
+                    found = intersect(&found, list);
+                }
+                found
+                    .iter()
+                    .filter_map(|slot| self.slots[*slot as usize].as_ref())
+                    .filter(may_find)
+                    .map(|slot| slot.id)
+                    .collect()
+            }
+            _ => self
+                .slots
+                .iter()
+                .flatten()
+                .filter(may_find)
+                .map(|slot| slot.id)
+                .collect(),
+        };
+        Lookup { ids }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::db::{MusicSheetDB, SearchHit};
+    use std::time::{Duration, Instant};
+
+    fn shortcut(desc: &str, app: &str) -> Shortcut {
+        Shortcut {
+            description: desc.to_string(),
+            application: app.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_index_lookup() {
+        let copy = shortcut("Copy line", "vscode");
+        let save = shortcut("保存文件", "vim");
+        let mut index = SearchIndex::new(&[copy.clone(), save.clone()]);
+        let found = |index: &SearchIndex, q: &str| -> Vec<bool> {
+            let lookup = index.lookup(&Query::parse(q).unwrap());
+            [copy.id, save.id]
+                .iter()
+                .map(|id| lookup.ids().contains(id))
+                .collect()
+        };
+        assert_eq!(found(&index, "CL"), vec![true, false]);
+        assert_eq!(found(&index, "ep"), vec![false, false]); // Not in this order
+        assert_eq!(found(&index, "baocun"), vec![false, true]);
+        assert_eq!(found(&index, "app:vim 文"), vec![false, true]);
+        assert_eq!(found(&index, "xyz"), vec![false, false]);
+        assert_eq!(found(&index, "hits:>1 -z"), vec![true, true]);
+
+        // Changed and removed shortcuts are found by their new content only
+        index.insert(&Shortcut {
+            description: "Paste".to_string(),
+            ..copy.clone()
+        });
+        assert_eq!(found(&index, "cl"), vec![false, false]);
+        assert_eq!(found(&index, "paste"), vec![true, false]);
+        index.remove(save.id);
+        assert_eq!(index.slots.len(), 1); // Compacted once half of the slots were free
+        assert_eq!(found(&index, "pa"), vec![true, false]);
+        assert_eq!(found(&index, "bc"), vec![false, false]);
+
+        // The tags are joined as they are searched
+        let tagged = Shortcut {
+            tags: vec!["git".to_string(), "review".to_string()],
+            ..shortcut("Push", "terminal")
+        };
+        let index = SearchIndex::new(std::slice::from_ref(&tagged));
+        let lookup = index.lookup(&Query::parse(r#""git, review""#).unwrap());
+        assert_eq!(lookup.ids(), &[tagged.id]);
+    }
+
+    /// A sheet of `count` shortcuts with a common verb and two words out of 4096 each,
+    /// so that a word is in about `count / 2000` shortcuts
+    fn large_sheet(count: usize) -> MusicSheetDB {
+        let verbs = [
+            "copy", "paste", "close", "open", "save", "move", "select", "delete",
+        ];
+        let syllables = [
+            "ka", "lo", "mi", "ne", "ru", "sa", "te", "vo", "pi", "da", "fe", "gu", "ho", "ji",
+            "ze", "wa",
+        ];
+        let apps = [
+            "vim", "vscode", "firefox", "chrome", "terminal", "idea", "emacs", "slack",
+        ];
+        let mut state: u64 = 42;
+        let mut next = |n: usize| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as usize % n
+        };
+        let shortcuts: Vec<Shortcut> = (0..count)
+            .map(|i| {
+                let mut word = || -> String { (0..3).map(|_| syllables[next(16)]).collect() };
+                Shortcut {
+                    description: format!("{} {} {}", verbs[i % verbs.len()], word(), word()),
+                    application: format!("{}{}", apps[i % apps.len()], i % 100),
+                    shortcut: format!("<ctrl>+<shift>+{}", (b'a' + (i % 26) as u8) as char),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let mut db = MusicSheetDB::new();
+        let start = Instant::now();
+        db.add_shortcuts(shortcuts, Some(false));
+        println!("Indexed {} shortcuts in {:?}", count, start.elapsed());
+        db
+    }
+
+    /// How long it takes on average to find the best `limit` hits of the query
+    fn time_search(db: &MusicSheetDB, q: &str, limit: Option<usize>) -> Duration {
+        let query = Query::parse(q).unwrap();
+        db.search_top(&query, limit); // The positions of the shortcuts are cached after a change
+        let rounds = 5;
+        let start = Instant::now();
+        let mut found = (0, 0);
+        for _ in 0..rounds {
+            let (hits, total) = db.search_top(&query, limit);
+            found = (hits.len(), total);
+        }
+        let elapsed = start.elapsed() / rounds;
+        println!(
+            "{:>20}: {:>6} of {:>6} hits in {:?}",
+            q, found.0, found.1, elapsed
+        );
+        elapsed
+    }
+
+    /// The index keeps searching a large sheet fast, the bound leaves room for a debug build
+    /// on a slow machine. Run with `cargo test --release test_search_speed -- --nocapture`
+    /// for the timings.
+    #[test]
+    fn test_search_speed() {
+        let db = large_sheet(20_000);
+        for q in [
+            "kalomi",
+            "vohoze",
+            "copy kalo",
+            "app:vim40 nesa",
+            "kalo tegu",
+            "qqq",
+        ] {
+            let elapsed = time_search(&db, q, None);
+            assert!(elapsed.as_millis() < 100, "{} took {:?}", q, elapsed);
+        }
+        // Queries finding a large part of the sheet take longer, the hits are all ranked
+        for q in ["kalo", "copy"] {
+            time_search(&db, q, None);
+        }
+    }
+
+    /// A page of hits is found in less than 10ms in a sheet of 100k shortcuts, even for the
+    /// queries finding most of it. Run with
+    /// `cargo test --release bench_search_100k -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_search_100k() {
+        let db = large_sheet(100_000);
+        for q in [
+            "kalomi",
+            "vohoze",
+            "copy kalo",
+            "app:vim40 nesa",
+            "kalo tegu",
+            "qqq",
+            "kalo",
+            "copy",
+            "e",
+        ] {
+            let elapsed = time_search(&db, q, Some(50));
+            assert!(elapsed.as_millis() < 10, "{} took {:?}", q, elapsed);
+        }
+    }
+
+    #[test]
+    fn test_search_top() {
+        let db = large_sheet(2_000);
+        for q in ["kalo", "copy", "e", "app:vim4 s"] {
+            let query = Query::parse(q).unwrap();
+            let all = db.search(&query);
+            let (top, total) = db.search_top(&query, Some(20));
+            assert_eq!(total, all.len());
+            let ids =
+                |hits: &[SearchHit]| -> Vec<u128> { hits.iter().map(|h| h.shortcut.id).collect() };
+            assert_eq!(ids(&top), ids(&all[..20.min(all.len())]), "{}", q);
+        }
+    }
+}
@@ -0,0 +1,623 @@
+use std::error::Error;
+use std::fmt;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::Duration;
+
+use enigo::{Enigo, Keyboard, Settings};
+
+use super::exec::string_to_key;
+
+/// Whether a key goes down or up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Press,
+    Release,
+}
+
+/// One event sent to an input backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(String, Direction),
+    Text(String),
+    Delay(u64), // In milliseconds
+}
+
+/// Presses the keys and types the text of a shortcut.
+/// The keys are named as in the shortcuts, such as "ctrl", "pageup" or "a",
+/// or are the keycodes of the keymap.
+/// It is `Send` to be kept open in the flute, see `OpenBackend`.
+pub trait InputBackend: Send {
+    fn key(&mut self, key: &str, direction: Direction) -> Result<(), Box<dyn Error>>;
+    fn text(&mut self, text: &str) -> Result<(), Box<dyn Error>>;
+
+    fn delay(&mut self, ms: u64) {
+        sleep(Duration::from_millis(ms));
+    }
+}
+
+/// Which backend presses the keys, see `input_backend` in Rhythm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Enigo,
+    Xdotool,
+    Uinput,
+    Mock,
+}
+
+impl BackendKind {
+    /// Parse the backend in Rhythm, unknown values are treated as "enigo"
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "xdotool" => BackendKind::Xdotool,
+            "uinput" => BackendKind::Uinput,
+            "mock" => BackendKind::Mock,
+            _ => BackendKind::Enigo,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BackendKind::Enigo => "enigo",
+            BackendKind::Xdotool => "xdotool",
+            BackendKind::Uinput => "uinput",
+            BackendKind::Mock => "mock",
+        }
+    }
+
+    /// Whether the backend can press the key, without opening it
+    pub fn can_press(self, key: &str) -> bool {
+        match self {
+            BackendKind::Enigo => string_to_key(key).is_some(),
+            BackendKind::Xdotool => xdotool_keysym(key).is_some(),
+            #[cfg(target_os = "linux")]
+            BackendKind::Uinput => {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) => uinput_char(ch).is_some(),
+                    _ => uinput_code(key).is_some(),
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            BackendKind::Uinput => false,
+            BackendKind::Mock => true,
+        }
+    }
+
+    /// Whether the backend can type the char, only uinput is limited to a US keyboard
+    pub fn can_type(self, ch: char) -> bool {
+        match self {
+            #[cfg(target_os = "linux")]
+            BackendKind::Uinput => uinput_char(ch).is_some(),
+            #[cfg(not(target_os = "linux"))]
+            BackendKind::Uinput => false,
+            _ => true,
+        }
+    }
+
+    pub fn open(self) -> Result<Box<dyn InputBackend>, Box<dyn Error>> {
+        Ok(match self {
+            BackendKind::Enigo => Box::new(EnigoBackend::new()?),
+            BackendKind::Xdotool => Box::new(XdotoolBackend),
+            BackendKind::Uinput => Box::new(UinputBackend::new()?),
+            BackendKind::Mock => Box::new(RecordingBackend::default()),
+        })
+    }
+}
+
+/// An input backend opened once and kept for the next shortcuts,
+/// as opening one may take a while (uinput waits for the device to be ready)
+pub struct OpenBackend {
+    pub kind: BackendKind,
+    pub backend: Box<dyn InputBackend>,
+}
+
+impl OpenBackend {
+    pub fn open(kind: BackendKind) -> Result<Self, Box<dyn Error>> {
+        Ok(OpenBackend {
+            kind,
+            backend: kind.open()?,
+        })
+    }
+}
+
+impl fmt::Debug for OpenBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenBackend")
+            .field("kind", &self.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sends the events with enigo, which works on Windows, macOS and X11
+pub struct EnigoBackend(Enigo);
+
+impl EnigoBackend {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        Ok(EnigoBackend(Enigo::new(&Settings::default())?))
+    }
+}
+
+// The event source of enigo on macOS is not marked `Send`, it is only used by the thread
+// holding the flute, one at a time
+#[cfg(target_os = "macos")]
+unsafe impl Send for EnigoBackend {}
+
+impl InputBackend for EnigoBackend {
+    fn key(&mut self, key: &str, direction: Direction) -> Result<(), Box<dyn Error>> {
+        let key = string_to_key(key).ok_or_else(|| format!("Unknown key: '{}'", key))?;
+        let direction = match direction {
+            Direction::Press => enigo::Direction::Press,
+            Direction::Release => enigo::Direction::Release,
+        };
+        self.0.key(key, direction)?;
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        self.0.text(text)?;
+        Ok(())
+    }
+}
+
+/// Runs the `xdotool` command for every event, for X11 and XWayland apps
+pub struct XdotoolBackend;
+
+/// The X keysym of a key name
+fn xdotool_keysym(key: &str) -> Option<String> {
+    let keysym = match key.to_lowercase().as_str() {
+        "ctrl" | "control" => "ctrl",
+        "alt" => "alt",
+        "shift" => "shift",
+        "win" | "meta" | "cmd" => "super",
+        "enter" | "return" => "Return",
+        "esc" | "escape" => "Escape",
+        "space" => "space",
+        "tab" => "Tab",
+        "backspace" => "BackSpace",
+        "up" => "Up",
+        "down" => "Down",
+        "left" => "Left",
+        "right" => "Right",
+        "home" => "Home",
+        "end" => "End",
+        "pageup" => "Prior",
+        "pagedown" => "Next",
+        "delete" => "Delete",
+        "insert" => "Insert",
+        "capslock" => "Caps_Lock",
+        "f1" | "f2" | "f3" | "f4" | "f5" | "f6" | "f7" | "f8" | "f9" | "f10" | "f11" | "f12" => {
+            return Some(key.to_uppercase())
+        }
+        _ => {
+            let mut chars = key.chars();
+            let ch = chars.next()?;
+            if chars.next().is_some() {
+                return None;
+            }
+            if ch.is_ascii_alphanumeric() {
+                return Some(ch.to_string());
+            }
+            match ch {
+                '!' => "exclam",
+                '"' => "quotedbl",
+                '#' => "numbersign",
+                '$' => "dollar",
+                '%' => "percent",
+                '&' => "ampersand",
+                '\'' => "apostrophe",
+                '(' => "parenleft",
+                ')' => "parenright",
+                '*' => "asterisk",
+                '+' => "plus",
+                ',' => "comma",
+                '-' => "minus",
+                '.' => "period",
+                '/' => "slash",
+                ':' => "colon",
+                ';' => "semicolon",
+                '<' => "less",
+                '=' => "equal",
+                '>' => "greater",
+                '?' => "question",
+                '@' => "at",
+                '[' => "bracketleft",
+                '\\' => "backslash",
+                ']' => "bracketright",
+                '^' => "asciicircum",
+                '_' => "underscore",
+                '`' => "grave",
+                '{' => "braceleft",
+                '|' => "bar",
+                '}' => "braceright",
+                '~' => "asciitilde",
+                _ => return None,
+            }
+        }
+    };
+    Some(keysym.to_string())
+}
+
+fn run_xdotool(args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let output = Command::new("xdotool")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run xdotool: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("xdotool {} failed: {}", args[0], stderr.trim()).into());
+    }
+    Ok(())
+}
+
+impl InputBackend for XdotoolBackend {
+    fn key(&mut self, key: &str, direction: Direction) -> Result<(), Box<dyn Error>> {
+        let keysym = xdotool_keysym(key).ok_or_else(|| format!("Unknown key: '{}'", key))?;
+        let action = match direction {
+            Direction::Press => "keydown",
+            Direction::Release => "keyup",
+        };
+        run_xdotool(&[action, &keysym])
+    }
+
+    fn text(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        run_xdotool(&["type", "--delay", "0", "--", text])
+    }
+}
+
+/// Records the events instead of sending them, for tests and trying shortcuts out.
+/// The clones share the recorded events.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingBackend {
+    events: Arc<Mutex<Vec<InputEvent>>>,
+}
+
+impl RecordingBackend {
+    pub fn events(&self) -> Vec<InputEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl InputBackend for RecordingBackend {
+    fn key(&mut self, key: &str, direction: Direction) -> Result<(), Box<dyn Error>> {
+        let event = InputEvent::Key(key.to_string(), direction);
+        self.events.lock().unwrap().push(event);
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        let event = InputEvent::Text(text.to_string());
+        self.events.lock().unwrap().push(event);
+        Ok(())
+    }
+
+    fn delay(&mut self, ms: u64) {
+        self.events.lock().unwrap().push(InputEvent::Delay(ms));
+    }
+}
+
+// The codes of linux/input-event-codes.h
+#[cfg(target_os = "linux")]
+mod codes {
+    pub const EV_SYN: u16 = 0x00;
+    pub const EV_KEY: u16 = 0x01;
+    pub const SYN_REPORT: u16 = 0;
+    pub const KEY_MAX: u16 = 255; // The last key of the virtual keyboard
+    pub const KEY_LEFTCTRL: u16 = 29;
+    pub const KEY_LEFTSHIFT: u16 = 42;
+    pub const KEY_LEFTALT: u16 = 56;
+    pub const KEY_LEFTMETA: u16 = 125;
+}
+
+/// The keycode of a key name, keycodes of the keymap are used as they are
+#[cfg(target_os = "linux")]
+fn uinput_code(key: &str) -> Option<u16> {
+    use codes::*;
+
+    if key.len() > 1 {
+        if let Ok(code) = key.parse::<u16>() {
+            return (1..=KEY_MAX).contains(&code).then_some(code);
+        }
+    }
+    let code = match key.to_lowercase().as_str() {
+        "ctrl" | "control" => KEY_LEFTCTRL,
+        "alt" => KEY_LEFTALT,
+        "shift" => KEY_LEFTSHIFT,
+        "win" | "meta" | "cmd" => KEY_LEFTMETA,
+        "enter" | "return" => 28,
+        "esc" | "escape" => 1,
+        "space" => 57,
+        "tab" => 15,
+        "backspace" => 14,
+        "up" => 103,
+        "down" => 108,
+        "left" => 105,
+        "right" => 106,
+        "home" => 102,
+        "end" => 107,
+        "pageup" => 104,
+        "pagedown" => 109,
+        "delete" => 111,
+        "insert" => 110,
+        "capslock" => 58,
+        "f1" => 59,
+        "f2" => 60,
+        "f3" => 61,
+        "f4" => 62,
+        "f5" => 63,
+        "f6" => 64,
+        "f7" => 65,
+        "f8" => 66,
+        "f9" => 67,
+        "f10" => 68,
+        "f11" => 87,
+        "f12" => 88,
+        _ => {
+            let mut chars = key.chars();
+            let ch = chars.next()?;
+            return match chars.next() {
+                None => uinput_char(ch).map(|(code, _)| code),
+                Some(_) => None,
+            };
+        }
+    };
+    Some(code)
+}
+
+/// The keycode of a char on a US keyboard, and whether shift is needed for it
+#[cfg(target_os = "linux")]
+fn uinput_char(ch: char) -> Option<(u16, bool)> {
+    const ROWS: [(&str, &str, u16); 4] = [
+        ("1234567890-=", "!@#$%^&*()_+", 2),
+        ("qwertyuiop[]", "QWERTYUIOP{}", 16),
+        ("asdfghjkl;'`", "ASDFGHJKL:\"~", 30),
+        ("\\zxcvbnm,./", "|ZXCVBNM<>?", 43),
+    ];
+    for (plain, shifted, first) in ROWS {
+        if let Some(i) = plain.chars().position(|c| c == ch) {
+            return Some((first + i as u16, false));
+        }
+        if let Some(i) = shifted.chars().position(|c| c == ch) {
+            return Some((first + i as u16, true));
+        }
+    }
+    match ch {
+        ' ' => Some((57, false)),
+        '\n' => Some((28, false)),
+        '\t' => Some((15, false)),
+        _ => None,
+    }
+}
+
+/// A virtual keyboard made with /dev/uinput, which works under Wayland too.
+/// The user needs write access to /dev/uinput, e.g. by a udev rule. The text is
+/// typed as on a US keyboard, so only ASCII chars can be typed.
+#[cfg(target_os = "linux")]
+pub struct UinputBackend {
+    device: std::fs::File,
+}
+
+#[cfg(target_os = "linux")]
+impl UinputBackend {
+    // The ioctls of linux/uinput.h
+    const UI_SET_EVBIT: u64 = 0x4004_5564;
+    const UI_SET_KEYBIT: u64 = 0x4004_5565;
+    const UI_DEV_CREATE: u64 = 0x5501;
+    const UI_DEV_DESTROY: u64 = 0x5502;
+
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        // struct uinput_user_dev
+        #[repr(C)]
+        struct UserDev {
+            name: [u8; 80],
+            id: [u16; 4], // bustype, vendor, product, version
+            ff_effects_max: u32,
+            absmax: [i32; 64],
+            absmin: [i32; 64],
+            absfuzz: [i32; 64],
+            absflat: [i32; 64],
+        }
+
+        let device = std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/uinput")
+            .map_err(|e| format!("Failed to open /dev/uinput: {}", e))?;
+        let backend = UinputBackend { device };
+        backend.ioctl(Self::UI_SET_EVBIT, codes::EV_KEY.into())?;
+        for code in 1..=codes::KEY_MAX {
+            backend.ioctl(Self::UI_SET_KEYBIT, code.into())?;
+        }
+        let mut dev = UserDev {
+            name: [0; 80],
+            id: [0x06, 0x1, 0x1, 1], // BUS_VIRTUAL
+            ff_effects_max: 0,
+            absmax: [0; 64],
+            absmin: [0; 64],
+            absfuzz: [0; 64],
+            absflat: [0; 64],
+        };
+        dev.name[..13].copy_from_slice(b"Liz shortcuts");
+        // SAFETY: UserDev is plain old data laid out as the kernel expects
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                &dev as *const UserDev as *const u8,
+                std::mem::size_of::<UserDev>(),
+            )
+        };
+        (&backend.device).write_all(bytes)?;
+        backend.ioctl(Self::UI_DEV_CREATE, 0)?;
+        // Give the compositor some time to pick up the new keyboard
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        Ok(backend)
+    }
+
+    fn ioctl(&self, request: u64, arg: libc::c_int) -> Result<(), Box<dyn Error>> {
+        use std::os::unix::io::AsRawFd;
+
+        // SAFETY: the uinput ioctls used here take an int by value
+        let r = unsafe { libc::ioctl(self.device.as_raw_fd(), request as _, arg) };
+        if r < 0 {
+            return Err(format!(
+                "ioctl on /dev/uinput failed: {}",
+                std::io::Error::last_os_error()
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn emit(&self, kind: u16, code: u16, value: i32) -> Result<(), Box<dyn Error>> {
+        use std::io::Write;
+
+        let event = libc::input_event {
+            time: libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            type_: kind,
+            code,
+            value,
+        };
+        // SAFETY: input_event is plain old data laid out as the kernel expects
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                &event as *const libc::input_event as *const u8,
+                std::mem::size_of::<libc::input_event>(),
+            )
+        };
+        (&self.device).write_all(bytes)?;
+        Ok(())
+    }
+
+    fn press(&self, code: u16, direction: Direction) -> Result<(), Box<dyn Error>> {
+        let value = match direction {
+            Direction::Press => 1,
+            Direction::Release => 0,
+        };
+        self.emit(codes::EV_KEY, code, value)?;
+        self.emit(codes::EV_SYN, codes::SYN_REPORT, 0)
+    }
+
+    /// Press or release the key of the char, with shift if the char needs it
+    fn press_char(&self, ch: char, direction: Direction) -> Result<(), Box<dyn Error>> {
+        let (code, shift) = uinput_char(ch).ok_or_else(|| format!("Cannot type '{}'", ch))?;
+        if shift && direction == Direction::Press {
+            self.press(codes::KEY_LEFTSHIFT, Direction::Press)?;
+        }
+        self.press(code, direction)?;
+        if shift && direction == Direction::Release {
+            self.press(codes::KEY_LEFTSHIFT, Direction::Release)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl InputBackend for UinputBackend {
+    fn key(&mut self, key: &str, direction: Direction) -> Result<(), Box<dyn Error>> {
+        let mut chars = key.chars();
+        if let (Some(ch), None) = (chars.next(), chars.next()) {
+            return self.press_char(ch, direction);
+        }
+        let code = uinput_code(key).ok_or_else(|| format!("Unknown key: '{}'", key))?;
+        self.press(code, direction)
+    }
+
+    fn text(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        for ch in text.chars() {
+            self.press_char(ch, Direction::Press)?;
+            self.press_char(ch, Direction::Release)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for UinputBackend {
+    fn drop(&mut self) {
+        // Let the last events through before the keyboard is gone
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let _ = self.ioctl(Self::UI_DEV_DESTROY, 0);
+    }
+}
+
+/// uinput is only on Linux
+#[cfg(not(target_os = "linux"))]
+pub struct UinputBackend;
+
+#[cfg(not(target_os = "linux"))]
+impl UinputBackend {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        Err("The uinput backend is only available on Linux".into())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl InputBackend for UinputBackend {
+    fn key(&mut self, _key: &str, _direction: Direction) -> Result<(), Box<dyn Error>> {
+        unreachable!()
+    }
+
+    fn text(&mut self, _text: &str) -> Result<(), Box<dyn Error>> {
+        unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_backend() {
+        let recording = RecordingBackend::default();
+        let mut backend: Box<dyn InputBackend> = Box::new(recording.clone());
+        backend.key("ctrl", Direction::Press).unwrap();
+        backend.text("hi").unwrap();
+        assert_eq!(
+            recording.events(),
+            vec![
+                InputEvent::Key("ctrl".to_string(), Direction::Press),
+                InputEvent::Text("hi".to_string()),
+            ]
+        );
+        assert_eq!(BackendKind::parse("uinput"), BackendKind::Uinput);
+        assert_eq!(BackendKind::parse("wayland"), BackendKind::Enigo);
+
+        assert!(BackendKind::Enigo.can_press("pagedown"));
+        assert!(!BackendKind::Enigo.can_press("126")); // A keycode of the keymap
+        assert!(!BackendKind::Xdotool.can_press("f13"));
+        assert!(BackendKind::Mock.can_press("126"));
+        assert!(BackendKind::Enigo.can_type('é'));
+    }
+
+    #[test]
+    fn test_key_names() {
+        assert_eq!(xdotool_keysym("Ctrl").as_deref(), Some("ctrl"));
+        assert_eq!(xdotool_keysym("pageup").as_deref(), Some("Prior"));
+        assert_eq!(xdotool_keysym("f11").as_deref(), Some("F11"));
+        assert_eq!(xdotool_keysym("A").as_deref(), Some("A"));
+        assert_eq!(xdotool_keysym("+").as_deref(), Some("plus"));
+        assert_eq!(xdotool_keysym("f13"), None);
+        assert_eq!(xdotool_keysym("hello"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_uinput_codes() {
+        assert_eq!(uinput_code("ctrl"), Some(29));
+        assert_eq!(uinput_code("126"), Some(126)); // A keycode of the keymap
+        assert_eq!(uinput_code("z"), Some(44));
+        assert_eq!(uinput_code("1"), Some(2));
+        assert_eq!(uinput_code("hello"), None);
+        assert_eq!(uinput_char('a'), Some((30, false)));
+        assert_eq!(uinput_char('Q'), Some((16, true)));
+        assert_eq!(uinput_char('?'), Some((53, true)));
+        assert_eq!(uinput_char('0'), Some((11, false)));
+        assert_eq!(uinput_char('é'), None);
+    }
+}
@@ -0,0 +1,350 @@
+use pinyin::ToPinyin;
+use serde::Serialize;
+use std::cmp::Reverse;
+
+const SCORE_MATCH: i64 = 16; // Every matched char
+const BONUS_BOUNDARY: i64 = 8; // The char starts a word
+const BONUS_CAMEL: i64 = 7; // The char starts a camelCase hump or a number
+const BONUS_CONSECUTIVE: i64 = 6; // The char follows the previous matched char
+const BONUS_FIRST_CHAR_FACTOR: i64 = 2; // The bonus of the first char of the term counts more
+const PENALTY_GAP_START: i64 = 3; // Chars skipped between two matched chars
+const PENALTY_GAP_EXTENSION: i64 = 1;
+
+/// The chars matched in one field of a shortcut, as [start, end) ranges of char indices
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct FieldMatch {
+    pub field: &'static str,
+    pub ranges: Vec<(usize, usize)>,
+}
+
+/// Bonus for matching the char at `pos`, according to the char before it
+fn position_bonus(chars: &[char], pos: usize) -> i64 {
+    let c = chars[pos];
+    let Some(&prev) = pos.checked_sub(1).and_then(|p| chars.get(p)) else {
+        return BONUS_BOUNDARY;
+    };
+    if !prev.is_alphanumeric() && c.is_alphanumeric() {
+        BONUS_BOUNDARY
+    } else if (prev.is_lowercase() && c.is_uppercase())
+        || (!prev.is_numeric() && c.is_numeric())
+    {
+        BONUS_CAMEL
+    } else {
+        0
+    }
+}
+
+pub fn lower(c: char) -> char {
+    if c.is_ascii() {
+        return c.to_ascii_lowercase();
+    }
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Whether the chars of the term appear in the text in the same order, ignoring case
+pub fn is_subsequence(term: &str, text: &str) -> bool {
+    if term.is_ascii() && text.is_ascii() {
+        let mut rest = text.bytes();
+        return term
+            .bytes()
+            .all(|t| rest.any(|c| c.eq_ignore_ascii_case(&t)));
+    }
+    let mut rest = text.chars().map(lower);
+    term.chars().map(lower).all(|t| rest.any(|c| c == t))
+}
+
+/// Match the term as a subsequence of the text, ignoring case.
+/// Returns the best score and the char indices of the matched chars, None if it does not match.
+///
+/// The best alignment is found by dynamic programming: `cells[i * n + j]` holds the best score of
+/// the first i + 1 chars of the term, with the char i matched at position j of the text,
+/// and the position of the char i - 1 in that alignment.
+pub fn fuzzy_match(term: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    // Most texts do not match at all, rule them out before allocating anything
+    if !is_subsequence(term, text) {
+        return None;
+    }
+    // The chars of the text, the same in lower case, then the term in lower case,
+    // all in one allocation as this runs for every hit
+    let mut buffer: Vec<char> = Vec::with_capacity(text.len() * 2 + term.len());
+    buffer.extend(text.chars());
+    let n = buffer.len();
+    buffer.extend_from_within(..n);
+    buffer[n..].iter_mut().for_each(|c| *c = lower(*c));
+    buffer.extend(term.chars().map(lower));
+    let (chars, rest) = buffer.split_at(n);
+    let (lowered, term) = rest.split_at(n);
+    let m = term.len();
+    if m == 0 {
+        return Some((0, Vec::new()));
+    }
+
+    // None where the term char cannot be matched at that position
+    let mut cells: Vec<Option<(i64, usize)>> = vec![None; m * n];
+    for i in 0..m {
+        // Best of scores[i - 1][k] + k * PENALTY_GAP_EXTENSION over k < j - 1,
+        // so that the best match before a gap is found without scanning back
+        let mut best_gap: Option<(i64, usize)> = None;
+        for j in i..n {
+            if i > 0 && j >= 2 {
+                if let Some((s, _)) = cells[(i - 1) * n + j - 2] {
+                    let candidate = s + (j - 2) as i64 * PENALTY_GAP_EXTENSION;
+                    if best_gap.is_none_or(|(b, _)| candidate > b) {
+                        best_gap = Some((candidate, j - 2));
+                    }
+                }
+            }
+            if lowered[j] != term[i] {
+                continue;
+            }
+            let bonus = position_bonus(chars, j);
+            if i == 0 {
+                cells[j] = Some((SCORE_MATCH + bonus * BONUS_FIRST_CHAR_FACTOR, 0));
+                continue;
+            }
+            let consecutive =
+                cells[(i - 1) * n + j - 1].map(|(s, _)| (s + BONUS_CONSECUTIVE.max(bonus), j - 1));
+            // Skipping j - k - 1 chars costs PENALTY_GAP_START + (j - k - 2) * PENALTY_GAP_EXTENSION
+            let gap = best_gap.map(|(b, k)| {
+                (b - PENALTY_GAP_START - (j - 2) as i64 * PENALTY_GAP_EXTENSION + bonus, k)
+            });
+            let best = match (consecutive, gap) {
+                (Some(c), Some(g)) => Some(if c.0 >= g.0 { c } else { g }),
+                (c, g) => c.or(g),
+            };
+            cells[i * n + j] = best.map(|(s, k)| (s + SCORE_MATCH, k));
+        }
+    }
+
+    let (mut pos, score) = cells[(m - 1) * n..]
+        .iter()
+        .enumerate()
+        .filter_map(|(j, cell)| cell.map(|(s, _)| (j, s)))
+        .max_by_key(|(j, s)| (*s, Reverse(*j)))?;
+    let mut positions = vec![pos; m];
+    for i in (1..m).rev() {
+        pos = cells[i * n + pos].map_or(0, |(_, k)| k);
+        positions[i - 1] = pos;
+    }
+    Some((score, positions))
+}
+
+/// The best score `fuzzy_match` can give the term whatever the text, and `pinyin_match` too
+/// if `pinyin` is set. A char of the text following a matched letter or digit does not start
+/// a word, so it gets at most the camel bonus.
+pub fn max_score(term: &str, pinyin: bool) -> i64 {
+    let chars: Vec<char> = term.chars().collect();
+    let Some(first) = chars.first() else {
+        return 0;
+    };
+    let mut fuzzy = SCORE_MATCH + BONUS_BOUNDARY * BONUS_FIRST_CHAR_FACTOR;
+    let mut prev = first;
+    for c in &chars[1..] {
+        let bonus = match prev.is_alphanumeric() {
+            true => BONUS_CAMEL,
+            false => BONUS_BOUNDARY,
+        };
+        fuzzy += SCORE_MATCH + bonus.max(BONUS_CONSECUTIVE);
+        prev = c;
+    }
+    if !pinyin {
+        return fuzzy;
+    }
+    let pinyin = chars.len() as i64 * (SCORE_MATCH + BONUS_CONSECUTIVE)
+        + BONUS_BOUNDARY * BONUS_FIRST_CHAR_FACTOR;
+    fuzzy.max(pinyin)
+}
+
+/// The text spelled in pinyin, every char of the spelling knows the char of the text it comes from
+#[derive(Default)]
+struct Spelling {
+    chars: Vec<char>,
+    sources: Vec<usize>, // The index in the text of every char
+    starts: Vec<bool>,   // Whether the char starts a syllable or a word
+}
+
+impl Spelling {
+    fn push(&mut self, unit: &str, source: usize, start: bool) {
+        for (i, c) in unit.chars().enumerate() {
+            self.chars.push(c);
+            self.sources.push(source);
+            self.starts.push(start && i == 0);
+        }
+    }
+
+    /// The text positions of the first occurrence of the term starting a syllable or a word
+    fn find(&self, term: &[char]) -> Option<Vec<usize>> {
+        let end = (self.chars.len() + 1).checked_sub(term.len())?;
+        let start = (0..end).find(|&i| self.starts[i] && self.chars[i..i + term.len()] == *term)?;
+        let mut positions: Vec<usize> = self.sources[start..start + term.len()].to_vec();
+        positions.dedup();
+        Some(positions)
+    }
+}
+
+/// Match the term against the pinyin of the Chinese chars of the text, either the full
+/// pinyin (`baocun` for 保存) or the initials (`bc`), ignoring case. The term has to be
+/// contiguous in the spelling and start a syllable, other chars of the text are kept as is.
+/// Returns the score and the char indices of the matched chars like `fuzzy_match`,
+/// None if it does not match or the text has no Chinese char.
+pub fn pinyin_match(term: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    if term.is_empty() || text.is_ascii() {
+        return None;
+    }
+    let term: Vec<char> = term.chars().map(lower).collect();
+    let mut full = Spelling::default();
+    let mut initials = Spelling::default();
+    let mut has_pinyin = false;
+    let mut prev: Option<char> = None;
+    for (i, c) in text.chars().enumerate() {
+        match c.to_pinyin() {
+            Some(p) => {
+                has_pinyin = true;
+                full.push(p.plain(), i, true);
+                initials.push(p.first_letter(), i, true);
+            }
+            None => {
+                let start = c.is_alphanumeric()
+                    && prev.is_none_or(|p| !p.is_alphanumeric() || p.to_pinyin().is_some());
+                let c = lower(c).to_string();
+                full.push(&c, i, start);
+                initials.push(&c, i, start);
+            }
+        }
+        prev = Some(c);
+    }
+    if !has_pinyin {
+        return None;
+    }
+
+    let positions = full.find(&term).or_else(|| initials.find(&term))?;
+    let score = term.len() as i64 * (SCORE_MATCH + BONUS_CONSECUTIVE)
+        + BONUS_BOUNDARY * BONUS_FIRST_CHAR_FACTOR;
+    Some((score, positions))
+}
+
+/// The char ranges of all the occurrences of the needle in the text, ignoring case
+pub fn find_ranges(text: &str, needle: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<char> = text.chars().map(lower).collect();
+    let needle: Vec<char> = needle.chars().map(lower).collect();
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    if needle.is_empty() {
+        return ranges;
+    }
+    let mut i = 0;
+    while i + needle.len() <= chars.len() {
+        if chars[i..i + needle.len()] == needle[..] {
+            ranges.push((i, i + needle.len()));
+            i += needle.len();
+        } else {
+            i += 1;
+        }
+    }
+    ranges
+}
+
+/// Merge sorted char indices into [start, end) ranges
+pub fn to_ranges(positions: &[usize]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &p in positions {
+        match ranges.last_mut() {
+            Some(last) if last.1 == p => last.1 = p + 1,
+            Some(last) if last.1 > p => {}
+            _ => ranges.push((p, p + 1)),
+        }
+    }
+    ranges
+}
+
+/// Wrap the chars of the ranges in `open` and `close`
+pub fn highlight(text: &str, ranges: &[(usize, usize)], open: &str, close: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut ranges = ranges.iter().peekable();
+    let mut in_range = false;
+    for (i, c) in text.chars().enumerate() {
+        while ranges.peek().is_some_and(|r| r.1 <= i) {
+            ranges.next();
+        }
+        let inside = ranges.peek().is_some_and(|r| r.0 <= i);
+        if inside != in_range {
+            result.push_str(if inside { open } else { close });
+            in_range = inside;
+        }
+        result.push(c);
+    }
+    if in_range {
+        result.push_str(close);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert!(fuzzy_match("xyz", "Copy line").is_none());
+        let (_, positions) = fuzzy_match("cl", "Copy line").unwrap();
+        assert_eq!(positions, vec![0, 5]);
+        assert_eq!(to_ranges(&[0, 1, 2, 5]), vec![(0, 3), (5, 6)]);
+
+        // Word boundaries and camel case beat matches in the middle of words
+        let (boundary, _) = fuzzy_match("gs", "git status").unwrap();
+        let (middle, _) = fuzzy_match("gs", "logs").unwrap();
+        assert!(boundary > middle);
+        let (_, positions) = fuzzy_match("fb", "fooBar").unwrap();
+        assert_eq!(positions, vec![0, 3]);
+
+        // Consecutive chars beat scattered ones
+        let (consecutive, _) = fuzzy_match("term", "terminal").unwrap();
+        let (scattered, _) = fuzzy_match("term", "the every room").unwrap();
+        assert!(consecutive > scattered);
+        let (_, positions) = fuzzy_match("ab", "axxb ab").unwrap();
+        assert_eq!(positions, vec![5, 6]);
+    }
+
+    #[test]
+    fn test_pinyin_match() {
+        let (_, positions) = pinyin_match("baocun", "保存文件").unwrap();
+        assert_eq!(positions, vec![0, 1]);
+        let (_, positions) = pinyin_match("WJ", "保存文件").unwrap();
+        assert_eq!(positions, vec![2, 3]);
+        let (_, positions) = pinyin_match("cunw", "保存文件").unwrap();
+        assert_eq!(positions, vec![1, 2]);
+        let (_, positions) = pinyin_match("vimbc", "Vim保存").unwrap();
+        assert_eq!(positions, vec![0, 1, 2, 3, 4]);
+
+        // The term starts a syllable, and texts without Chinese are left to fuzzy_match
+        assert!(pinyin_match("aocun", "保存文件").is_none());
+        assert!(pinyin_match("bw", "保存文件").is_none());
+        assert!(pinyin_match("save", "Save file").is_none());
+    }
+
+    #[test]
+    fn test_max_score() {
+        let texts = ["a", "A-B c", "copyLine2", "VS Code", "保存文件", "Vim保存"];
+        for term in ["a", "ab", "AbC", "cl", "vsc", "bc", "baocun", "vimbc", "2"] {
+            for text in texts {
+                if let Some((score, _)) = fuzzy_match(term, text) {
+                    assert!(score <= max_score(term, false), "{} in {}", term, text);
+                }
+                if let Some((score, _)) = pinyin_match(term, text) {
+                    assert!(score <= max_score(term, true), "{} in {}", term, text);
+                }
+            }
+        }
+        assert_eq!(fuzzy_match("a", "a").unwrap().0, max_score("a", false));
+        assert_eq!(pinyin_match("bc", "保存").unwrap().0, max_score("bc", true));
+    }
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(
+            highlight("Copy line", &[(0, 1), (5, 9)], "<mark>", "</mark>"),
+            "<mark>C</mark>opy <mark>line</mark>"
+        );
+        assert_eq!(highlight("复制行", &[(2, 3)], "[", "]"), "复制[行]");
+        assert_eq!(find_ranges("Save ALL, all", "all"), vec![(5, 8), (10, 13)]);
+    }
+}
@@ -1,5 +1,15 @@
+pub mod backup;
 pub mod db;
 pub mod exec;
+pub mod history;
+pub mod index;
+pub mod input;
+pub mod matcher;
+pub mod page;
+pub mod query;
+pub mod ranking;
 pub mod rhythm;
+pub mod sequence;
+pub mod storage;
 pub mod utils;
-pub mod trans;
\ No newline at end of file
+pub mod trans;
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+/// Which part of the results to return.
+/// A cursor carries the generation of the music sheet it was given for, so a page
+/// asked after the shortcuts changed or were reordered is refused instead of skipping
+/// or repeating results.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PageRequest {
+    pub offset: usize,
+    pub limit: Option<usize>, // None to return all the results from the offset
+    pub generation: Option<u64>, // The generation of the cursor, None for a plain offset
+}
+
+/// Where the returned results are among all of them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Page {
+    pub total: usize,                // How many results there are in all
+    pub offset: usize,               // The index of the first returned result
+    pub count: usize,                // How many results are returned
+    pub next_cursor: Option<String>, // Asks the page after this one, None on the last page
+}
+
+fn cursor(generation: u64, offset: usize) -> String {
+    format!("{:x}:{}", generation, offset)
+}
+
+impl PageRequest {
+    /// The page starting at the cursor, or at the offset if there is no cursor.
+    /// A limit of 0 means no limit.
+    pub fn new(
+        cursor: Option<&str>,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    ) -> Result<Self, String> {
+        let mut page = PageRequest {
+            offset: offset.unwrap_or(0),
+            limit: limit.filter(|limit| *limit > 0),
+            generation: None,
+        };
+        if let Some(cursor) = cursor {
+            let parsed = cursor.split_once(':').and_then(|(generation, offset)| {
+                Some((
+                    u64::from_str_radix(generation, 16).ok()?,
+                    offset.parse::<usize>().ok()?,
+                ))
+            });
+            let (generation, offset) = parsed.ok_or(format!("Invalid cursor {}", cursor))?;
+            page.generation = Some(generation);
+            page.offset = offset;
+        }
+        Ok(page)
+    }
+
+    /// How many of the first results the page needs, None for all of them
+    pub fn end(&self) -> Option<usize> {
+        self.limit.map(|limit| self.offset.saturating_add(limit))
+    }
+
+    /// Keep the results of the page, `generation` is the current one of the music sheet.
+    /// None if the cursor is stale, the results should be fetched again from the start.
+    pub fn apply<T>(&self, results: Vec<T>, generation: u64) -> Option<(Vec<T>, Page)> {
+        let total = results.len();
+        self.apply_first(results, total, generation)
+    }
+
+    /// `apply` to the first results only, at least the `end` of the page, out of `total`
+    pub fn apply_first<T>(
+        &self,
+        results: Vec<T>,
+        total: usize,
+        generation: u64,
+    ) -> Option<(Vec<T>, Page)> {
+        if self.generation.is_some_and(|g| g != generation) {
+            return None;
+        }
+        let offset = self.offset.min(total);
+        let end = match self.limit {
+            Some(limit) => offset.saturating_add(limit).min(total),
+            None => total,
+        };
+        let results: Vec<T> = results
+            .into_iter()
+            .skip(offset)
+            .take(end - offset)
+            .collect();
+        let page = Page {
+            total,
+            offset,
+            count: results.len(),
+            next_cursor: (end < total).then(|| cursor(generation, end)),
+        };
+        Some((results, page))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paging() {
+        let all: Vec<u32> = (0..25).collect();
+        let first = PageRequest::new(None, None, Some(10)).unwrap();
+        let (results, page) = first.apply(all.clone(), 7).unwrap();
+        assert_eq!(results, (0..10).collect::<Vec<u32>>());
+        assert_eq!(page.total, 25);
+        assert_eq!(page.next_cursor.as_deref(), Some("7:10"));
+
+        let cursor = page.next_cursor.unwrap();
+        let next = PageRequest::new(Some(&cursor), None, Some(10)).unwrap();
+        let (results, _) = next.apply(all.clone(), 7).unwrap();
+        assert_eq!(results[0], 10);
+        // The shortcuts changed, the cursor is stale
+        assert!(next.apply(all.clone(), 8).is_none());
+        // Only the first results up to the end of the page are needed
+        assert_eq!(next.end(), Some(20));
+        let (results, page) = next.apply_first((0..20).collect(), 25, 7).unwrap();
+        assert_eq!(results, (10..20).collect::<Vec<u32>>());
+        assert_eq!((page.total, page.next_cursor.as_deref()), (25, Some("7:20")));
+
+        let (results, page) = PageRequest::new(None, Some(20), Some(10))
+            .unwrap()
+            .apply(all.clone(), 8)
+            .unwrap();
+        assert_eq!(
+            (results.len(), page.offset, page.next_cursor),
+            (5, 20, None)
+        );
+        let (results, page) = PageRequest::new(None, Some(30), None)
+            .unwrap()
+            .apply(all, 8)
+            .unwrap();
+        assert_eq!((results.len(), page.offset, page.count), (0, 25, 0));
+
+        assert_eq!(PageRequest::new(None, None, Some(0)).unwrap().limit, None);
+        assert!(PageRequest::new(Some("x:1"), None, None).is_err());
+        assert!(PageRequest::new(Some("12"), None, None).is_err());
+    }
+}
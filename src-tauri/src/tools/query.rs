@@ -0,0 +1,432 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+
+use super::db::Shortcut;
+use super::matcher::find_ranges;
+
+/// A query that cannot be parsed, the position is the index of the char where it went wrong
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl Error for QueryError {}
+
+/// The text fields of a shortcut that can be filtered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextField {
+    Application,
+    Description,
+    Shortcut,
+    Comment,
+}
+
+impl TextField {
+    /// The name of the field, as in the JSON of the shortcut
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextField::Application => "application",
+            TextField::Description => "description",
+            TextField::Shortcut => "shortcut",
+            TextField::Comment => "comment",
+        }
+    }
+
+    fn value<'a>(&self, sc: &'a Shortcut) -> &'a str {
+        match self {
+            TextField::Application => &sc.application,
+            TextField::Description => &sc.description,
+            TextField::Shortcut => &sc.shortcut,
+            TextField::Comment => &sc.comment,
+        }
+    }
+}
+
+/// The keys that filter a field in `key:value`, with their aliases
+const FIELD_KEYS: [&str; 11] = [
+    "app",
+    "application",
+    "desc",
+    "description",
+    "sc",
+    "shortcut",
+    "comment",
+    "tag",
+    "tags",
+    "hits",
+    "hit_number",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compare {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+}
+
+impl Compare {
+    fn test(&self, a: i64, b: i64) -> bool {
+        match self {
+            Compare::Lt => a < b,
+            Compare::Le => a <= b,
+            Compare::Eq => a == b,
+            Compare::Ge => a >= b,
+            Compare::Gt => a > b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Field(TextField, String), // The field contains the text, ignoring case
+    Tag(String),              // One of the tags is the text, ignoring case
+    Hits(Compare, i64),       // hit_number compared to the number
+    Text(String),             // One of the searched fields contains the text, ignoring case
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clause {
+    pub negated: bool,
+    pub filter: Filter,
+}
+
+/// A parsed search query.
+///
+/// ```text
+/// app:nvim tag:git -comment:deprecated hits:>10 "exact phrase" fuzzy words
+/// ```
+///
+/// - `field:value` keeps the shortcuts whose field contains the value, the fields are
+///   `app`, `desc`, `sc`, `comment`, `tag` (the whole tag) and `hits` (`>`, `>=`, `<`, `<=`, `=`)
+/// - `"exact phrase"` keeps the shortcuts containing the phrase in a searched field
+/// - `-` before a clause or a word excludes the shortcuts it matches
+/// - Values can be quoted to contain spaces: `app:"visual studio"`
+/// - Other words are fuzzy matched and rank the results
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Query {
+    pub clauses: Vec<Clause>,
+    pub terms: Vec<String>, // Fuzzy terms
+}
+
+/// The fields searched by fuzzy terms and phrases, with the tags joined as one field
+pub fn searched_fields(sc: &Shortcut) -> [(&'static str, Cow<'_, str>); 4] {
+    [
+        ("description", Cow::Borrowed(&sc.description)),
+        ("application", Cow::Borrowed(&sc.application)),
+        ("shortcut", Cow::Borrowed(&sc.shortcut)),
+        ("tags", Cow::Owned(sc.tags_string())),
+    ]
+}
+
+impl Query {
+    /// A query of fuzzy terms only, without parsing any syntax
+    pub fn fuzzy(query: &str) -> Self {
+        Self {
+            clauses: Vec::new(),
+            terms: query.split_whitespace().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    pub fn parse(query: &str) -> Result<Self, QueryError> {
+        Parser {
+            chars: query.chars().collect(),
+            pos: 0,
+        }
+        .parse()
+    }
+
+    /// Whether the shortcut passes all the clauses, the fuzzy terms are not checked
+    pub fn matches(&self, sc: &Shortcut) -> bool {
+        self.clauses.iter().all(|clause| {
+            let matched = match &clause.filter {
+                Filter::Field(field, text) => field.value(sc).to_lowercase().contains(text),
+                Filter::Tag(tag) => sc.tags.iter().any(|t| t.to_lowercase() == *tag),
+                Filter::Hits(cmp, n) => cmp.test(sc.hit_number, *n),
+                Filter::Text(text) => searched_fields(sc)
+                    .iter()
+                    .any(|(_, value)| value.to_lowercase().contains(text)),
+            };
+            matched != clause.negated
+        })
+    }
+
+    /// The chars matched by the clauses that are not negated, as (field, ranges)
+    pub fn highlights(&self, sc: &Shortcut) -> Vec<(&'static str, Vec<(usize, usize)>)> {
+        let mut highlights = Vec::new();
+        for clause in self.clauses.iter().filter(|c| !c.negated) {
+            match &clause.filter {
+                Filter::Field(field, text) => {
+                    highlights.push((field.name(), find_ranges(field.value(sc), text)));
+                }
+                Filter::Tag(tag) => highlights.push(("tags", find_ranges(&sc.tags_string(), tag))),
+                Filter::Text(text) => {
+                    for (name, value) in searched_fields(sc) {
+                        highlights.push((name, find_ranges(&value, text)));
+                    }
+                }
+                Filter::Hits(..) => {}
+            }
+        }
+        highlights
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, position: usize, message: &str) -> QueryError {
+        QueryError {
+            position,
+            message: message.to_string(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn parse(mut self) -> Result<Query, QueryError> {
+        let mut query = Query::default();
+        loop {
+            while self.peek().is_some_and(|c| c.is_whitespace()) {
+                self.pos += 1;
+            }
+            let Some(c) = self.peek() else {
+                return Ok(query);
+            };
+            let negated = c == '-'
+                && self
+                    .chars
+                    .get(self.pos + 1)
+                    .is_some_and(|c| !c.is_whitespace());
+            if negated {
+                self.pos += 1;
+            }
+
+            let filter = if self.peek() == Some('"') {
+                Filter::Text(self.quoted()?.to_lowercase())
+            } else if let Some(key) = self.key() {
+                self.field(&key)?
+            } else {
+                let word = self.word();
+                if !negated {
+                    query.terms.push(word);
+                    continue;
+                }
+                Filter::Text(word.to_lowercase())
+            };
+            query.clauses.push(Clause { negated, filter });
+        }
+    }
+
+    /// Read `key:` if it is there and names a field, and move to the value.
+    /// Other words with a colon, like `http://localhost` or `std::vec`, are plain terms.
+    fn key(&mut self) -> Option<String> {
+        let end = self.pos
+            + self.chars[self.pos..]
+                .iter()
+                .take_while(|c| c.is_alphabetic() || **c == '_')
+                .count();
+        if end == self.pos || self.chars.get(end) != Some(&':') {
+            return None;
+        }
+        let key: String = self.chars[self.pos..end].iter().collect();
+        if !FIELD_KEYS.contains(&key.as_str()) {
+            return None;
+        }
+        self.pos = end + 1;
+        Some(key)
+    }
+
+    /// Read until the next whitespace
+    fn word(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| !c.is_whitespace()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Read a string in double quotes, `\` escapes the next char
+    fn quoted(&mut self) -> Result<String, QueryError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut text = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error(start, "Unterminated quote")),
+                Some('"') => break,
+                Some('\\') if self.pos + 1 < self.chars.len() => {
+                    text.push(self.chars[self.pos + 1]);
+                    self.pos += 2;
+                    continue;
+                }
+                Some(c) => text.push(c),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        if text.is_empty() {
+            return Err(self.error(start, "Empty quotes"));
+        }
+        Ok(text)
+    }
+
+    fn value(&mut self) -> Result<String, QueryError> {
+        match self.peek() {
+            Some('"') => self.quoted(),
+            Some(c) if !c.is_whitespace() => Ok(self.word()),
+            _ => Err(self.error(self.pos, "Missing value")),
+        }
+    }
+
+    /// The filter of the field, `key` is one of `FIELD_KEYS`
+    fn field(&mut self, key: &str) -> Result<Filter, QueryError> {
+        let text_field = match key {
+            "app" | "application" => Some(TextField::Application),
+            "desc" | "description" => Some(TextField::Description),
+            "sc" | "shortcut" => Some(TextField::Shortcut),
+            "comment" => Some(TextField::Comment),
+            _ => None,
+        };
+        if let Some(field) = text_field {
+            return Ok(Filter::Field(field, self.value()?.to_lowercase()));
+        }
+        match key {
+            "tag" | "tags" => Ok(Filter::Tag(self.value()?.to_lowercase())),
+            "hits" | "hit_number" => {
+                let value_start = self.pos;
+                let value = self.value()?;
+                let (cmp, number) = [
+                    (">=", Compare::Ge),
+                    ("<=", Compare::Le),
+                    (">", Compare::Gt),
+                    ("<", Compare::Lt),
+                    ("=", Compare::Eq),
+                ]
+                .iter()
+                .find_map(|(op, cmp)| value.strip_prefix(op).map(|n| (*cmp, n)))
+                .unwrap_or((Compare::Eq, &value));
+                let number_start = value_start + value.chars().count() - number.chars().count();
+                number
+                    .parse::<i64>()
+                    .map(|n| Filter::Hits(cmp, n))
+                    .map_err(|_| self.error(number_start, "Invalid number"))
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        let query =
+            Query::parse(r#"app:nvim tag:Git -comment:deprecated hits:>10 "exact phrase" sav"#)
+                .unwrap();
+        assert_eq!(
+            query.clauses,
+            vec![
+                Clause {
+                    negated: false,
+                    filter: Filter::Field(TextField::Application, "nvim".to_string())
+                },
+                Clause {
+                    negated: false,
+                    filter: Filter::Tag("git".to_string())
+                },
+                Clause {
+                    negated: true,
+                    filter: Filter::Field(TextField::Comment, "deprecated".to_string())
+                },
+                Clause {
+                    negated: false,
+                    filter: Filter::Hits(Compare::Gt, 10)
+                },
+                Clause {
+                    negated: false,
+                    filter: Filter::Text("exact phrase".to_string())
+                },
+            ]
+        );
+        assert_eq!(query.terms, vec!["sav".to_string()]);
+
+        let query = Query::parse(r#"app:"visual studio" -tmp ctrl+: -"#).unwrap();
+        assert_eq!(
+            query.clauses[0].filter,
+            Filter::Field(TextField::Application, "visual studio".to_string())
+        );
+        assert!(query.clauses[1].negated);
+        assert_eq!(query.terms, vec!["ctrl+:".to_string(), "-".to_string()]);
+    }
+
+    #[test]
+    fn test_query_errors() {
+        let position = |q: &str| Query::parse(q).unwrap_err().position;
+        assert_eq!(position(r#"app:vim "unterminated"#), 8);
+        assert_eq!(position("hits:>ten"), 6);
+        assert_eq!(position("tag: git"), 4);
+        assert_eq!(
+            Query::parse("hits:many").unwrap_err().to_string(),
+            "Invalid number at position 5"
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_are_terms() {
+        let query = Query::parse("http://localhost std::vec vim: app:vim colour:red").unwrap();
+        assert_eq!(
+            query.terms,
+            vec![
+                "http://localhost".to_string(),
+                "std::vec".to_string(),
+                "vim:".to_string(),
+                "colour:red".to_string(),
+            ]
+        );
+        assert_eq!(
+            query.clauses,
+            vec![Clause {
+                negated: false,
+                filter: Filter::Field(TextField::Application, "vim".to_string())
+            }]
+        );
+        let query = Query::parse("-foo:bar").unwrap();
+        assert_eq!(query.clauses[0].filter, Filter::Text("foo:bar".to_string()));
+    }
+
+    #[test]
+    fn test_query_matches() {
+        let sc = Shortcut {
+            application: "nvim".to_string(),
+            description: "Save all".to_string(),
+            comment: "Deprecated, use :wa".to_string(),
+            tags: vec!["git".to_string()],
+            hit_number: 11,
+            ..Default::default()
+        };
+        let matches = |q: &str| Query::parse(q).unwrap().matches(&sc);
+        assert!(matches("app:vim tag:git hits:>10"));
+        assert!(matches(r#""save all" hits:<=11"#));
+        assert!(!matches("-comment:deprecated"));
+        assert!(!matches("tag:gi"));
+        assert!(!matches("hits:10"));
+        assert!(!matches("-save"));
+    }
+}
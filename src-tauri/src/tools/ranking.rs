@@ -0,0 +1,98 @@
+use std::cmp::Reverse;
+
+use super::db::Shortcut;
+
+/// How the shortcuts are ranked, set by `rank_mode` in Rhythm
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RankMode {
+    HitNumber, // The most hit first, no matter how long ago
+    Frecency,  // Every hit decays with a half-life, so recent hits count more
+}
+
+impl RankMode {
+    /// Parse the mode in Rhythm, unknown values are treated as "frecency"
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "hit_number" => RankMode::HitNumber,
+            _ => RankMode::Frecency,
+        }
+    }
+}
+
+/// How much one hit is worth after `elapsed` seconds
+fn decay(elapsed: i64, half_life_secs: i64) -> f64 {
+    0.5_f64.powf(elapsed.max(0) as f64 / half_life_secs.max(1) as f64)
+}
+
+/// The frecency of the shortcut at `now`.
+/// Each hit counts 1 when it happens, and is halved every `half_life_secs` after that.
+pub fn frecency_at(sc: &Shortcut, now: i64, half_life_secs: i64) -> f64 {
+    sc.frecency * decay(now - sc.last_used, half_life_secs)
+}
+
+/// Record one hit of the shortcut at `now`
+pub fn record_hit(sc: &mut Shortcut, now: i64, half_life_secs: i64) {
+    sc.frecency = frecency_at(sc, now, half_life_secs) + 1.0;
+    sc.last_used = now;
+    sc.hit_number += 1;
+}
+
+/// Sort the shortcuts, the best first.
+/// The sort is stable, so the shortcuts ranked the same keep their previous order.
+/// In frecency mode, the ties (e.g. never hit since frecency was recorded) fall back to hit_number.
+pub fn rank(shortcuts: &mut [Shortcut], mode: RankMode, now: i64, half_life_secs: i64) {
+    match mode {
+        RankMode::HitNumber => shortcuts.sort_by_key(|sc| Reverse(sc.hit_number)),
+        RankMode::Frecency => shortcuts.sort_by(|a, b| {
+            frecency_at(b, now, half_life_secs)
+                .total_cmp(&frecency_at(a, now, half_life_secs))
+                .then(b.hit_number.cmp(&a.hit_number))
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 24 * 3600;
+
+    fn hit_at(sc: &mut Shortcut, times: &[i64]) {
+        for t in times {
+            record_hit(sc, *t, 7 * DAY);
+        }
+    }
+
+    #[test]
+    fn test_frecency_ranking() {
+        let now = 400 * DAY;
+        let mut old_favorite = Shortcut {
+            description: "old".to_string(),
+            ..Default::default()
+        };
+        hit_at(&mut old_favorite, &vec![0; 500]);
+        let mut recent = Shortcut {
+            description: "recent".to_string(),
+            ..Default::default()
+        };
+        hit_at(&mut recent, &[now - DAY; 20]);
+        assert_eq!(old_favorite.hit_number, 500);
+        assert!((frecency_at(&recent, now - DAY, 7 * DAY) - 20.0).abs() < 1e-9);
+        assert!((frecency_at(&recent, now + 6 * DAY, 7 * DAY) - 10.0).abs() < 1e-9);
+
+        let mut shortcuts = vec![old_favorite, recent];
+        rank(&mut shortcuts, RankMode::Frecency, now, 7 * DAY);
+        assert_eq!(shortcuts[0].description, "recent");
+        rank(&mut shortcuts, RankMode::HitNumber, now, 7 * DAY);
+        assert_eq!(shortcuts[0].description, "old");
+
+        // Without any recorded hit, frecency falls back to hit_number
+        let never = Shortcut {
+            hit_number: 3,
+            ..Default::default()
+        };
+        let mut shortcuts = vec![Shortcut::default(), never];
+        rank(&mut shortcuts, RankMode::Frecency, now, 7 * DAY);
+        assert_eq!(shortcuts[0].hit_number, 3);
+    }
+}
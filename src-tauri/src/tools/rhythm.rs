@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use std::fs::{self, OpenOptions};
 use std::io::Write;
 use std::path::PathBuf;
@@ -12,8 +12,22 @@ pub struct Rhythm {
     pub liz_path: String, // The config path from
     // pub user_sheets_path: String, // Path for all the shortcut sheets
     pub music_sheet_path: String, // Path for the lock file for Bluebird
+    pub storage_backend: String,  // How the music sheet is stored: "sqlite" or "json"
+    pub music_sheet_db_path: String, // Path for the SQLite database when using "sqlite"
+    pub backup_count: usize,      // How many backups of the music sheet to keep, 0 to disable
+    pub autosave_ms: u64,         // Save the changes once no more change for this long, 0 to disable
+    pub trash_retention_days: u64, // Deleted shortcuts older than this are purged on exit
+    pub undo_depth: usize,        // How many changes of the shortcuts can be undone
+    pub rank_mode: String,        // How the shortcuts are ranked: "frecency" or "hit_number"
+    pub frecency_half_life_days: u64, // A hit counts half as much after this many days
+    pub pinyin_search: bool,      // Chinese text also matches its pinyin and pinyin initials
+    pub ipc_enabled: bool,        // Serve the commands on a local socket for external launchers
+    pub ipc_socket_path: String,  // The socket (a named pipe on Windows), empty for the default
+    pub input_backend: String,    // How the keys are pressed: "enigo", "xdotool", "uinput" or "mock"
     pub keymap_path: String,      // Can be used to customize key mapping
     pub interval_ms: u64,         // interval of each shortcut block. No need to set it normally.
+    pub key_delay_ms: u64,        // Wait between the key events, for slow apps and remote desktops
+    pub repeat_limit: usize,      // The most steps a shortcut runs once its repeats like down*5 are expanded
     pub trigger_shortcut: String, // The shortcut to activate Liz
     pub shortcut_print_fmt: String, // The format to show one shortcut
     pub language: String,    // The Application Language
@@ -28,6 +42,7 @@ impl Default for Rhythm {
             .to_string();
         // let user_sheets_path: String = format!("{}/sheets", liz_path);
         let music_sheet_path: String = format!("{}/music_sheet.lock", liz_path);
+        let music_sheet_db_path: String = format!("{}/music_sheet.db", liz_path);
         let keymap_path: String = format!("");
         let trigger_shortcut: String = "Ctrl+Alt+L".to_string();
         let shortcut_print_fmt: String =
@@ -37,8 +52,22 @@ impl Default for Rhythm {
             liz_path,
             // user_sheets_path,
             music_sheet_path,
+            storage_backend: "sqlite".to_string(),
+            music_sheet_db_path,
+            backup_count: 5,
+            autosave_ms: 5000,
+            trash_retention_days: 30,
+            undo_depth: 50,
+            rank_mode: "frecency".to_string(),
+            frecency_half_life_days: 7,
+            pinyin_search: true,
+            ipc_enabled: true,
+            ipc_socket_path: String::new(),
+            input_backend: "enigo".to_string(),
             keymap_path,
             interval_ms: 100,
+            key_delay_ms: 0,
+            repeat_limit: 200,
             trigger_shortcut,
             shortcut_print_fmt,
             language: format!("en"),
@@ -58,16 +87,63 @@ pub fn parse_rhythm(json_str: &str) -> Result<Rhythm, Box<dyn std::error::Error>
 }
 
 impl Rhythm {
-    pub fn to_string_list(&self) -> Vec<String> {
+    /// The dir to keep the backups of the music sheet
+    pub fn backup_dir(&self) -> String {
+        format!("{}/backups", self.liz_path)
+    }
+
+    /// The file locked by the running app, see `utils::InstanceLock`
+    pub fn instance_lock_path(&self) -> String {
+        format!("{}/liz.instance", self.liz_path)
+    }
+
+    /// Where the IPC server listens, `ipc_socket_path` if it is set.
+    /// By default it is `$XDG_RUNTIME_DIR/liz.sock` (or `<liz_path>/liz.sock` without it),
+    /// and the named pipe `\\.\pipe\liz-<user>` on Windows.
+    pub fn ipc_path(&self) -> String {
+        if !self.ipc_socket_path.is_empty() {
+            return self.ipc_socket_path.clone();
+        }
+        if cfg!(windows) {
+            let user = std::env::var("USERNAME").unwrap_or_default();
+            return format!(r"\\.\pipe\liz-{}", user);
+        }
+        match std::env::var("XDG_RUNTIME_DIR") {
+            Ok(dir) if !dir.is_empty() => format!("{}/liz.sock", dir),
+            _ => format!("{}/liz.sock", self.liz_path),
+        }
+    }
+
+    /// The half-life of frecency in seconds, at least one day
+    pub fn frecency_half_life_secs(&self) -> i64 {
+        self.frecency_half_life_days.max(1) as i64 * 24 * 3600
+    }
+
+    /// The settings shown in the config panel, each with its name, value and hint
+    pub fn to_settings(&self) -> Vec<Value> {
         vec![
-            json!({"name": "language", "value": self.language, "hint": "The Application Language (Support zh, en)"}).to_string(),
-            json!({"name": "liz_path", "value": self.liz_path, "hint": "The path of data dir"}).to_string(),
-            // json!({"name": "user_sheets_path", "value": self.user_sheets_path, "hint": "Path for all the shortcut sheets"}).to_string(),
-            json!({"name": "music_sheet_path", "value": self.music_sheet_path, "hint": "Path for the lock file for Bluebird"}).to_string(),
-            json!({"name": "keymap_path", "value": self.keymap_path, "hint": "Can be used to customize key mapping"}).to_string(),
-            json!({"name": "interval_ms", "value": self.interval_ms, "hint": "Interval of each shortcut block. No need to set it normally."}).to_string(),
-            json!({"name": "trigger_shortcut", "value": self.trigger_shortcut, "hint": "The shortcut to activate Liz"}).to_string(),
-            json!({"name": "shortcut_print_fmt", "value": self.shortcut_print_fmt, "hint": "The format to show one shortcut"}).to_string(),
+            json!({"name": "language", "value": self.language, "hint": "The Application Language (Support zh, en)"}),
+            json!({"name": "liz_path", "value": self.liz_path, "hint": "The path of data dir"}),
+            // json!({"name": "user_sheets_path", "value": self.user_sheets_path, "hint": "Path for all the shortcut sheets"}),
+            json!({"name": "music_sheet_path", "value": self.music_sheet_path, "hint": "Path for the lock file for Bluebird"}),
+            json!({"name": "storage_backend", "value": self.storage_backend, "hint": "How the music sheet is stored: sqlite or json"}),
+            json!({"name": "music_sheet_db_path", "value": self.music_sheet_db_path, "hint": "Path for the SQLite database when the storage backend is sqlite"}),
+            json!({"name": "backup_count", "value": self.backup_count, "hint": "How many backups of the music sheet to keep in <liz_path>/backups, 0 to disable"}),
+            json!({"name": "autosave_ms", "value": self.autosave_ms, "hint": "Save the changes automatically once nothing changed for this long (ms), 0 to disable"}),
+            json!({"name": "trash_retention_days", "value": self.trash_retention_days, "hint": "Deleted shortcuts older than this many days are purged on exit, 0 to purge all"}),
+            json!({"name": "undo_depth", "value": self.undo_depth, "hint": "How many changes of the shortcuts can be undone, 0 to disable"}),
+            json!({"name": "rank_mode", "value": self.rank_mode, "hint": "How the shortcuts are ranked: frecency (recent hits count more) or hit_number"}),
+            json!({"name": "frecency_half_life_days", "value": self.frecency_half_life_days, "hint": "In frecency mode, a hit counts half as much after this many days"}),
+            json!({"name": "pinyin_search", "value": self.pinyin_search, "hint": "Chinese text also matches its pinyin (baocun) and pinyin initials (bc)"}),
+            json!({"name": "ipc_enabled", "value": self.ipc_enabled, "hint": "Let external launchers (rofi, fzf, editor plugins) search and execute shortcuts over a local socket, applied on restart"}),
+            json!({"name": "ipc_socket_path", "value": self.ipc_socket_path, "hint": "The socket (a named pipe on Windows) of the launchers, empty for the default, applied on restart"}),
+            json!({"name": "input_backend", "value": self.input_backend, "hint": "How the keys are pressed: enigo, xdotool (X11), uinput (Linux, also Wayland) or mock (record only)"}),
+            json!({"name": "keymap_path", "value": self.keymap_path, "hint": "Can be used to customize key mapping"}),
+            json!({"name": "interval_ms", "value": self.interval_ms, "hint": "Interval of each shortcut block. No need to set it normally."}),
+            json!({"name": "key_delay_ms", "value": self.key_delay_ms, "hint": "Wait between the key events, for slow apps and remote desktops. A shortcut can set its own with keydelay(30ms)"}),
+            json!({"name": "repeat_limit", "value": self.repeat_limit, "hint": "The most steps a shortcut runs once its repeats like down*5 are expanded, so a typo cannot press keys for minutes"}),
+            json!({"name": "trigger_shortcut", "value": self.trigger_shortcut, "hint": "The shortcut to activate Liz"}),
+            json!({"name": "shortcut_print_fmt", "value": self.shortcut_print_fmt, "hint": "The format to show one shortcut"}),
         ]
     }
 
@@ -93,7 +169,7 @@ impl Rhythm {
             .open(rhythm_path)?;
         file.write_all(content.as_bytes())?;
 
-        println!("Saved Rhythm settings to {}.", rhythm_path_str);
+        eprintln!("Saved Rhythm settings to {}.", rhythm_path_str);
 
         Ok(rhythm_path_str)
     }
@@ -0,0 +1,998 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+// The grammar of the shortcut field, the steps are separated by whitespace:
+//
+//   sequence := step*
+//   step     := chord | key | string | text | comment | directive | repeat
+//   chord    := key ("+" key)+          e.g. ctrl+shift+t, ctrl++ for ctrl and the + key
+//   key      := NAME | CHAR             e.g. enter, pageup, a, "
+//   string   := '"' (CHAR | ESCAPE)* '"'  typed as it is, with \" \\ \n and \t escaped
+//   text     := "[STR]+" TEXT ("[STR]" | end)   typed as it is, trimmed
+//   comment  := "/*" ... "*/"           ignored
+//   directive := "wait(" DURATION ")"                 wait before the next step
+//              | "hold(" (chord | key) "," DURATION ")"  keep the keys down for a while
+//              | "keydelay(" DURATION ")"            wait between the key events from here on
+//   DURATION := NUMBER ("ms" | "s")    e.g. 250ms, 2s, at most 60s
+//   repeat   := (chord | key | "(" step+ ")") "*" COUNT   e.g. down*5, (tab shift+tab)*3
+//
+// Anything else is an unknown key, a text to type is quoted, e.g. ":wq" rather than :wq.
+//
+// The text of strings and texts can have placeholders, `{{name}}` or `{{name:default}}`,
+// whose values are asked when the shortcut is executed.
+// They can also have variables such as `{date}`, expanded when they are typed, where `\{` and
+// `\}` are literal braces, see `exec::expand_variables`.
+//
+// A lone `"` is the key of the char, a string starts with a `"` followed by something else.
+// The same goes for `(`, a group starts with a `(` followed by something else, and ends at
+// the first `)` starting a step, so a `[STR]+` text in a group has to be closed by `[STR]`.
+
+/// A shortcut that cannot be parsed, `start..end` are the indexes of the chars where it went wrong
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceError {
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
+}
+
+impl SequenceError {
+    /// The shortcut with the wrong chars underlined on the next line
+    pub fn pointed(&self, shortcut: &str) -> String {
+        let width = (self.end - self.start).max(1);
+        format!(
+            "{}\n{}{}",
+            shortcut,
+            " ".repeat(self.start),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.start)
+    }
+}
+
+impl Error for SequenceError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Chord(Vec<String>), // Press the keys in order and release them in reverse, one key is a tap
+    Text(String),       // Type the text of a string or a `[STR]+` block
+    Wait(u64),          // Wait some milliseconds
+    Hold(Vec<String>, u64), // Press the keys, wait some milliseconds and release them
+    KeyDelay(u64),      // Wait some milliseconds between the key events from here on
+    Repeat(Vec<Step>, usize), // Run the steps some times
+}
+
+/// One step of a sequence, `start..end` are the indexes of its chars in the shortcut
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub action: Action,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Sequence {
+    pub steps: Vec<Step>,
+}
+
+/// The names of the keys, with their aliases
+const KEY_NAMES: [(&str, &str); 39] = [
+    ("ctrl", "ctrl"),
+    ("control", "ctrl"),
+    ("alt", "alt"),
+    ("shift", "shift"),
+    ("meta", "meta"),
+    ("win", "meta"),
+    ("cmd", "meta"),
+    ("enter", "enter"),
+    ("return", "enter"),
+    ("esc", "esc"),
+    ("escape", "esc"),
+    ("space", "space"),
+    ("tab", "tab"),
+    ("backspace", "backspace"),
+    ("up", "up"),
+    ("down", "down"),
+    ("left", "left"),
+    ("right", "right"),
+    ("home", "home"),
+    ("end", "end"),
+    ("pageup", "pageup"),
+    ("pgup", "pageup"),
+    ("pagedown", "pagedown"),
+    ("pgdn", "pagedown"),
+    ("delete", "delete"),
+    ("insert", "insert"),
+    ("capslock", "capslock"),
+    ("f1", "f1"),
+    ("f2", "f2"),
+    ("f3", "f3"),
+    ("f4", "f4"),
+    ("f5", "f5"),
+    ("f6", "f6"),
+    ("f7", "f7"),
+    ("f8", "f8"),
+    ("f9", "f9"),
+    ("f10", "f10"),
+    ("f11", "f11"),
+    ("f12", "f12"),
+];
+
+const MODIFIERS: [&str; 4] = ["ctrl", "alt", "shift", "meta"];
+
+const DIRECTIVES: [&str; 3] = ["wait", "hold", "keydelay"];
+
+/// The longest wait, and the longest a shortcut waits in total, as the shortcuts
+/// are executed one at a time
+const MAX_DURATION_MS: u64 = 60_000;
+
+/// The name of the key ignoring case, with the aliases resolved
+pub fn key_name(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+    KEY_NAMES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map(|(_, key)| *key)
+}
+
+/// A value asked when the shortcut is executed, written `{{name}}` or `{{name:default}}`
+/// in the shortcut. The shortcut can keep a hint and a default for it in its placeholders.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Placeholder {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub hint: String, // Shown when the value is asked
+}
+
+/// The placeholders in the text with their byte ranges, a `{{` that starts none is kept as it is
+fn find_placeholders(text: &str) -> Vec<(Range<usize>, Placeholder)> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(open) = text[from..].find("{{").map(|i| from + i) {
+        let Some(close) = text[open + 2..].find("}}").map(|i| open + 2 + i) else {
+            break;
+        };
+        let inner = &text[open + 2..close];
+        let (name, default) = match inner.split_once(':') {
+            Some((name, default)) => (name, Some(default.to_string())),
+            None => (inner, None),
+        };
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            from = open + 1;
+            continue;
+        }
+        let placeholder = Placeholder {
+            name: name.to_string(),
+            default,
+            hint: String::new(),
+        };
+        found.push((open..close + 2, placeholder));
+        from = close + 2;
+    }
+    found
+}
+
+/// The text with its placeholders replaced by the values, or by their defaults.
+/// Their braces are escaped, so they are not expanded as variables.
+fn fill_text(text: &str, values: &HashMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut last = 0;
+    for (range, placeholder) in find_placeholders(text) {
+        let value = values
+            .get(&placeholder.name)
+            .or(placeholder.default.as_ref());
+        if let Some(value) = value {
+            result.push_str(&text[last..range.start]);
+            result.push_str(&value.replace('{', "\\{").replace('}', "\\}"));
+            last = range.end;
+        }
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+fn collect_placeholders(steps: &[Step], placeholders: &mut Vec<Placeholder>) {
+    for step in steps {
+        match &step.action {
+            Action::Text(text) => {
+                for (_, placeholder) in find_placeholders(text) {
+                    if !placeholders.iter().any(|p| p.name == placeholder.name) {
+                        placeholders.push(placeholder);
+                    }
+                }
+            }
+            Action::Repeat(steps, _) => collect_placeholders(steps, placeholders),
+            _ => {}
+        }
+    }
+}
+
+fn fill_steps(steps: &mut [Step], values: &HashMap<String, String>) {
+    for step in steps {
+        match &mut step.action {
+            Action::Text(text) => *text = fill_text(text, values),
+            Action::Repeat(steps, _) => fill_steps(steps, values),
+            _ => {}
+        }
+    }
+}
+
+/// How many steps are run, with the repeats expanded
+fn count_steps(steps: &[Step]) -> usize {
+    steps
+        .iter()
+        .map(|step| match &step.action {
+            Action::Repeat(steps, count) => count_steps(steps).saturating_mul(*count),
+            _ => 1,
+        })
+        .fold(0, usize::saturating_add)
+}
+
+/// How long the steps wait with their repeats expanded, counting the waits, the holds and
+/// the keydelay between key events. `key_delay` is the one set by the last keydelay.
+fn wait_ms(steps: &[Step], key_delay: &mut u64) -> u64 {
+    let mut total: u64 = 0;
+    for step in steps {
+        let ms = match &step.action {
+            Action::Chord(keys) => key_delay.saturating_mul(2 * keys.len() as u64),
+            Action::Hold(keys, ms) => {
+                ms.saturating_add(key_delay.saturating_mul(2 * keys.len() as u64))
+            }
+            Action::Wait(ms) => *ms,
+            Action::KeyDelay(ms) => {
+                *key_delay = *ms;
+                0
+            }
+            Action::Repeat(steps, count) => (0..*count)
+                .map(|_| wait_ms(steps, key_delay))
+                .fold(0, u64::saturating_add),
+            Action::Text(_) => 0,
+        };
+        total = total.saturating_add(ms);
+    }
+    total
+}
+
+impl Sequence {
+    pub fn parse(shortcut: &str) -> Result<Self, SequenceError> {
+        let mut parser = Parser {
+            chars: shortcut.chars().collect(),
+            pos: 0,
+            depth: 0,
+        };
+        Ok(Sequence {
+            steps: parser.steps(None)?,
+        })
+    }
+
+    /// Parse the shortcut, which cannot run more than `repeat_limit` steps once its
+    /// repeats are expanded, nor wait more than 60s in total
+    pub fn parse_limited(shortcut: &str, repeat_limit: usize) -> Result<Self, SequenceError> {
+        let sequence = Self::parse(shortcut)?;
+        let mut total: usize = 0;
+        let (mut waited, mut key_delay) = (0u64, 0u64);
+        for step in &sequence.steps {
+            total = total.saturating_add(count_steps(std::slice::from_ref(step)));
+            if total > repeat_limit {
+                return Err(SequenceError {
+                    start: step.start,
+                    end: step.end,
+                    message: format!(
+                        "Runs more than {} steps once repeated, see repeat_limit",
+                        repeat_limit
+                    ),
+                });
+            }
+            waited = waited.saturating_add(wait_ms(std::slice::from_ref(step), &mut key_delay));
+            if waited > MAX_DURATION_MS {
+                return Err(SequenceError {
+                    start: step.start,
+                    end: step.end,
+                    message: "Waits more than 60s in total".to_string(),
+                });
+            }
+        }
+        Ok(sequence)
+    }
+
+    /// The placeholders in the order they first appear, once each
+    pub fn placeholders(&self) -> Vec<Placeholder> {
+        let mut placeholders = Vec::new();
+        collect_placeholders(&self.steps, &mut placeholders);
+        placeholders
+    }
+
+    /// Replace the placeholders by the values, or by their defaults.
+    /// The values are typed as they are, they are not parsed as keys.
+    pub fn fill(&mut self, values: &HashMap<String, String>) {
+        fill_steps(&mut self.steps, values);
+    }
+
+    /// The keycode shown by the previews, the keys in the keymap are replaced
+    /// by their codes, e.g. `ctrl.1 c.1 c.0 ctrl.0 [WAIT]250 [STR]+ text[STR]`
+    pub fn to_keycode(&self, keymap: &HashMap<String, String>) -> String {
+        let mut result = Vec::new();
+        push_keycode(&self.steps, keymap, &mut result);
+        result.join(" ")
+    }
+}
+
+fn push_keycode(steps: &[Step], keymap: &HashMap<String, String>, result: &mut Vec<String>) {
+    let code = |key: &String| keymap.get(key).unwrap_or(key).clone();
+    for step in steps {
+        match &step.action {
+            Action::Chord(keys) => {
+                result.extend(keys.iter().map(|key| format!("{}.1", code(key))));
+                result.extend(keys.iter().rev().map(|key| format!("{}.0", code(key))));
+            }
+            Action::Text(text) => result.push(format!("[STR]+ {}[STR]", text)),
+            Action::Wait(ms) => result.push(format!("[WAIT]{}", ms)),
+            Action::Hold(keys, ms) => {
+                result.extend(keys.iter().map(|key| format!("{}.1", code(key))));
+                result.push(format!("[WAIT]{}", ms));
+                result.extend(keys.iter().rev().map(|key| format!("{}.0", code(key))));
+            }
+            Action::KeyDelay(ms) => result.push(format!("[DELAY]{}", ms)),
+            Action::Repeat(steps, count) => {
+                for _ in 0..*count {
+                    push_keycode(steps, keymap, result);
+                }
+            }
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    depth: usize, // How many groups are open
+}
+
+impl Parser {
+    fn error(&self, start: usize, end: usize, message: &str) -> SequenceError {
+        SequenceError {
+            start,
+            end,
+            message: message.to_string(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        let mut chars = self.chars[self.pos..].iter();
+        s.chars().all(|c| chars.next() == Some(&c))
+    }
+
+    fn find(&self, s: &str) -> Option<usize> {
+        let needle: Vec<char> = s.chars().collect();
+        self.chars[self.pos..]
+            .windows(needle.len())
+            .position(|w| w == needle.as_slice())
+            .map(|i| self.pos + i)
+    }
+
+    /// Whether the step ends here, at a whitespace, a `[STR]`, the `)` of a group or the end
+    fn at_step_end(&self) -> bool {
+        match self.peek() {
+            None => true,
+            Some(c) => {
+                c.is_whitespace() || self.starts_with("[STR]") || (self.depth > 0 && c == ')')
+            }
+        }
+    }
+
+    /// Read the steps until the end, or until the `)` of the group opened at `group`
+    fn steps(&mut self, group: Option<usize>) -> Result<Vec<Step>, SequenceError> {
+        let mut steps: Vec<Step> = Vec::new();
+        loop {
+            while self.peek().is_some_and(|c| c.is_whitespace()) {
+                self.pos += 1;
+            }
+            let start = self.pos;
+            let Some(c) = self.peek() else {
+                return match group {
+                    None => Ok(steps),
+                    Some(open) => Err(self.error(open, open + 1, "Missing ')' of the group")),
+                };
+            };
+            if group.is_some() && c == ')' {
+                self.pos += 1;
+                return Ok(steps);
+            }
+            let action = if self.starts_with("[STR]+") {
+                let text = self.text();
+                if text.is_empty() {
+                    continue;
+                }
+                Action::Text(text)
+            } else if self.starts_with("[STR]") {
+                let message = "[STR] closes no text, start the text with [STR]+";
+                return Err(self.error(start, start + 5, message));
+            } else if self.starts_with("/*") {
+                self.comment()?;
+                continue;
+            } else if let Some(name) = self.directive_name() {
+                self.directive(name)?
+            } else if c == '"'
+                && self
+                    .chars
+                    .get(start + 1)
+                    .is_some_and(|c| !c.is_whitespace())
+            {
+                Action::Text(self.string()?)
+            } else if c == '('
+                && self
+                    .chars
+                    .get(start + 1)
+                    .is_some_and(|c| !c.is_whitespace())
+            {
+                self.group()?
+            } else {
+                self.word()?
+            };
+            // Pressing a modifier alone and then + is never meant
+            if action == Action::Chord(vec!["+".to_string()]) {
+                let after_modifier = steps.last().is_some_and(|step| {
+                    matches!(&step.action, Action::Chord(keys)
+                        if keys.len() == 1 && MODIFIERS.contains(&keys[0].as_str()))
+                });
+                if after_modifier {
+                    let message = "A chord is written without spaces, like ctrl+c";
+                    return Err(self.error(start, self.pos, message));
+                }
+            }
+            steps.push(Step {
+                action,
+                start,
+                end: self.pos,
+            });
+        }
+    }
+
+    /// Read a group of steps and its count, like `(tab shift+tab)*3`
+    fn group(&mut self) -> Result<Action, SequenceError> {
+        let start = self.pos;
+        self.pos += 1;
+        self.depth += 1;
+        let steps = self.steps(Some(start))?;
+        self.depth -= 1;
+        if steps.is_empty() {
+            return Err(self.error(start, self.pos, "Empty group"));
+        }
+        let count_start = self.pos;
+        while !self.at_step_end() {
+            self.pos += 1;
+        }
+        let count = match self.chars[count_start..self.pos].split_first() {
+            Some((&'*', digits)) => self.count(count_start + 1, digits)?,
+            _ => {
+                let message = "Expect a count after the group, like (tab)*3";
+                return Err(self.error(count_start, self.pos.max(count_start + 1), message));
+            }
+        };
+        Ok(Action::Repeat(steps, count))
+    }
+
+    /// Parse the digits starting at `start` as the count of a repeat
+    fn count(&self, start: usize, digits: &[char]) -> Result<usize, SequenceError> {
+        let end = start + digits.len();
+        let digits: String = digits.iter().collect();
+        match digits.parse::<usize>() {
+            Ok(0) => Err(self.error(start, end, "A repeat runs at least once")),
+            Ok(count) => Ok(count),
+            Err(_) => Err(self.error(start, end, "Expect a count like *3")),
+        }
+    }
+
+    /// Read a `[STR]+` block until the next `[STR]` or the end
+    fn text(&mut self) -> String {
+        self.pos += "[STR]+".len();
+        let end = self.find("[STR]").unwrap_or(self.chars.len());
+        let text: String = self.chars[self.pos..end].iter().collect();
+        self.pos = (end + "[STR]".len()).min(self.chars.len());
+        text.trim().to_string()
+    }
+
+    fn comment(&mut self) -> Result<(), SequenceError> {
+        let start = self.pos;
+        self.pos += 2;
+        let end = self
+            .find("*/")
+            .ok_or_else(|| self.error(start, start + 2, "Unterminated comment"))?;
+        self.pos = end + 2;
+        Ok(())
+    }
+
+    /// Read a string in double quotes
+    fn string(&mut self) -> Result<String, SequenceError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut text = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error(start, self.pos, "Unterminated string")),
+                Some('"') => break,
+                Some('\\') => {
+                    let escaped = match self.chars.get(self.pos + 1) {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        // Kept for the variables, see `exec::expand_variables`
+                        Some(brace @ ('{' | '}')) => {
+                            text.push('\\');
+                            *brace
+                        }
+                        _ => {
+                            let message = "Unknown escape, use \\\" \\\\ \\n \\t \\{ or \\}";
+                            return Err(self.error(self.pos, self.pos + 2, message));
+                        }
+                    };
+                    text.push(escaped);
+                    self.pos += 2;
+                    continue;
+                }
+                Some(c) => text.push(c),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        if text.is_empty() {
+            return Err(self.error(start, self.pos, "Empty string"));
+        }
+        Ok(text)
+    }
+
+    /// The directive starting here, a name followed by `(`
+    fn directive_name(&self) -> Option<&'static str> {
+        DIRECTIVES.into_iter().find(|name| {
+            self.starts_with(name) && self.chars.get(self.pos + name.len()) == Some(&'(')
+        })
+    }
+
+    /// Read a directive such as `hold(shift, 500ms)`
+    fn directive(&mut self, name: &str) -> Result<Action, SequenceError> {
+        let start = self.pos;
+        self.pos += name.len() + 1;
+        let close = self.find(")").ok_or_else(|| {
+            let message = format!("Missing ')' after the arguments of {}", name);
+            self.error(start, self.pos, &message)
+        })?;
+        // The start and end of each argument, trimmed
+        let mut args = Vec::new();
+        let mut arg_start = self.pos;
+        for i in self.pos..=close {
+            if i == close || self.chars[i] == ',' {
+                let mut s = arg_start;
+                let mut e = i;
+                while s < e && self.chars[s].is_whitespace() {
+                    s += 1;
+                }
+                while e > s && self.chars[e - 1].is_whitespace() {
+                    e -= 1;
+                }
+                args.push((s, e));
+                arg_start = i + 1;
+            }
+        }
+        self.pos = close + 1;
+        if !self.at_step_end() {
+            return Err(self.error(self.pos, self.pos + 1, "Expect a space after ')'"));
+        }
+        let usage = match name {
+            "hold" => "hold takes keys and a duration, like hold(shift, 500ms)",
+            "wait" => "wait takes a duration, like wait(250ms)",
+            _ => "keydelay takes a duration, like keydelay(30ms)",
+        };
+        let expected = if name == "hold" { 2 } else { 1 };
+        if args.len() != expected || args.iter().any(|(s, e)| s == e) {
+            return Err(self.error(start, self.pos, usage));
+        }
+        let (s, e) = args[expected - 1];
+        let ms = self.duration(s, e)?;
+        Ok(match name {
+            "hold" => Action::Hold(self.chord(args[0].0, args[0].1)?, ms),
+            "wait" => Action::Wait(ms),
+            _ => Action::KeyDelay(ms),
+        })
+    }
+
+    /// Parse the chars `start..end` as a duration in milliseconds, like 250ms or 2s
+    fn duration(&self, start: usize, end: usize) -> Result<u64, SequenceError> {
+        let text: String = self.chars[start..end].iter().collect();
+        let ms = match text.strip_suffix("ms") {
+            Some(ms) => ms.parse::<u64>().ok(),
+            None => text
+                .strip_suffix('s')
+                .and_then(|s| s.parse::<u64>().ok())
+                .and_then(|s| s.checked_mul(1000)),
+        };
+        let ms = ms.ok_or_else(|| self.error(start, end, "Expect a duration like 250ms or 2s"))?;
+        if ms > MAX_DURATION_MS {
+            return Err(self.error(start, end, "A duration is at most 60s"));
+        }
+        Ok(ms)
+    }
+
+    /// The end of the placeholder starting here, if one does
+    fn placeholder_end(&self) -> Option<usize> {
+        if !self.starts_with("{{") {
+            return None;
+        }
+        let end = self.find("}}")? + 2;
+        let text: String = self.chars[self.pos..end].iter().collect();
+        let starts_here = find_placeholders(&text)
+            .first()
+            .is_some_and(|(range, _)| range.start == 0);
+        starts_here.then_some(end)
+    }
+
+    /// Read until the next whitespace or `[STR]`, as a chord or a key
+    /// A key or a chord followed by `*` and digits is repeated, like `down*5`
+    fn word(&mut self) -> Result<Action, SequenceError> {
+        let start = self.pos;
+        while !self.at_step_end() {
+            if let Some(end) = self.placeholder_end() {
+                self.pos = end;
+                continue;
+            }
+            self.pos += 1;
+        }
+        let end = self.pos;
+        let digits = self.chars[start..end]
+            .iter()
+            .rev()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if digits > 0 && end - digits > start + 1 && self.chars[end - digits - 1] == '*' {
+            let star = end - digits - 1;
+            let step = Step {
+                action: Action::Chord(self.keys(start, star)?),
+                start,
+                end: star,
+            };
+            let count = self.count(star + 1, &self.chars[star + 1..end])?;
+            return Ok(Action::Repeat(vec![step], count));
+        }
+        self.keys(start, end).map(Action::Chord)
+    }
+
+    /// The chars `start..end` as a chord or a key, an unknown word is an error
+    fn keys(&self, start: usize, end: usize) -> Result<Vec<String>, SequenceError> {
+        let word: String = self.chars[start..end].iter().collect();
+        if word.chars().count() == 1 {
+            return Ok(vec![word]);
+        }
+        if let Some(key) = key_name(&word) {
+            return Ok(vec![key.to_string()]);
+        }
+        if word.contains('+') {
+            return self.chord(start, end);
+        }
+        let message = format!("Unknown key '{}', quote the text to type it", word);
+        Err(self.error(start, end, &message))
+    }
+
+    /// Parse the chars `start..end` as keys joined by `+`
+    fn chord(&self, start: usize, end: usize) -> Result<Vec<String>, SequenceError> {
+        let mut keys = Vec::new();
+        let mut i = start;
+        loop {
+            if i == end {
+                return Err(self.error(i - 1, i, "Missing key after '+'"));
+            }
+            let key_start = i;
+            if self.chars[i] == '+' {
+                i += 1; // The + key
+            } else {
+                while i < end && self.chars[i] != '+' {
+                    i += 1;
+                }
+            }
+            let key: String = self.chars[key_start..i].iter().collect();
+            let key = match key_name(&key) {
+                Some(name) => name.to_string(),
+                None if key.chars().count() == 1 => key.to_lowercase(),
+                None => {
+                    let message = format!("Unknown key '{}'", key);
+                    return Err(self.error(key_start, i, &message));
+                }
+            };
+            keys.push(key);
+            if i == end {
+                return Ok(keys);
+            }
+            if self.chars[i] != '+' {
+                return Err(self.error(i, i + 1, "Expect '+' between keys"));
+            }
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actions(shortcut: &str) -> Vec<Action> {
+        let sequence = Sequence::parse(shortcut).unwrap();
+        sequence.steps.into_iter().map(|step| step.action).collect()
+    }
+
+    fn chord(keys: &[&str]) -> Action {
+        Action::Chord(keys.iter().map(|k| k.to_string()).collect())
+    }
+
+    fn error(shortcut: &str) -> (usize, usize, String) {
+        let e = Sequence::parse(shortcut).unwrap_err();
+        (e.start, e.end, e.message)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            actions("Ctrl+Shift+T esc \":wq\" enter"),
+            vec![
+                chord(&["ctrl", "shift", "t"]),
+                chord(&["esc"]),
+                Action::Text(":wq".to_string()),
+                chord(&["enter"]),
+            ]
+        );
+        assert_eq!(
+            actions("ctrl++ pgdn"),
+            vec![chord(&["ctrl", "+"]), chord(&["pagedown"])]
+        );
+        // A lone " and + are keys, as in the registers of vim
+        assert_eq!(
+            actions("\" + y"),
+            vec![chord(&["\""]), chord(&["+"]), chord(&["y"])]
+        );
+        assert_eq!(
+            actions(r#"esc "say \"hi\"\n" /* a comment */ [STR]+ 123! @# [STR] tab"#),
+            vec![
+                chord(&["esc"]),
+                Action::Text("say \"hi\"\n".to_string()),
+                Action::Text("123! @#".to_string()),
+                chord(&["tab"]),
+            ]
+        );
+        assert_eq!(
+            actions("[STR]+ Hello World!"),
+            vec![Action::Text("Hello World!".to_string())]
+        );
+        assert_eq!(actions("  "), vec![]);
+
+        assert_eq!(
+            actions("keydelay(30ms) wait(2s) hold( ctrl+shift , 500ms ) hold(a,0ms)"),
+            vec![
+                Action::KeyDelay(30),
+                Action::Wait(2000),
+                Action::Hold(vec!["ctrl".to_string(), "shift".to_string()], 500),
+                Action::Hold(vec!["a".to_string()], 0),
+            ]
+        );
+        // Only a name followed by ( is a directive
+        assert_eq!(
+            actions("\"wait\" \":wait(1s)\""),
+            vec![
+                Action::Text("wait".to_string()),
+                Action::Text(":wait(1s)".to_string()),
+            ]
+        );
+
+        let step = |action, start, end| Step { action, start, end };
+        assert_eq!(
+            actions("down*5 (tab shift+tab)*3"),
+            vec![
+                Action::Repeat(vec![step(chord(&["down"]), 0, 4)], 5),
+                Action::Repeat(
+                    vec![
+                        step(chord(&["tab"]), 8, 11),
+                        step(chord(&["shift", "tab"]), 12, 21)
+                    ],
+                    3
+                ),
+            ]
+        );
+        // Nested, with a wait and a text closed by [STR]
+        assert_eq!(
+            actions("((j)*2 wait(5ms) [STR]+ x [STR])*10"),
+            vec![Action::Repeat(
+                vec![
+                    step(Action::Repeat(vec![step(chord(&["j"]), 2, 3)], 2), 1, 6),
+                    step(Action::Wait(5), 7, 16),
+                    step(Action::Text("x".to_string()), 17, 31),
+                ],
+                10
+            )]
+        );
+        // A lone ( is the key
+        assert_eq!(
+            actions("( ) * x*2"),
+            vec![
+                chord(&["("]),
+                chord(&[")"]),
+                chord(&["*"]),
+                Action::Repeat(vec![step(chord(&["x"]), 6, 7)], 2),
+            ]
+        );
+
+        let sequence = Sequence::parse("esc ctrl+s").unwrap();
+        assert_eq!((sequence.steps[1].start, sequence.steps[1].end), (4, 10));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            error("ctrl+pgdnn"),
+            (5, 10, "Unknown key 'pgdnn'".to_string())
+        );
+        assert_eq!(error("ctrl+"), (4, 5, "Missing key after '+'".to_string()));
+        // A word that is no key is a typo or a text to quote
+        assert_eq!(
+            error("tab entr"),
+            (
+                4,
+                8,
+                "Unknown key 'entr', quote the text to type it".to_string()
+            )
+        );
+        assert_eq!(error("esc :wq*3"), error("esc :wq"));
+        assert_eq!(
+            error("wait").2,
+            "Unknown key 'wait', quote the text to type it"
+        );
+        assert_eq!(error("+c").0, 1);
+        assert_eq!(error("ctrl + c").0, 5);
+        assert_eq!(error("tab [STR] enter").0, 4);
+        assert_eq!(error("\"abc").0, 0);
+        assert_eq!(error("\"a\\qb\"").0, 2);
+        assert_eq!(error("a /* b").0, 2);
+        assert_eq!(
+            error("wait(250)"),
+            (5, 8, "Expect a duration like 250ms or 2s".to_string())
+        );
+        assert_eq!(error("wait(61s)").2, "A duration is at most 60s");
+        assert_eq!(error("x wait(1s").0, 2);
+        assert_eq!(error("wait(1s)enter").0, 8);
+        assert_eq!(
+            error("hold(500ms)"),
+            (
+                0,
+                11,
+                "hold takes keys and a duration, like hold(shift, 500ms)".to_string()
+            )
+        );
+        assert_eq!(error("hold()").1, 6);
+        assert_eq!(
+            error("(tab enter"),
+            (0, 1, "Missing ')' of the group".to_string())
+        );
+        assert_eq!(
+            error("(tab)x"),
+            (
+                5,
+                6,
+                "Expect a count after the group, like (tab)*3".to_string()
+            )
+        );
+        assert_eq!(error("(tab)").0, 5);
+        assert_eq!(error("down*0").2, "A repeat runs at least once");
+        assert_eq!(
+            error("(tab)*99999999999999999999").2,
+            "Expect a count like *3"
+        );
+        assert_eq!(error("(/* x */)*2").2, "Empty group");
+
+        let e = Sequence::parse_limited("esc (down*10 up)*10", 100).unwrap_err();
+        assert_eq!((e.start, e.end), (4, 19));
+        assert_eq!(
+            e.message,
+            "Runs more than 100 steps once repeated, see repeat_limit"
+        );
+        assert!(Sequence::parse_limited("(down*9 up)*10", 100).is_ok());
+        assert!(Sequence::parse_limited("((a*100000)*100000)*100000", 100).is_err());
+        assert_eq!(error("hold(shfit, 1s)").2, "Unknown key 'shfit'");
+
+        let e = Sequence::parse("ctrl+pgdnn").unwrap_err();
+        assert_eq!(e.pointed("ctrl+pgdnn"), "ctrl+pgdnn\n     ^^^^^");
+        assert_eq!(e.to_string(), "Unknown key 'pgdnn' at position 5");
+    }
+
+    #[test]
+    fn test_total_wait() {
+        let e = Sequence::parse_limited("esc (wait(60s))*200", 1000).unwrap_err();
+        assert_eq!((e.start, e.end), (4, 19));
+        assert_eq!(e.message, "Waits more than 60s in total");
+        assert!(Sequence::parse_limited("wait(30s) hold(shift, 30s)", 1000).is_ok());
+        assert!(Sequence::parse_limited("wait(30s) hold(shift, 30s) a", 1000).is_ok());
+        assert!(Sequence::parse_limited("keydelay(1s) (ctrl+c)*15", 1000).is_ok());
+        assert!(Sequence::parse_limited("keydelay(1s) (ctrl+c)*16", 1000).is_err());
+        assert!(Sequence::parse_limited("(keydelay(10s) a)*3 wait(1s)", 1000).is_err());
+    }
+
+    #[test]
+    fn test_to_keycode() {
+        let mut keymap = HashMap::new();
+        keymap.insert("meta".to_string(), "126".to_string());
+        let sequence = Sequence::parse("win+s \"a b\" \"gg\"").unwrap();
+        assert_eq!(
+            sequence.to_keycode(&keymap),
+            "126.1 s.1 s.0 126.0 [STR]+ a b[STR] [STR]+ gg[STR]"
+        );
+        let sequence = Sequence::parse("keydelay(20ms) hold(win+a, 1s) wait(5ms) tab").unwrap();
+        assert_eq!(
+            sequence.to_keycode(&keymap),
+            "[DELAY]20 126.1 a.1 [WAIT]1000 a.0 126.0 [WAIT]5 tab.1 tab.0"
+        );
+        let sequence = Sequence::parse("(j win+k)*2").unwrap();
+        assert_eq!(
+            sequence.to_keycode(&keymap),
+            "j.1 j.0 126.1 k.1 k.0 126.0 j.1 j.0 126.1 k.1 k.0 126.0"
+        );
+    }
+
+    #[test]
+    fn test_placeholders() {
+        // A placeholder is quoted, the whole of it is pointed out when it is not
+        assert_eq!(
+            actions("ctrl+l \"{{url:about blank}}\" enter"),
+            vec![
+                chord(&["ctrl", "l"]),
+                Action::Text("{{url:about blank}}".to_string()),
+                chord(&["enter"]),
+            ]
+        );
+        assert_eq!(error("ctrl+l {{url:about blank}} enter").1, 26);
+        let mut sequence = Sequence::parse(
+            r#""git commit -m '{{message}}'" ("{{branch:main}}" tab)*2 "{{message}} {{ not one }}""#,
+        )
+        .unwrap();
+        assert_eq!(
+            sequence.placeholders(),
+            vec![
+                Placeholder {
+                    name: "message".to_string(),
+                    ..Default::default()
+                },
+                Placeholder {
+                    name: "branch".to_string(),
+                    default: Some("main".to_string()),
+                    ..Default::default()
+                },
+            ]
+        );
+
+        // The values are typed, even the ones that look like keys or placeholders
+        let values = HashMap::from([("message".to_string(), "ctrl+c {{branch}}".to_string())]);
+        sequence.fill(&values);
+        assert_eq!(
+            sequence.to_keycode(&HashMap::new()),
+            "[STR]+ git commit -m 'ctrl+c \\{\\{branch\\}\\}'[STR] [STR]+ main[STR] tab.1 tab.0 \
+             [STR]+ main[STR] tab.1 tab.0 [STR]+ ctrl+c \\{\\{branch\\}\\} {{ not one }}[STR]"
+        );
+        assert!(Sequence::parse("esc \":wq\"")
+            .unwrap()
+            .placeholders()
+            .is_empty());
+    }
+}
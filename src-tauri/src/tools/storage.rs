@@ -0,0 +1,816 @@
+use rusqlite::{params, Connection, Row};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use super::db::{
+    DeleteReason, DeletedShortcut, MusicSheetDBTable, Revision, Shortcut, SCHEMA_VERSION,
+};
+use super::utils::{atomic_write, id_to_string, now_timestamp, string_to_id};
+
+/// A storage backend that keeps the music sheet on disk.
+///
+/// `MusicSheetDB` keeps the whole table in memory and forwards every mutation
+/// to its storage. Incremental backends (SQLite) write each change right away,
+/// while snapshot backends (the JSON lock file) ignore the incremental calls and
+/// rewrite everything on `persist`.
+pub trait SheetStorage: fmt::Debug + Send {
+    /// Load the whole table from the storage
+    fn load(&mut self) -> Result<MusicSheetDBTable, Box<dyn Error>>;
+
+    /// Insert the shortcuts into data, replacing the rows with the same id
+    fn put_shortcuts(&mut self, shortcuts: &[Shortcut]) -> Result<(), Box<dyn Error>>;
+
+    /// Remove the shortcuts of the given ids from data
+    fn remove_shortcuts(&mut self, ids: &[u128]) -> Result<(), Box<dyn Error>>;
+
+    /// Append the entries to the deleted list
+    fn put_deleted(&mut self, deleted: &[DeletedShortcut]) -> Result<(), Box<dyn Error>>;
+
+    /// Replace the whole deleted list, used when entries are restored or purged
+    fn set_deleted(&mut self, deleted: &[DeletedShortcut]) -> Result<(), Box<dyn Error>>;
+
+    /// Write the usage of one shortcut in data: hit_number, last_used and frecency
+    fn put_usage(&mut self, shortcut: &Shortcut) -> Result<(), Box<dyn Error>>;
+
+    /// Insert the revisions, replacing the ones with the same id and revision number
+    fn put_revisions(&mut self, revisions: &[Revision]) -> Result<(), Box<dyn Error>>;
+
+    /// Remove the revisions of the given (id, revision number) pairs
+    fn remove_revisions(&mut self, keys: &[(u128, u64)]) -> Result<(), Box<dyn Error>>;
+
+    /// Replace everything in the storage by the given table
+    fn replace_all(&mut self, t: &MusicSheetDBTable) -> Result<(), Box<dyn Error>>;
+
+    /// Make sure the whole table is on disk
+    fn persist(&mut self, t: &MusicSheetDBTable) -> Result<(), Box<dyn Error>>;
+}
+
+/// The original storage: the whole table serialized into one JSON lock file.
+#[derive(Debug)]
+pub struct JsonStorage {
+    path: String,
+}
+
+impl JsonStorage {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+        }
+    }
+}
+
+/// Read a table from a JSON lock file
+/// Files written by an older version are migrated in memory, and the original file
+/// is copied to `<file>.v<version>.bak` first since the next persist overwrites it.
+pub fn read_json_table(file_path: &str) -> Result<MusicSheetDBTable, Box<dyn Error>> {
+    let content = fs::read(file_path)?;
+    let (t, version) = MusicSheetDBTable::from_json_slice(&content)?;
+    if version < SCHEMA_VERSION {
+        let backup_path = format!("{}.v{}.bak", file_path, version);
+        if !Path::new(&backup_path).exists() {
+            atomic_write(Path::new(&backup_path), &content)?;
+        }
+        eprintln!(
+            "Migrated {} from schema version {} to {}, the original is kept at {}",
+            file_path, version, SCHEMA_VERSION, backup_path
+        );
+    }
+    Ok(t)
+}
+
+impl SheetStorage for JsonStorage {
+    fn load(&mut self) -> Result<MusicSheetDBTable, Box<dyn Error>> {
+        if !Path::new(&self.path).exists() {
+            return Ok(MusicSheetDBTable::new());
+        }
+        read_json_table(&self.path)
+    }
+
+    fn put_shortcuts(&mut self, _shortcuts: &[Shortcut]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn remove_shortcuts(&mut self, _ids: &[u128]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn put_deleted(&mut self, _deleted: &[DeletedShortcut]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn set_deleted(&mut self, _deleted: &[DeletedShortcut]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn put_usage(&mut self, _shortcut: &Shortcut) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn put_revisions(&mut self, _revisions: &[Revision]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn remove_revisions(&mut self, _keys: &[(u128, u64)]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn replace_all(&mut self, t: &MusicSheetDBTable) -> Result<(), Box<dyn Error>> {
+        self.persist(t)
+    }
+
+    fn persist(&mut self, t: &MusicSheetDBTable) -> Result<(), Box<dyn Error>> {
+        let content = serde_json::to_vec(t)?;
+        atomic_write(Path::new(&self.path), &content)?;
+        Ok(())
+    }
+}
+
+/// SQLite storage, every mutation only touches the rows involved.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    conn: Connection,
+}
+
+const SHORTCUT_COLUMNS: &str = "id, hit_number, shortcut, application, description, comment, \
+    tags, last_used, frecency, placeholders";
+const SHORTCUT_COLUMN_COUNT: usize = 10;
+const SQLITE_SCHEMA_VERSION: i64 = 6;
+
+impl SqliteStorage {
+    /// Open (or create) the database at the given path.
+    ///
+    /// If the database is newly created and `legacy_json_path` points to an existing
+    /// lock file, its content is migrated into the database, and the lock file is
+    /// renamed to `<lock file>.migrated` so that it is kept as a backup.
+    /// A new database is removed if it cannot be set up, so the next start migrates again.
+    pub fn open(db_path: &str, legacy_json_path: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let is_new = !Path::new(db_path).exists();
+        let result = Self::open_and_migrate(db_path, legacy_json_path.filter(|_| is_new));
+        if result.is_err() && is_new {
+            let _ = fs::remove_file(db_path);
+            let _ = fs::remove_file(format!("{}-journal", db_path));
+        }
+        result
+    }
+
+    fn open_and_migrate(
+        db_path: &str,
+        legacy_json_path: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut storage = Self {
+            conn: Connection::open(db_path)?,
+        };
+        storage.create_tables()?;
+
+        if let Some(json_path) = legacy_json_path {
+            if Path::new(json_path).exists() {
+                let t = read_json_table(json_path)?;
+                storage.replace_all(&t)?;
+                std::fs::rename(json_path, format!("{}.migrated", json_path))?;
+                eprintln!(
+                    "Migrated {} shortcuts from {} into {}",
+                    t.data.len(),
+                    json_path,
+                    db_path
+                );
+            }
+        }
+        Ok(storage)
+    }
+
+    /// Open a database living only in memory, mostly for testing.
+    pub fn open_in_memory() -> Result<Self, Box<dyn Error>> {
+        let mut storage = Self {
+            conn: Connection::open_in_memory()?,
+        };
+        storage.create_tables()?;
+        Ok(storage)
+    }
+
+    /// Create the tables, or bring the tables of an older database up to date.
+    /// The schema version is kept in `PRAGMA user_version`, and bumped in the same
+    /// transaction as the steps, so an upgrade stopped halfway is run again from the start.
+    fn create_tables(&mut self) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        let version: i64 = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > SQLITE_SCHEMA_VERSION {
+            return Err(format!(
+                "The database schema version {} is newer than supported ({}), please upgrade Liz",
+                version, SQLITE_SCHEMA_VERSION
+            )
+            .into());
+        }
+        if version < 1 {
+            tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS shortcuts (
+                    id          TEXT PRIMARY KEY,
+                    hit_number  INTEGER NOT NULL DEFAULT 0,
+                    shortcut    TEXT NOT NULL DEFAULT '',
+                    application TEXT NOT NULL DEFAULT '',
+                    description TEXT NOT NULL DEFAULT '',
+                    comment     TEXT NOT NULL DEFAULT ''
+                );
+                CREATE TABLE IF NOT EXISTS deleted (
+                    seq         INTEGER PRIMARY KEY AUTOINCREMENT,
+                    id          TEXT NOT NULL,
+                    hit_number  INTEGER NOT NULL DEFAULT 0,
+                    shortcut    TEXT NOT NULL DEFAULT '',
+                    application TEXT NOT NULL DEFAULT '',
+                    description TEXT NOT NULL DEFAULT '',
+                    comment     TEXT NOT NULL DEFAULT ''
+                );",
+            )?;
+        }
+        if version < 2 {
+            tx.execute_batch(
+                "ALTER TABLE deleted ADD COLUMN deleted_at INTEGER NOT NULL DEFAULT 0;
+                ALTER TABLE deleted ADD COLUMN reason TEXT NOT NULL DEFAULT 'delete';",
+            )?;
+        }
+        if version < 3 {
+            tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS revisions (
+                    id          TEXT NOT NULL,
+                    hit_number  INTEGER NOT NULL DEFAULT 0,
+                    shortcut    TEXT NOT NULL DEFAULT '',
+                    application TEXT NOT NULL DEFAULT '',
+                    description TEXT NOT NULL DEFAULT '',
+                    comment     TEXT NOT NULL DEFAULT '',
+                    revision    INTEGER NOT NULL,
+                    timestamp   INTEGER NOT NULL DEFAULT 0,
+                    diffs       TEXT NOT NULL DEFAULT '[]',
+                    PRIMARY KEY (id, revision)
+                );",
+            )?;
+        }
+        if version < 4 {
+            tx.execute_batch(
+                "ALTER TABLE shortcuts ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';
+                ALTER TABLE deleted ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';
+                ALTER TABLE revisions ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';",
+            )?;
+        }
+        if version < 5 {
+            // The frecency starts from hit_number as of now, as in `db::migrate_v4_to_v5`
+            for table in ["shortcuts", "deleted", "revisions"] {
+                tx.execute_batch(&format!(
+                    "ALTER TABLE {0} ADD COLUMN last_used INTEGER NOT NULL DEFAULT 0;
+                    ALTER TABLE {0} ADD COLUMN frecency REAL NOT NULL DEFAULT 0;
+                    UPDATE {0} SET last_used = {1}, frecency = hit_number;",
+                    table,
+                    now_timestamp()
+                ))?;
+            }
+        }
+        if version < 6 {
+            for table in ["shortcuts", "deleted", "revisions"] {
+                tx.execute_batch(&format!(
+                    "ALTER TABLE {} ADD COLUMN placeholders TEXT NOT NULL DEFAULT '[]';",
+                    table
+                ))?;
+            }
+        }
+        tx.execute_batch(&format!("PRAGMA user_version = {}", SQLITE_SCHEMA_VERSION))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn select_shortcuts(&self) -> Result<Vec<Shortcut>, Box<dyn Error>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT {} FROM shortcuts", SHORTCUT_COLUMNS))?;
+        let rows = stmt.query_map([], row_to_shortcut)?;
+        let mut shortcuts = Vec::new();
+        for row in rows {
+            shortcuts.push(row??);
+        }
+        Ok(shortcuts)
+    }
+
+    fn select_deleted(&self) -> Result<Vec<DeletedShortcut>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, deleted_at, reason FROM deleted ORDER BY seq",
+            SHORTCUT_COLUMNS
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row_to_shortcut(row)?,
+                row.get::<_, i64>(SHORTCUT_COLUMN_COUNT)?,
+                row.get::<_, String>(SHORTCUT_COLUMN_COUNT + 1)?,
+            ))
+        })?;
+        let mut deleted = Vec::new();
+        for row in rows {
+            let (shortcut, deleted_at, reason) = row?;
+            deleted.push(DeletedShortcut {
+                shortcut: shortcut?,
+                deleted_at,
+                reason: DeleteReason::parse(&reason),
+            });
+        }
+        Ok(deleted)
+    }
+
+    fn select_revisions(&self) -> Result<Vec<Revision>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, revision, timestamp, diffs FROM revisions ORDER BY id, revision",
+            SHORTCUT_COLUMNS
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row_to_shortcut(row)?,
+                row.get::<_, u64>(SHORTCUT_COLUMN_COUNT)?,
+                row.get::<_, i64>(SHORTCUT_COLUMN_COUNT + 1)?,
+                row.get::<_, String>(SHORTCUT_COLUMN_COUNT + 2)?,
+            ))
+        })?;
+        let mut revisions = Vec::new();
+        for row in rows {
+            let (shortcut, revision, timestamp, diffs) = row?;
+            revisions.push(Revision {
+                revision,
+                timestamp,
+                shortcut: shortcut?,
+                diffs: serde_json::from_str(&diffs)?,
+            });
+        }
+        Ok(revisions)
+    }
+
+    /// Number of rows in data, mostly for testing.
+    pub fn count(&self) -> Result<usize, Box<dyn Error>> {
+        let n: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM shortcuts", [], |row| row.get(0))?;
+        Ok(n as usize)
+    }
+}
+
+/// Read the shortcut columns of one row, the inner Result is for the id, tags and
+/// placeholders parsing
+fn row_to_shortcut(row: &Row) -> rusqlite::Result<Result<Shortcut, Box<dyn Error>>> {
+    let id_str: String = row.get(0)?;
+    let tags_str: String = row.get(6)?;
+    let placeholders_str: String = row.get(9)?;
+    let sc = Shortcut {
+        id: 0,
+        hit_number: row.get(1)?,
+        shortcut: row.get(2)?,
+        application: row.get(3)?,
+        description: row.get(4)?,
+        comment: row.get(5)?,
+        tags: Vec::new(),
+        last_used: row.get(7)?,
+        frecency: row.get(8)?,
+        placeholders: Vec::new(),
+    };
+    Ok(string_to_id(&id_str).and_then(|id| {
+        let tags: Vec<String> = serde_json::from_str(&tags_str)?;
+        let placeholders = serde_json::from_str(&placeholders_str)?;
+        Ok(Shortcut {
+            id,
+            tags,
+            placeholders,
+            ..sc
+        })
+    }))
+}
+
+fn insert_shortcuts(conn: &Connection, shortcuts: &[Shortcut]) -> Result<(), Box<dyn Error>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "INSERT OR REPLACE INTO shortcuts ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        SHORTCUT_COLUMNS
+    ))?;
+    for sc in shortcuts {
+        stmt.execute(params![
+            id_to_string(sc.id),
+            sc.hit_number,
+            sc.shortcut,
+            sc.application,
+            sc.description,
+            sc.comment,
+            serde_json::to_string(&sc.tags)?,
+            sc.last_used,
+            sc.frecency,
+            serde_json::to_string(&sc.placeholders)?,
+        ])?;
+    }
+    Ok(())
+}
+
+fn insert_deleted(conn: &Connection, deleted: &[DeletedShortcut]) -> Result<(), Box<dyn Error>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "INSERT INTO deleted ({}, deleted_at, reason)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        SHORTCUT_COLUMNS
+    ))?;
+    for d in deleted {
+        let sc = &d.shortcut;
+        stmt.execute(params![
+            id_to_string(sc.id),
+            sc.hit_number,
+            sc.shortcut,
+            sc.application,
+            sc.description,
+            sc.comment,
+            serde_json::to_string(&sc.tags)?,
+            sc.last_used,
+            sc.frecency,
+            serde_json::to_string(&sc.placeholders)?,
+            d.deleted_at,
+            d.reason.as_str(),
+        ])?;
+    }
+    Ok(())
+}
+
+fn insert_revisions(conn: &Connection, revisions: &[Revision]) -> Result<(), Box<dyn Error>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "INSERT OR REPLACE INTO revisions ({}, revision, timestamp, diffs)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        SHORTCUT_COLUMNS
+    ))?;
+    for r in revisions {
+        let sc = &r.shortcut;
+        stmt.execute(params![
+            id_to_string(sc.id),
+            sc.hit_number,
+            sc.shortcut,
+            sc.application,
+            sc.description,
+            sc.comment,
+            serde_json::to_string(&sc.tags)?,
+            sc.last_used,
+            sc.frecency,
+            serde_json::to_string(&sc.placeholders)?,
+            r.revision,
+            r.timestamp,
+            serde_json::to_string(&r.diffs)?,
+        ])?;
+    }
+    Ok(())
+}
+
+impl SheetStorage for SqliteStorage {
+    fn load(&mut self) -> Result<MusicSheetDBTable, Box<dyn Error>> {
+        let mut t = MusicSheetDBTable::new();
+        t.data = self.select_shortcuts()?;
+        t.deleted = self.select_deleted()?;
+        t.revisions = self.select_revisions()?;
+        Ok(t)
+    }
+
+    fn put_shortcuts(&mut self, shortcuts: &[Shortcut]) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        insert_shortcuts(&tx, shortcuts)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove_shortcuts(&mut self, ids: &[u128]) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached("DELETE FROM shortcuts WHERE id = ?1")?;
+            for id in ids {
+                stmt.execute(params![id_to_string(*id)])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn put_deleted(&mut self, deleted: &[DeletedShortcut]) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        insert_deleted(&tx, deleted)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn set_deleted(&mut self, deleted: &[DeletedShortcut]) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM deleted", [])?;
+        insert_deleted(&tx, deleted)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn put_usage(&mut self, shortcut: &Shortcut) -> Result<(), Box<dyn Error>> {
+        self.conn.execute(
+            "UPDATE shortcuts SET hit_number = ?2, last_used = ?3, frecency = ?4 WHERE id = ?1",
+            params![
+                id_to_string(shortcut.id),
+                shortcut.hit_number,
+                shortcut.last_used,
+                shortcut.frecency,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn put_revisions(&mut self, revisions: &[Revision]) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        insert_revisions(&tx, revisions)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove_revisions(&mut self, keys: &[(u128, u64)]) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt =
+                tx.prepare_cached("DELETE FROM revisions WHERE id = ?1 AND revision = ?2")?;
+            for (id, revision) in keys {
+                stmt.execute(params![id_to_string(*id), revision])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn replace_all(&mut self, t: &MusicSheetDBTable) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM shortcuts", [])?;
+        tx.execute("DELETE FROM deleted", [])?;
+        tx.execute("DELETE FROM revisions", [])?;
+        insert_shortcuts(&tx, &t.data)?;
+        insert_deleted(&tx, &t.deleted)?;
+        insert_revisions(&tx, &t.revisions)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn persist(&mut self, _t: &MusicSheetDBTable) -> Result<(), Box<dyn Error>> {
+        // Every change is already written when it happens.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::db::MusicSheetDB;
+    use crate::tools::sequence::Placeholder;
+    use crate::tools::utils::generate_id;
+
+    fn shortcut(desc: &str) -> Shortcut {
+        Shortcut {
+            description: desc.to_string(),
+            shortcut: format!("ctrl+{}", desc),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sqlite_incremental_changes() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let a = shortcut("a");
+        let b = shortcut("b");
+        storage.put_shortcuts(&[a.clone(), b.clone()]).unwrap();
+        let a_hit = Shortcut {
+            hit_number: 2,
+            last_used: 100,
+            frecency: 1.5,
+            ..a.clone()
+        };
+        storage.put_usage(&a_hit).unwrap();
+
+        let mut b2 = b.clone();
+        b2.comment = "updated".to_string();
+        b2.tags = vec!["git".to_string()];
+        storage.put_shortcuts(&[b2]).unwrap();
+        storage
+            .put_deleted(&[DeletedShortcut::new(b.clone(), DeleteReason::Edit)])
+            .unwrap();
+        assert_eq!(storage.count().unwrap(), 2);
+
+        let t = storage.load().unwrap();
+        let a_loaded = t.data.iter().find(|sc| sc.id == a.id).unwrap();
+        assert_eq!(a_loaded.hit_number, 2);
+        assert_eq!(a_loaded.last_used, 100);
+        assert_eq!(a_loaded.frecency, 1.5);
+        let b_loaded = t.data.iter().find(|sc| sc.id == b.id).unwrap();
+        assert_eq!(b_loaded.comment, "updated");
+        assert_eq!(b_loaded.tags, vec!["git".to_string()]);
+        assert_eq!(t.deleted.len(), 1);
+        assert_eq!(t.deleted[0].shortcut.comment, "");
+        assert_eq!(t.deleted[0].reason, DeleteReason::Edit);
+        assert!(t.deleted[0].deleted_at > 0);
+
+        storage.remove_shortcuts(&[a.id]).unwrap();
+        storage.set_deleted(&[]).unwrap();
+        let t = storage.load().unwrap();
+        assert_eq!(t.data.len(), 1);
+        assert!(t.deleted.is_empty());
+    }
+
+    #[test]
+    fn test_db_writes_through_to_sqlite() {
+        let db_path = std::env::temp_dir().join(format!("liz_test_{}.db", generate_id()));
+        let db_path = db_path.to_str().unwrap();
+        let storage = SqliteStorage::open(db_path, None).unwrap();
+        let mut db = MusicSheetDB::with_storage(Box::new(storage)).unwrap();
+        let a = shortcut("a");
+        let dup = Shortcut {
+            id: a.id,
+            ..shortcut("dup")
+        };
+        let b = shortcut("b");
+        db.add_shortcuts(vec![a.clone(), dup, b.clone()], None);
+        db.delete_shortcuts(vec![a.id]);
+        let b2 = Shortcut {
+            comment: "edited".to_string(),
+            ..b.clone()
+        };
+        db.update_shortcuts(vec![b2]);
+
+        drop(db);
+
+        let storage = SqliteStorage::open(db_path, None).unwrap();
+        let mut db = MusicSheetDB::with_storage(Box::new(storage)).unwrap();
+        assert_eq!(db.retrieve_all().len(), 1);
+        assert_eq!(db.retrieve(b.id, None).unwrap().comment, "edited");
+        let deleted = db.retrieve_deleted();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].reason, DeleteReason::Delete);
+        let revisions = db.retrieve_revisions(b.id);
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].shortcut.comment, "");
+        assert_eq!(revisions[0].diffs[0].field, "comment");
+
+        // Reverting records the edited version as a new revision
+        assert!(db.revert_shortcut(b.id, 1).is_ok());
+        assert!(db.revert_shortcut(b.id, 9).is_err());
+        assert_eq!(db.retrieve(b.id, None).unwrap().comment, "");
+        assert_eq!(db.restore_shortcuts(&[a.id, b.id]), vec![b.id]);
+        assert_eq!(db.purge_deleted_older_than(3600), 0);
+        drop(db);
+
+        let storage = SqliteStorage::open(db_path, None).unwrap();
+        let mut db = MusicSheetDB::with_storage(Box::new(storage)).unwrap();
+        let revisions = db.retrieve_revisions(b.id);
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].revision, 2);
+        assert_eq!(revisions[0].shortcut.comment, "edited");
+        db.delete_shortcuts(vec![b.id]);
+        assert_eq!(db.purge_deleted(&[b.id]), 1);
+        assert!(db.retrieve_revisions(b.id).is_empty());
+        drop(db);
+
+        let t = SqliteStorage::open(db_path, None).unwrap().load().unwrap();
+        let _ = std::fs::remove_file(db_path);
+        assert_eq!(t.data.len(), 1);
+        assert!(t.deleted.is_empty());
+        assert!(t.revisions.is_empty());
+    }
+
+    #[test]
+    fn test_sqlite_placeholders() {
+        let db_path = std::env::temp_dir().join(format!("liz_test_{}.db", generate_id()));
+        let db_path = db_path.to_str().unwrap();
+        let storage = SqliteStorage::open(db_path, None).unwrap();
+        let mut db = MusicSheetDB::with_storage(Box::new(storage)).unwrap();
+        let placeholders = vec![Placeholder {
+            name: "b".to_string(),
+            default: Some("1".to_string()),
+            ..Default::default()
+        }];
+        let a = Shortcut {
+            placeholders: placeholders.clone(),
+            ..shortcut("a")
+        };
+        let b = Shortcut {
+            placeholders: placeholders.clone(),
+            ..shortcut("b")
+        };
+        db.add_shortcuts(vec![a.clone(), b.clone()], None);
+        db.delete_shortcuts(vec![a.id]);
+        db.update_shortcuts(vec![Shortcut {
+            placeholders: Vec::new(),
+            ..b.clone()
+        }]);
+        drop(db);
+
+        let t = SqliteStorage::open(db_path, None).unwrap().load().unwrap();
+        let _ = std::fs::remove_file(db_path);
+        assert!(t.data[0].placeholders.is_empty());
+        assert_eq!(t.deleted[0].shortcut.placeholders, placeholders);
+        assert_eq!(t.revisions[0].shortcut.placeholders, placeholders);
+        assert_eq!(t.revisions[0].diffs[0].field, "placeholders");
+    }
+
+    #[test]
+    fn test_sqlite_upgrade_is_atomic() {
+        let db_path = std::env::temp_dir().join(format!("liz_test_{}.db", generate_id()));
+        let db_path = db_path.to_str().unwrap();
+        drop(SqliteStorage::open(db_path, None).unwrap());
+        let columns = |table: &str| -> Vec<String> {
+            let conn = Connection::open(db_path).unwrap();
+            let mut stmt = conn
+                .prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))
+                .unwrap();
+            let names = stmt.query_map([], |row| row.get(0)).unwrap();
+            names.map(|name| name.unwrap()).collect()
+        };
+        // A version 5 database where the last step fails on its second table
+        let conn = Connection::open(db_path).unwrap();
+        conn.execute_batch(
+            "ALTER TABLE shortcuts DROP COLUMN placeholders;
+            ALTER TABLE revisions DROP COLUMN placeholders;
+            ALTER TABLE deleted RENAME TO gone;
+            PRAGMA user_version = 5;",
+        )
+        .unwrap();
+        drop(conn);
+        assert!(SqliteStorage::open(db_path, None).is_err());
+        assert!(!columns("shortcuts").contains(&"placeholders".to_string()));
+
+        // It is run again from the start once it can succeed
+        let conn = Connection::open(db_path).unwrap();
+        conn.execute_batch(
+            "ALTER TABLE gone RENAME TO deleted;
+            ALTER TABLE deleted DROP COLUMN placeholders;",
+        )
+        .unwrap();
+        drop(conn);
+        assert!(SqliteStorage::open(db_path, None).is_ok());
+        assert!(columns("shortcuts").contains(&"placeholders".to_string()));
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_sqlite_usage_migration() {
+        let db_path = std::env::temp_dir().join(format!("liz_test_{}.db", generate_id()));
+        let db_path = db_path.to_str().unwrap();
+        drop(SqliteStorage::open(db_path, None).unwrap());
+        // Back to a version 4 database with one shortcut hit 7 times
+        let conn = Connection::open(db_path).unwrap();
+        for table in ["shortcuts", "deleted", "revisions"] {
+            conn.execute_batch(&format!(
+                "ALTER TABLE {0} DROP COLUMN placeholders;
+                ALTER TABLE {0} DROP COLUMN frecency;
+                ALTER TABLE {0} DROP COLUMN last_used;",
+                table
+            ))
+            .unwrap();
+        }
+        conn.execute_batch(&format!(
+            "INSERT INTO shortcuts (id, hit_number) VALUES ('{}', 7);
+            PRAGMA user_version = 4;",
+            id_to_string(1)
+        ))
+        .unwrap();
+        drop(conn);
+
+        let t = SqliteStorage::open(db_path, None).unwrap().load().unwrap();
+        let _ = std::fs::remove_file(db_path);
+        assert_eq!(t.data[0].frecency, 7.0);
+        assert!(t.data[0].last_used >= now_timestamp() - 60);
+    }
+
+    #[test]
+    fn test_sqlite_migrates_lock_file() {
+        let dir = std::env::temp_dir().join(format!("liz_test_{}", generate_id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("music_sheet.db");
+        let db_path = db_path.to_str().unwrap();
+        let json_path = dir.join("music_sheet.lock");
+        let json_path = json_path.to_str().unwrap();
+
+        // A lock file that cannot be read leaves no database behind
+        std::fs::write(json_path, "{\"data\": [").unwrap();
+        assert!(SqliteStorage::open(db_path, Some(json_path)).is_err());
+        assert!(!Path::new(db_path).exists());
+
+        // So the next start still migrates it
+        let legacy = r#"{"deleted": [], "data": [{"id": "00000000-0000-0000-0000-000000000001",
+            "hit_number": 3, "shortcut": "ctrl+a", "application": "", "description": "all",
+            "comment": ""}]}"#;
+        std::fs::write(json_path, legacy).unwrap();
+        let mut storage = SqliteStorage::open(db_path, Some(json_path)).unwrap();
+        assert_eq!(storage.load().unwrap().data[0].description, "all");
+        assert!(!Path::new(json_path).exists());
+        assert!(Path::new(&format!("{}.migrated", json_path)).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_json_storage_migrates_legacy_file() {
+        let path = std::env::temp_dir().join(format!("liz_test_{}.json", generate_id()));
+        let path = path.to_str().unwrap();
+        let legacy = r#"{"deleted": [], "data": []}"#;
+        std::fs::write(path, legacy).unwrap();
+
+        let mut storage = JsonStorage::new(path);
+        let t = storage.load().unwrap();
+        assert_eq!(t.schema_version, SCHEMA_VERSION);
+        storage.persist(&t).unwrap();
+
+        let backup_path = format!("{}.v1.bak", path);
+        assert_eq!(std::fs::read_to_string(&backup_path).unwrap(), legacy);
+        assert!(std::fs::read_to_string(path).unwrap().contains("schema_version"));
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(backup_path);
+    }
+}
@@ -1,5 +1,7 @@
 use std::env;
 use std::error::Error;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
@@ -50,6 +52,11 @@ pub fn generate_id() -> u128 {
     id
 }
 
+/// Current time as a unix timestamp in seconds
+pub fn now_timestamp() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
 // Convert String to u128 id
 pub fn string_to_id(s: &str) -> Result<u128, Box<dyn Error>> {
     let id: u128 = match Uuid::parse_str(s) {
@@ -69,6 +76,66 @@ pub fn id_to_string(n: u128) -> String {
     uuid.to_string()
 }
 
+/// Write the content to a file without ever leaving it half written.
+/// The content goes to a temp file next to the target, which is synced to disk
+/// and then renamed over the target. The temp file is named after the process and
+/// a random suffix, so two writers never share it.
+pub fn atomic_write(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        &Uuid::new_v4().simple().to_string()[..8]
+    ));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)?;
+    if let Err(e) = file.write_all(content).and_then(|_| file.sync_all()) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    drop(file);
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// A lock held by the running app for as long as it runs, so that `liz` on the command line
+/// does not change the music sheet behind it, which the app would overwrite later
+#[derive(Debug)]
+pub struct InstanceLock {
+    /// Only kept open, the lock is released when the file is closed
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Take the lock of the file, None if another process holds it
+    pub fn acquire(path: &Path) -> io::Result<Option<Self>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(InstanceLock { _file: file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,4 +165,39 @@ mod tests {
 
         assert_eq!(id, id2)
     }
+
+    #[test]
+    fn test_atomic_write() {
+        let dir = env::temp_dir().join(format!("liz_test_{}", generate_id()));
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("music_sheet.json");
+        atomic_write(&path, b"first").unwrap();
+        atomic_write(&path, b"second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || atomic_write(&path, format!("writer {}", i).as_bytes()))
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap().unwrap();
+        }
+        assert!(fs::read_to_string(&path).unwrap().starts_with("writer "));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(atomic_write(&dir.join("missing").join("a.json"), b"").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_instance_lock() {
+        let path = env::temp_dir().join(format!("liz_test_{}.instance", generate_id()));
+        let lock = InstanceLock::acquire(&path).unwrap();
+        assert!(lock.is_some());
+        assert!(InstanceLock::acquire(&path).unwrap().is_none());
+        drop(lock);
+        assert!(InstanceLock::acquire(&path).unwrap().is_some());
+        let _ = fs::remove_file(&path);
+    }
 }
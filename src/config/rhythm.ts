@@ -16,8 +16,9 @@ type BlueBirdResponse = {
 
 type RhythmSetting = {
     name: string;
-    value: string;
+    value: string | boolean;
     hint: string;
+    readonly?: boolean;
 }
 
 function formatString(input: string): string {
@@ -63,12 +64,21 @@ async function createSettingItem(setting: RhythmSetting): Promise<HTMLLabelEleme
         });
 
         inputElement = select;
+    } else if (typeof setting.value === "boolean") {
+        // A checkbox for the switches
+        const input = document.createElement('input');
+        input.type = "checkbox";
+        input.id = `input-${setting.name}`;
+        input.checked = setting.value;
+        input.disabled = setting.readonly === true;
+        inputElement = input;
     } else {
         // Create the input element for other settings
         const input = document.createElement('input');
         input.type = "text"; // Set the input type to text
         input.id = `input-${setting.name}`; // Generate a dynamic ID based on the name
         input.value = setting.value; // Set the input value
+        input.disabled = setting.readonly === true; // Status info, not a setting
         inputElement = input;
     }
     label.title = translations[hint_key] || setting.hint; // Set the hover hint using the title attribute
@@ -78,15 +88,19 @@ async function createSettingItem(setting: RhythmSetting): Promise<HTMLLabelEleme
     return label;
 }
 
-function getSettingsJson(): Record<string, string | number> {
-    const settingsObj: Record<string, string | number> = {}; // JSON object to store values
+function getSettingsJson(): Record<string, string | number | boolean> {
+    const settingsObj: Record<string, string | number | boolean> = {}; // JSON object to store values
 
     // Select all labels with class "rhythm-setting"
     const labels = document.querySelectorAll<HTMLLabelElement>(".rhythm-setting");
 
     labels.forEach(label => {
         const input = label.querySelector("input") || label.querySelector("select"); // Find input inside label
-        if (input) {
+        if (input instanceof HTMLInputElement && input.type === "checkbox") {
+            if (!input.disabled) {
+                settingsObj[label.id] = input.checked;
+            }
+        } else if (input && !input.disabled) {
             const value = input.value.trim(); // Get and trim the value
             if (value) {
                 // Convert to number if it's a valid numeric value, otherwise keep as string
@@ -26,8 +26,24 @@ type Shortcut = {
     application: string;
     description: string;
     comment: string;
+    tags: string[];
 };
 
+// The first result of a paged response
+type Page = {
+    total: number;
+    offset: number;
+    count: number;
+    next_cursor: string | null; // Asks the next page, null on the last page
+};
+
+const PAGE_SIZE = 100; // Rows fetched at once, more are fetched when scrolling down
+
+// Tags are edited as one comma separated string
+function parseTags(tags: string): string[] {
+    return tags.split(",").map(tag => tag.trim()).filter(tag => tag.length > 0);
+}
+
 document.addEventListener('DOMContentLoaded', async () => {
     const menuButton = document.getElementById("menu-button")!;
     const dropdownMenu = document.getElementById("dropdown-menu")!;
@@ -37,6 +53,10 @@ document.addEventListener('DOMContentLoaded', async () => {
     const tableBody = document.querySelector("#commands-table tbody")!;
     const editModal = document.getElementById("edit-modal")!;
     let total_cnt = 0;
+    let match_cnt = 0; // How many shortcuts the current query matches
+    let currentQuery = "";
+    let nextCursor: string | null = null;
+    let loadingMore = false;
 
     loadLanguage()
 
@@ -54,32 +74,43 @@ document.addEventListener('DOMContentLoaded', async () => {
     const counter = document.getElementById("shortcut-counter") as HTMLSpanElement;
 
     function updateCounter() {
-      const totalShortcuts = tableBody.children.length;
-      counter.textContent = `${totalShortcuts} / ${total_cnt}`;
+      counter.textContent = `${match_cnt} / ${total_cnt}`;
     }
 
-    // Fetch shortcuts from Rust via Tauri command
-    async function fetchShortcuts(query: string) {
+    // Fetch the first page of shortcuts from Rust via Tauri command, or the next one with the cursor
+    async function fetchShortcuts(query: string, cursor: string | null = null) {
         const response = await invoke<BlueBirdResponse>('send_command', {
-            cmd: { action: 'get_shortcut_details', args: [query] },
+            cmd: { action: 'get_shortcut_details', args: [query, cursor ?? "", PAGE_SIZE.toString()] },
         });
+        if (cursor && response.code === StateCode.FAIL) {
+            // The shortcuts changed since the cursor was given, start over
+            return fetchShortcuts(query);
+        }
         if (response.code !== StateCode.OK) {
             alert(`Failed to retrieve shortcuts because ${response.results.join("; ")}`)
+            return;
         }
-        const shortcuts = response.results.map((content) => {
+        const page = JSON.parse(response.results[0]) as Page;
+        const shortcuts = response.results.slice(1).map((content) => {
             // Parse the JSON string into a Shortcut
             return JSON.parse(content) as Shortcut;
         });
-        if (!query) total_cnt = shortcuts.length;
-
-        while (tableBody.firstChild) {
-            tableBody.removeChild(tableBody.firstChild);
-        } // Clear previous list
+        if (!query) total_cnt = page.total;
+        match_cnt = page.total;
+        currentQuery = query;
+        nextCursor = page.next_cursor;
+
+        if (!cursor) {
+            while (tableBody.firstChild) {
+                tableBody.removeChild(tableBody.firstChild);
+            } // Clear previous list
+        }
 
         // Populate Table
         shortcuts.forEach(cmd => {
             const row = document.createElement("tr");
             row.id = cmd.id
+            row.dataset.tags = (cmd.tags || []).join(", ");
             row.innerHTML = `<td>${cmd.application}</td><td title="${cmd.comment}">${cmd.description}</td><td>${cmd.shortcut}</td><td>${cmd.hit_number}</td>`;
             tableBody.appendChild(row);
         });
@@ -92,6 +123,25 @@ document.addEventListener('DOMContentLoaded', async () => {
         await fetchShortcuts("");
     });
 
+    // Fetch the next page of the current query, if there is one
+    async function fetchMore() {
+        if (!nextCursor || loadingMore) return;
+        loadingMore = true;
+        try {
+            await fetchShortcuts(currentQuery, nextCursor);
+        } finally {
+            loadingMore = false;
+        }
+    }
+
+    // Fetch the next page when the table is scrolled near its end
+    const tableContainer = document.getElementById("table-container")!;
+    tableContainer.addEventListener("scroll", () => {
+        if (tableContainer.scrollTop + tableContainer.clientHeight >= tableContainer.scrollHeight - 200) {
+            fetchMore();
+        }
+    });
+
     // Check instance to avoid possible error of ts
     if (searchBox instanceof HTMLInputElement) {
         // Add event listener for the 'keydown' event to check for Enter key
@@ -277,6 +327,7 @@ document.addEventListener('DOMContentLoaded', async () => {
         (document.getElementById("edit-desc") as HTMLInputElement).value = cells[1].textContent || "";
         (document.getElementById("edit-command") as HTMLInputElement).value = cells[2].textContent || "";
         (document.getElementById("edit-comment") as HTMLInputElement).value = cells[1].getAttribute('title') || "";
+        (document.getElementById("edit-tags") as HTMLInputElement).value = row.dataset.tags || "";
         (document.getElementById("edit-hit") as HTMLInputElement).value = cells[3].textContent || "";
         commandsSection.classList.add("hidden");
         editModal.classList.remove("hidden");
@@ -291,6 +342,7 @@ document.addEventListener('DOMContentLoaded', async () => {
         (document.getElementById("edit-desc") as HTMLInputElement).value = "";
         (document.getElementById("edit-command") as HTMLInputElement).value = "";
         (document.getElementById("edit-comment") as HTMLInputElement).value = "";
+        (document.getElementById("edit-tags") as HTMLInputElement).value = "";
         (document.getElementById("edit-hit") as HTMLInputElement).value = "0";
 
         // Show the edit modal and hide the commands section
@@ -367,6 +419,7 @@ document.addEventListener('DOMContentLoaded', async () => {
         const desc = (document.getElementById("edit-desc") as HTMLInputElement).value;
         const command = (document.getElementById("edit-command") as HTMLInputElement).value;
         const comment = (document.getElementById("edit-comment") as HTMLInputElement).value;
+        const tags = parseTags((document.getElementById("edit-tags") as HTMLInputElement).value);
         const hit_str = (document.getElementById("edit-hit") as HTMLInputElement).value;
         const hit = parseInt(hit_str, 10);
 
@@ -393,6 +446,7 @@ document.addEventListener('DOMContentLoaded', async () => {
             }
             let new_id: string = response.results[0];
             newRow.id = new_id;
+            newRow.dataset.tags = tags.join(", ");
 
 
             {
@@ -403,7 +457,8 @@ document.addEventListener('DOMContentLoaded', async () => {
                     shortcut: command,
                     application: app,
                     description: desc,
-                    comment: comment
+                    comment: comment,
+                    tags: tags
                 }
                 const response = await invoke<BlueBirdResponse>('send_command', {
                     cmd: { action: 'create_shortcuts', args: [JSON.stringify(sc)] },
@@ -446,7 +501,8 @@ document.addEventListener('DOMContentLoaded', async () => {
                     shortcut: command,
                     application: app,
                     description: desc,
-                    comment: comment
+                    comment: comment,
+                    tags: tags
                 }
                 const response = await invoke<BlueBirdResponse>('send_command', {
                     cmd: { action: 'update_shortcuts', args: [JSON.stringify(sc)] },
@@ -464,6 +520,7 @@ document.addEventListener('DOMContentLoaded', async () => {
             cells[1].textContent = desc;
             cells[2].textContent = command;
             cells[1].setAttribute('title', comment);
+            lastClickedRow.dataset.tags = tags.join(", ");
             cells[3].textContent = hit_str;
             editModal.classList.add("hidden");
             commandsSection.classList.remove("hidden");
@@ -2,31 +2,66 @@ import { invoke } from '@tauri-apps/api/core';
 import { listen } from '@tauri-apps/api/event';
 import { getCurrentWindow } from "@tauri-apps/api/window";
 
-enum StateCode {
-  OK = "OK",
-  FAIL = "FAIL",
-  BUG = "BUG",
+// The error of a failed command, e.g. { kind: "invalid_query", position: 4, message: "..." }
+type LizError = {
+  kind: string;
+  message?: string;
+  [key: string]: unknown;
 }
 
-
-// Class for BlueBirdResponse
-type BlueBirdResponse = {
-  code: StateCode;
-  results: string[];
+// Describe the error for the user, the kinds without a message are named by their kind
+function describeError(error: unknown): string {
+  const e = error as LizError;
+  return e.message ?? e.kind ?? String(error);
 }
 
-
 type Shortcut = {
   id: string;
   sc: string;
+  matches?: { field: string; ranges: [number, number][] }[]; // Matched chars of each field
+}
+
+// Where the returned shortcuts are among all the matched ones
+type Page = {
+  total: number;
+  offset: number;
+  count: number;
+  next_cursor: string | null; // Asks the next page, null on the last page
+}
+
+type HitsReply = {
+  type: "hits";
+  data: { page: Page; hits: Shortcut[] };
 }
 
+// A value asked before executing, written {{name}} or {{name:default}} in the shortcut
+type Placeholder = {
+  name: string;
+  default?: string;
+  hint?: string;
+}
+
+type NeedsInputReply = {
+  type: "needs_input";
+  data: { id: string; placeholders: Placeholder[] };
+}
+
+const PAGE_SIZE = 50; // Shortcuts fetched at once, more are fetched when scrolling down
+
 document.addEventListener('DOMContentLoaded', async () => {
 
   // let shortcuts: Shortcut[] = [];
   let shortcut_task: string = '';
+  let shortcut_values: Record<string, string> | null = null; // The values of the placeholders of `shortcut_task`
+  // The placeholders asked one by one in the search bar, null when searching
+  let prompt: { id: string; placeholders: Placeholder[]; values: Record<string, string> } | null = null;
+  let searchPlaceholder = '';
   let selectedIndex = 0;
   let total_cnt = 0;
+  let match_cnt = 0; // How many shortcuts the current query matches
+  let currentQuery = '';
+  let nextCursor: string | null = null;
+  let loadingMore = false;
 
   const appWindow = getCurrentWindow();
 
@@ -35,8 +70,7 @@ document.addEventListener('DOMContentLoaded', async () => {
   const counter = document.getElementById("shortcut-counter") as HTMLSpanElement;
 
   function updateCounter() {
-    const totalShortcuts = shortcutListContainer.children.length;
-    counter.textContent = `${totalShortcuts} / ${total_cnt}`;
+    counter.textContent = `${match_cnt} / ${total_cnt}`;
   }
 
   // Set the searchbar to be active in the beginning
@@ -44,20 +78,47 @@ document.addEventListener('DOMContentLoaded', async () => {
     searchBar.focus();  // Focus on the search input when the window is active
   }
 
-  // Fetch shortcuts from Rust via Tauri command
-  async function fetchShortcuts(query: string) {
-    const response: BlueBirdResponse = await invoke<BlueBirdResponse>('send_command', {
-      cmd: { action: 'get_shortcuts', args: [query] },
-    });
-    if (response.code !== StateCode.OK) {
-      alert(`Failed to retrieve shortcuts because ${response.results.join("; ")}`)
+  // Fetch the first page of shortcuts from Rust via Tauri command, or the next one with the cursor
+  async function fetchShortcuts(query: string, cursor: string | null = null) {
+    let reply: HitsReply;
+    try {
+      reply = await invoke<HitsReply>('run_command', {
+        cmd: { action: 'get_shortcuts', query, cursor, limit: PAGE_SIZE },
+      });
+    } catch (error) {
+      const kind = (error as LizError).kind;
+      if (kind === "stale_cursor") {
+        // The shortcuts changed since the cursor was given, start over
+        return fetchShortcuts(query);
+      }
+      // An invalid query is common while typing, show it on the search bar and keep the list
+      if (kind === "invalid_query") {
+        searchBar.classList.add('invalid');
+        searchBar.title = describeError(error);
+        return;
+      }
+      alert(`Failed to retrieve shortcuts because ${describeError(error)}`);
+      return;
+    }
+    searchBar.classList.remove('invalid');
+    searchBar.title = "";
+    const { page, hits } = reply.data;
+    if (!query) total_cnt = page.total;
+    match_cnt = page.total;
+    currentQuery = query;
+    nextCursor = page.next_cursor;
+    renderList(hits, shortcutListContainer, cursor !== null);
+  }
+
+  // Fetch the next page of the current query, if there is one
+  async function fetchMore() {
+    if (!nextCursor || loadingMore) return;
+    loadingMore = true;
+    try {
+      await fetchShortcuts(currentQuery, nextCursor);
+    } finally {
+      loadingMore = false;
     }
-    const shortcuts = response.results.map((content) => {
-      // Parse the JSON string into a Shortcut
-      return JSON.parse(content) as Shortcut;
-    });
-    if (!query) total_cnt = shortcuts.length;
-    renderList(shortcuts, shortcutListContainer);
   }
 
   // refresh the shortcut list.
@@ -66,10 +127,12 @@ document.addEventListener('DOMContentLoaded', async () => {
   });
 
   // Render the list of shortcuts
-  function renderList(list: Shortcut[], listContainer: HTMLUListElement) {
-    while (listContainer.firstChild) {
-      listContainer.removeChild(listContainer.firstChild);
-    } // Clear previous list
+  function renderList(list: Shortcut[], listContainer: HTMLUListElement, append = false) {
+    if (!append) {
+      while (listContainer.firstChild) {
+        listContainer.removeChild(listContainer.firstChild);
+      } // Clear previous list
+    }
 
     const fragment = document.createDocumentFragment();
 
@@ -77,12 +140,12 @@ document.addEventListener('DOMContentLoaded', async () => {
       const li = document.createElement('li');
       li.innerHTML = item.sc;
       li.id = item.id; // Store index for event delegation
-      if (index === 0) li.classList.add('selected'); // First item selected by default
+      if (index === 0 && !append) li.classList.add('selected'); // First item selected by default
       fragment.appendChild(li);
     });
 
     listContainer.appendChild(fragment);
-    selectedIndex = 0; // Reset selection on search input
+    if (!append) selectedIndex = 0; // Reset selection on search input
 
     updateCounter();
   }
@@ -104,6 +167,14 @@ document.addEventListener('DOMContentLoaded', async () => {
 
   addClickListener(shortcutListContainer);
 
+  // Fetch the next page when the list is scrolled near its end
+  shortcutListContainer.addEventListener('scroll', () => {
+    const ul = shortcutListContainer;
+    if (ul.scrollTop + ul.clientHeight >= ul.scrollHeight - 100) {
+      fetchMore();
+    }
+  });
+
   // Handle search input events
   const searchInput = document.getElementById('search') as HTMLInputElement;
 
@@ -118,12 +189,27 @@ document.addEventListener('DOMContentLoaded', async () => {
 
   // Handle search input with debounce
   const debouncedSearch = debounce(async () => {
+    if (prompt) return; // The search bar holds a value being asked
     const query = searchInput.value;
     await fetchShortcuts(query);
   }, 300);
 
   searchInput.addEventListener('input', debouncedSearch);
 
+  // Fill in the query of `liz --search`, which waits in Rust until the page takes it,
+  // false if there is none
+  async function takeSearch(): Promise<boolean> {
+    const query = await invoke<string | null>('take_search');
+    if (query === null) return false;
+    if (prompt) endPrompt();
+    searchInput.value = query;
+    searchInput.focus();
+    await fetchShortcuts(query);
+    return true;
+  }
+
+  listen('search', takeSearch);
+
   // Helper function to scroll selected item into view
   function scrollSelectedItemIntoView(items: HTMLCollectionOf<HTMLLIElement>, index: number) {
     items[index].scrollIntoView({
@@ -145,8 +231,61 @@ document.addEventListener('DOMContentLoaded', async () => {
     scrollSelectedItemIntoView(items, newIndex);
   }
 
+  // Ask the next placeholder in the search bar, prefilled with its default
+  function askNextPlaceholder() {
+    if (!prompt) return;
+    const placeholder = prompt.placeholders[Object.keys(prompt.values).length];
+    searchInput.value = placeholder.default ?? '';
+    searchInput.placeholder = placeholder.hint || placeholder.name;
+    searchInput.select();
+  }
+
+  // Ask the values of the placeholders, then execute the shortcut with them
+  async function startPrompt(data: NeedsInputReply["data"]) {
+    searchPlaceholder = searchInput.placeholder;
+    prompt = { id: data.id, placeholders: data.placeholders, values: {} };
+    askNextPlaceholder();
+    await appWindow.show();
+    await appWindow.setFocus();
+  }
+
+  // Leave the prompt and give the search bar back its query
+  function endPrompt() {
+    prompt = null;
+    searchInput.value = currentQuery;
+    searchInput.placeholder = searchPlaceholder;
+  }
+
+  function handlePromptKey(e: KeyboardEvent) {
+    if (!prompt) return;
+    switch (e.key) {
+      case 'Enter': {
+        const placeholder = prompt.placeholders[Object.keys(prompt.values).length];
+        prompt.values[placeholder.name] = searchInput.value;
+        if (Object.keys(prompt.values).length < prompt.placeholders.length) {
+          askNextPlaceholder();
+        } else {
+          const { id, values } = prompt;
+          endPrompt();
+          executeShortcut(id, values);
+        }
+        e.preventDefault();
+        break;
+      }
+
+      case 'Escape': // Cancel, back to the search
+        endPrompt();
+        e.preventDefault();
+        break;
+    }
+  }
+
   // Keyboard navigation (up/down, esc and enter)
   document.addEventListener('keydown', (e) => {
+    if (prompt) {
+      handlePromptKey(e);
+      return;
+    }
     const ul = shortcutListContainer;
     const items = ul.getElementsByTagName('li');
 
@@ -155,6 +294,9 @@ document.addEventListener('DOMContentLoaded', async () => {
         if (selectedIndex < items.length - 1) {
           _updateSelection(items, selectedIndex + 1);
         }
+        if (selectedIndex >= items.length - 5) {
+          fetchMore();
+        }
         e.preventDefault();
         break;
 
@@ -183,8 +325,10 @@ document.addEventListener('DOMContentLoaded', async () => {
   });
 
   // Execute the shortcut by set the shortcut_task, which will be executed when the window lost focus.
-  async function executeShortcut(shortcut: string) {
+  // Without `values`, the shortcut asks its placeholders if it has any.
+  async function executeShortcut(shortcut: string, values: Record<string, string> | null = null) {
     shortcut_task = shortcut;
+    shortcut_values = values;
     // Hide the window and start listening for focus changes
     await appWindow.hide();
     console.log("Window hidden. Waiting for focus loss...");
@@ -209,13 +353,15 @@ document.addEventListener('DOMContentLoaded', async () => {
       }
       try {
         // Send the "execute" command to Rust with the shortcut task
-        const response = await invoke<BlueBirdResponse>('send_command', {
-          cmd: { action: 'execute', args: [shortcut_task] },
+        const reply = await invoke<NeedsInputReply | { type: "done" }>('run_command', {
+          cmd: { action: 'execute', id: shortcut_task, ...(shortcut_values && { values: shortcut_values }) },
+        }).catch((error) => {
+          alert(`Failed to execute shortcut because ${describeError(error)}`);
+          console.log(`Failed to execute shortcut because ${describeError(error)}`);
         });
-
-        if (response.code !== StateCode.OK) {
-          alert(`Failed to execute shortcut because ${response.results.join("; ")}`);
-          console.log(`Failed to execute shortcut because ${response.results.join("; ")}`);
+        if (reply?.type === 'needs_input') {
+          await startPrompt(reply.data); // Executed again once the values are asked
+          return;
         }
 
         await appWindow.close();
@@ -228,10 +374,13 @@ document.addEventListener('DOMContentLoaded', async () => {
       } finally {
         // Reset the shortcut task after execution
         shortcut_task = '';
+        shortcut_values = null;
       }
     }
   });
 
-  // Initialize by fetching shortcuts
-  fetchShortcuts("");
+  // Initialize by fetching shortcuts, or the query given before the page was loaded
+  if (!(await takeSearch())) {
+    fetchShortcuts("");
+  }
 });